@@ -0,0 +1,40 @@
+use std::collections::VecDeque;
+
+/// Fixed-capacity FIFO of mono samples between a core and the frontend.
+///
+/// When the frontend falls behind, the oldest samples are dropped, so the
+/// buffer never holds more than `capacity` samples of latency.
+pub struct AudioBuffer {
+    samples: VecDeque<f32>,
+    capacity: usize,
+}
+
+impl AudioBuffer {
+    pub fn new(capacity: usize) -> Self {
+        AudioBuffer { samples: VecDeque::with_capacity(capacity), capacity: capacity.max(1) }
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Moves every buffered sample, oldest first, onto the end of `out`.
+    pub fn drain_into(&mut self, out: &mut Vec<f32>) {
+        out.extend(self.samples.drain(..));
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
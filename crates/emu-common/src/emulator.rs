@@ -0,0 +1,38 @@
+use crate::{FrameBufferInfo, Input};
+
+/// Error from any core. Each system reports its own error types through it.
+pub type CoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// What a frontend needs from an emulated system.
+pub trait EmulatorCore {
+    /// Short lowercase name of the system, e.g. `chip8`.
+    fn system(&self) -> &'static str;
+
+    /// Replaces the running program and starts it from power-on.
+    fn load_rom(&mut self, rom: &[u8]) -> Result<(), CoreError>;
+
+    /// Restarts the loaded ROM from power-on.
+    fn reset(&mut self);
+
+    /// Runs until the next frame is ready.
+    fn run_frame(&mut self) -> Result<(), CoreError>;
+
+    fn frame_buffer_info(&self) -> FrameBufferInfo;
+
+    /// A copy of the current frame, laid out as `frame_buffer_info` says.
+    fn frame_buffer(&self) -> Vec<u8>;
+
+    fn audio_sample_rate(&self) -> u32;
+
+    /// Moves the mono samples produced since the last call, in -1.0..=1.0,
+    /// onto the end of `out`.
+    fn drain_audio(&mut self, out: &mut Vec<f32>);
+
+    fn set_input(&mut self, input: Input, pressed: bool);
+
+    fn save_state(&self) -> Vec<u8>;
+
+    /// Restores a blob from `save_state`, leaving the core as it was if the
+    /// blob is rejected.
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CoreError>;
+}
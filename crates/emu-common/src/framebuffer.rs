@@ -0,0 +1,38 @@
+/// How the bytes returned by `EmulatorCore::frame_buffer` encode a pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// One byte per pixel, from 0 (black) to 255 (white).
+    Gray8,
+    /// Four bytes per pixel, red, green, blue then alpha, ready to wrap in
+    /// an `ImageData`.
+    Rgba8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Gray8 => 1,
+            PixelFormat::Rgba8888 => 4,
+        }
+    }
+}
+
+/// Shape of a core's frame buffer. Rows are stored top to bottom with no
+/// padding between them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameBufferInfo {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+}
+
+impl FrameBufferInfo {
+    /// Size in bytes of a whole frame.
+    pub fn len(&self) -> usize {
+        self.width as usize * self.height as usize * self.format.bytes_per_pixel()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,15 @@
+/// A button or key on any system's controller. Cores ignore inputs their
+/// hardware doesn't have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Select,
+    Start,
+    /// A key 0x0-0xF on the CHIP-8 hex keypad.
+    Key(u8),
+}
@@ -0,0 +1,18 @@
+//! Pieces shared by every system in the collection: the `EmulatorCore`
+//! trait that lets one frontend or test harness drive any of them, and the
+//! logging, save-state, rewind, tracing, palette and audio plumbing behind
+//! it.
+
+pub mod audio;
+mod emulator;
+mod framebuffer;
+mod input;
+pub mod logging;
+pub mod palette;
+pub mod rewind;
+pub mod state;
+pub mod trace;
+
+pub use emulator::{CoreError, EmulatorCore};
+pub use framebuffer::{FrameBufferInfo, PixelFormat};
+pub use input::Input;
@@ -0,0 +1,57 @@
+/// One RGBA8888 pixel, in the byte order `ImageData` expects.
+pub type Rgba = [u8; 4];
+
+/// Expands `0xRRGGBB` to an opaque pixel.
+pub const fn rgb(color: u32) -> Rgba {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8, 0xFF]
+}
+
+/// Four colours, lightest shade first, used to turn a core's shade indices
+/// into RGBA.
+///
+/// The Game Boy uses all four shades. CHIP-8 only has lit and unlit pixels,
+/// drawn with the first and last entries respectively.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Rgba; 4],
+}
+
+impl Palette {
+    /// The green-tinted screen of the original Game Boy.
+    pub const DMG_GREEN: Palette = Palette::custom([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]);
+
+    /// The greyer, higher-contrast screen of the Game Boy Pocket.
+    pub const POCKET_GREY: Palette = Palette::custom([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]);
+
+    /// The same levels the `Gray8` frame buffers use.
+    pub const GREY: Palette = Palette::custom([0xFFFFFF, 0xC0C0C0, 0x808080, 0x000000]);
+
+    /// White pixels on black, as CHIP-8 interpreters traditionally draw.
+    pub const CHIP8: Palette = Palette::monochrome(0x000000, 0xFFFFFF);
+
+    /// Builds a palette from four `0xRRGGBB` colours, lightest shade first.
+    pub const fn custom(colors: [u32; 4]) -> Palette {
+        Palette { colors: [rgb(colors[0]), rgb(colors[1]), rgb(colors[2]), rgb(colors[3])] }
+    }
+
+    /// Builds a two-colour palette for CHIP-8 style displays.
+    pub const fn monochrome(background: u32, foreground: u32) -> Palette {
+        Palette::custom([background, background, foreground, foreground])
+    }
+
+    /// Looks up a preset by name: `dmg`, `pocket`, `grey` or `chip8`.
+    pub fn named(name: &str) -> Option<Palette> {
+        match name {
+            "dmg" => Some(Palette::DMG_GREEN),
+            "pocket" => Some(Palette::POCKET_GREY),
+            "grey" => Some(Palette::GREY),
+            "chip8" => Some(Palette::CHIP8),
+            _ => None,
+        }
+    }
+
+    /// The colour for `shade`, 0 (lightest) to 3 (darkest).
+    pub fn color(&self, shade: u8) -> Rgba {
+        self.colors[shade as usize & 3]
+    }
+}
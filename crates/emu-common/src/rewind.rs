@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+
+/// Snapshots between two full keyframes; the rest are stored as deltas.
+const KEYFRAME_INTERVAL: usize = 30;
+
+enum Snapshot {
+    /// A complete save state, run-length encoded.
+    Key(Vec<u8>),
+    /// A save state XORed against the closest earlier keyframe, then
+    /// run-length encoded. Consecutive frames differ in few bytes, so the
+    /// XOR is mostly zeros and compresses well.
+    Delta(Vec<u8>),
+}
+
+impl Snapshot {
+    fn size(&self) -> usize {
+        match self {
+            Snapshot::Key(data) | Snapshot::Delta(data) => data.len(),
+        }
+    }
+}
+
+/// Ring buffer of compressed save states taken every `interval` frames,
+/// evicting the oldest ones to stay within `budget` bytes.
+pub struct RewindBuffer {
+    interval: u32,
+    budget: usize,
+    frames: u32,
+    used: usize,
+    since_keyframe: usize,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl RewindBuffer {
+    pub fn new(interval: u32, budget: usize) -> Self {
+        RewindBuffer {
+            interval: interval.max(1),
+            budget,
+            frames: 0,
+            used: 0,
+            since_keyframe: 0,
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: u32) {
+        self.interval = interval.max(1);
+    }
+
+    /// Changes the memory budget, dropping old snapshots if needed. A budget
+    /// of zero disables rewinding.
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    /// Number of snapshots held.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Compressed bytes held, which `evict` keeps within the budget.
+    pub fn used_bytes(&self) -> usize {
+        self.used
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.used = 0;
+        self.since_keyframe = 0;
+        self.snapshots.clear();
+    }
+
+    /// Counts a finished frame; returns true when a snapshot should be pushed.
+    pub fn tick(&mut self) -> bool {
+        if self.budget == 0 {
+            return false;
+        }
+        self.frames += 1;
+        if self.frames < self.interval {
+            return false;
+        }
+        self.frames = 0;
+        true
+    }
+
+    pub fn push(&mut self, state: &[u8]) {
+        let snapshot = match self.latest_keyframe() {
+            Some(key) if self.since_keyframe < KEYFRAME_INTERVAL && key.len() == state.len() => {
+                self.since_keyframe += 1;
+                Snapshot::Delta(encode(&xor(state, &key)))
+            }
+            _ => {
+                self.since_keyframe = 0;
+                Snapshot::Key(encode(state))
+            }
+        };
+        self.used += snapshot.size();
+        self.snapshots.push_back(snapshot);
+        self.evict();
+    }
+
+    /// Removes and returns the most recent snapshot.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let key = self.latest_keyframe();
+        let snapshot = self.snapshots.pop_back()?;
+        self.used -= snapshot.size();
+        self.frames = 0;
+        match snapshot {
+            Snapshot::Key(data) => {
+                self.since_keyframe = KEYFRAME_INTERVAL;
+                Some(decode(&data))
+            }
+            Snapshot::Delta(data) => {
+                self.since_keyframe = self.since_keyframe.saturating_sub(1);
+                Some(xor(&decode(&data), &key?))
+            }
+        }
+    }
+
+    fn latest_keyframe(&self) -> Option<Vec<u8>> {
+        self.snapshots.iter().rev().find_map(|snapshot| match snapshot {
+            Snapshot::Key(data) => Some(decode(data)),
+            Snapshot::Delta(_) => None,
+        })
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.budget {
+            let Some(oldest) = self.snapshots.pop_front() else { break };
+            self.used -= oldest.size();
+            if let Snapshot::Key(key) = oldest {
+                self.rebase_front(&decode(&key));
+            }
+        }
+        if self.snapshots.is_empty() {
+            self.since_keyframe = 0;
+        }
+    }
+
+    /// After a keyframe is evicted, the deltas that followed it need a new
+    /// base: the first becomes a keyframe and the rest are re-encoded
+    /// against it.
+    fn rebase_front(&mut self, old_key: &[u8]) {
+        let mut new_key: Option<Vec<u8>> = None;
+        for snapshot in self.snapshots.iter_mut() {
+            let Snapshot::Delta(delta) = snapshot else { break };
+            let state = xor(&decode(delta), old_key);
+            let rebased = match &new_key {
+                None => Snapshot::Key(encode(&state)),
+                Some(key) => Snapshot::Delta(encode(&xor(&state, key))),
+            };
+            self.used = self.used - snapshot.size() + rebased.size();
+            *snapshot = rebased;
+            new_key.get_or_insert(state);
+        }
+    }
+}
+
+impl Default for RewindBuffer {
+    /// One snapshot every 15 frames within 4 MiB.
+    fn default() -> Self {
+        RewindBuffer::new(15, 4 * 1024 * 1024)
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(x, y)| x ^ y).collect()
+}
+
+/// Encodes as repeated `zero_run, literal_len, literal bytes`, with both
+/// lengths as LEB128 varints.
+fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = data[i..].iter().take_while(|&&byte| byte == 0).count();
+        i += zeros;
+        let literals = data[i..].iter().take_while(|&&byte| byte != 0).count();
+        put_varint(&mut out, zeros);
+        put_varint(&mut out, literals);
+        out.extend_from_slice(&data[i..i + literals]);
+        i += literals;
+    }
+    out
+}
+
+fn decode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = get_varint(data, &mut i);
+        let literals = get_varint(data, &mut i);
+        out.resize(out.len() + zeros, 0);
+        out.extend_from_slice(&data[i..i + literals]);
+        i += literals;
+    }
+    out
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn get_varint(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+
+/// Reasons a save-state blob is rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The blob does not start with the expected magic bytes.
+    BadMagic,
+    /// The blob was written by an incompatible format version.
+    UnsupportedVersion(u16),
+    /// The blob is shorter or longer than the format requires.
+    WrongLength { expected: usize, actual: usize },
+    /// The trailing checksum does not match the contents.
+    ChecksumMismatch,
+    /// A field holds a value the machine can never be in.
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::WrongLength { expected, actual } => {
+                write!(f, "save state is {} bytes, expected {}", actual, expected)
+            }
+            StateError::ChecksumMismatch => write!(f, "save state checksum mismatch"),
+            StateError::InvalidField(field) => write!(f, "save state has invalid {}", field),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Header and trailer around a save-state payload:
+/// `magic[4] | version u16 | payload | crc32 u32`, all little-endian.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new(magic: &[u8; 4], version: u16) -> Self {
+        let mut data = Vec::new();
+        data.extend_from_slice(magic);
+        data.extend_from_slice(&version.to_le_bytes());
+        StateWriter { data }
+    }
+
+    pub fn put_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn put_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn put_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn put_bools(&mut self, values: &[bool]) {
+        self.data.extend(values.iter().map(|&value| value as u8));
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let checksum = crc32(&self.data);
+        self.data.extend_from_slice(&checksum.to_le_bytes());
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    payload: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the header, length and checksum, returning a reader over the
+    /// payload. `payload_len` is the exact payload size for `version`.
+    pub fn open(data: &'a [u8], magic: &[u8; 4], version: u16, payload_len: usize) -> Result<Self, StateError> {
+        if data.len() < 6 || &data[..4] != magic {
+            return Err(StateError::BadMagic);
+        }
+        let found_version = u16::from_le_bytes([data[4], data[5]]);
+        if found_version != version {
+            return Err(StateError::UnsupportedVersion(found_version));
+        }
+        let expected = 6 + payload_len + 4;
+        if data.len() != expected {
+            return Err(StateError::WrongLength { expected, actual: data.len() });
+        }
+        let (body, trailer) = data.split_at(expected - 4);
+        if crc32(body).to_le_bytes() != trailer {
+            return Err(StateError::ChecksumMismatch);
+        }
+        Ok(StateReader { payload: &body[6..], position: 0 })
+    }
+
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        // `open` already verified the total length, so the payload layout
+        // alone decides whether this can run past the end.
+        let bytes = &self.payload[self.position..self.position + len];
+        self.position += len;
+        bytes
+    }
+
+    pub fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        let bytes = self.take(2);
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4));
+        u32::from_le_bytes(bytes)
+    }
+
+    pub fn u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8));
+        u64::from_le_bytes(bytes)
+    }
+
+    pub fn bool(&mut self, field: &'static str) -> Result<bool, StateError> {
+        match self.u8() {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidField(field)),
+        }
+    }
+
+    pub fn bytes(&mut self, out: &mut [u8]) {
+        out.copy_from_slice(self.take(out.len()));
+    }
+
+    pub fn bools(&mut self, out: &mut [bool], field: &'static str) -> Result<(), StateError> {
+        let bytes = self.take(out.len());
+        for (value, &byte) in out.iter_mut().zip(bytes) {
+            *value = match byte {
+                0 => false,
+                1 => true,
+                _ => return Err(StateError::InvalidField(field)),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// CRC-32 (IEEE 802.3), bitwise; save states are small enough not to need a table.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs::File, io::{BufWriter, Write}, path::Path};
+
+enum Sink {
+    /// Keeps only the most recent `capacity` lines.
+    Ring { lines: VecDeque<String>, capacity: usize },
+    #[cfg(not(target_arch = "wasm32"))]
+    File(BufWriter<File>),
+}
+
+/// Collects one line per executed instruction.
+///
+/// Write errors on a file sink don't interrupt emulation; the first one is
+/// kept and reported by `flush`, and tracing stops there.
+pub struct Tracer {
+    sink: Sink,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn ring(capacity: usize) -> Self {
+        Tracer {
+            sink: Sink::Ring { lines: VecDeque::new(), capacity: capacity.max(1) },
+            error: None,
+        }
+    }
+
+    /// Streams every line to a newly created file at `path`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Tracer {
+            sink: Sink::File(BufWriter::new(File::create(path)?)),
+            error: None,
+        })
+    }
+
+    pub fn record(&mut self, line: String) {
+        match &mut self.sink {
+            Sink::Ring { lines, capacity } => {
+                if lines.len() == *capacity {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Sink::File(file) => {
+                if self.error.is_none() {
+                    if let Err(error) = writeln!(file, "{}", line) {
+                        self.error = Some(error);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes and returns the buffered lines, oldest first. File sinks
+    /// buffer nothing and return an empty list.
+    pub fn drain(&mut self) -> Vec<String> {
+        match &mut self.sink {
+            Sink::Ring { lines, .. } => lines.drain(..).collect(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Sink::File(_) => Vec::new(),
+        }
+    }
+
+    /// Flushes a file sink, reporting the first write error if there was one.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        match &mut self.sink {
+            Sink::Ring { .. } => Ok(()),
+            #[cfg(not(target_arch = "wasm32"))]
+            Sink::File(file) => file.flush(),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Every snapshot a `RewindBuffer` hands back must be byte-identical to the
+//! state pushed, through delta encoding, keyframes and eviction.
+
+use emu_common::rewind::RewindBuffer;
+
+const STATE_LEN: usize = 4096;
+
+/// A state of mostly non-zero bytes, so keyframes compress poorly, in
+/// which frame `n` differs from frame 0 in a couple of bytes. A long zero
+/// run in the middle exercises multi-byte run lengths.
+fn state(n: usize) -> Vec<u8> {
+    let mut state: Vec<u8> = (0..STATE_LEN).map(|i| (i * 7 % 251) as u8 | 1).collect();
+    state[1000..1500].fill(0);
+    state[n % STATE_LEN] ^= 0x5A;
+    state[(n * 13 + 7) % STATE_LEN] = n as u8;
+    state
+}
+
+#[test]
+fn round_trips_in_reverse_order() {
+    let mut buffer = RewindBuffer::new(1, usize::MAX);
+    for n in 0..100 {
+        buffer.push(&state(n));
+    }
+    assert_eq!(buffer.len(), 100);
+    for n in (0..100).rev() {
+        assert_eq!(buffer.pop(), Some(state(n)), "snapshot {}", n);
+    }
+    assert_eq!(buffer.pop(), None);
+    assert_eq!(buffer.used_bytes(), 0);
+}
+
+#[test]
+fn round_trips_edge_cases() {
+    let mut buffer = RewindBuffer::new(1, usize::MAX);
+    let states = [vec![], vec![0; 300], vec![0xFF; 300], vec![0, 1, 0, 0, 2]];
+    for state in &states {
+        buffer.push(state);
+    }
+    for state in states.iter().rev() {
+        assert_eq!(buffer.pop().as_ref(), Some(state));
+    }
+}
+
+#[test]
+fn stores_a_keyframe_every_31_snapshots() {
+    let mut buffer = RewindBuffer::new(1, usize::MAX);
+    let mut sizes = Vec::new();
+    for n in 0..62 {
+        let before = buffer.used_bytes();
+        buffer.push(&state(n));
+        sizes.push(buffer.used_bytes() - before);
+    }
+    // Keyframes hold the whole state; deltas only the changed bytes
+    let keyframes: Vec<usize> = (0..sizes.len()).filter(|&n| sizes[n] > STATE_LEN / 2).collect();
+    assert_eq!(keyframes, [0, 31]);
+    assert!(sizes.iter().all(|size| !(64..=STATE_LEN / 2).contains(size)));
+}
+
+#[test]
+fn evicts_oldest_snapshots_within_budget() {
+    let budget = 3 * STATE_LEN;
+    let mut buffer = RewindBuffer::new(1, budget);
+    for n in 0..200 {
+        buffer.push(&state(n));
+        assert!(buffer.used_bytes() <= budget);
+    }
+
+    // Eviction took out the keyframes the surviving deltas were encoded
+    // against, so those had to be rebased onto a new keyframe
+    let kept = buffer.len();
+    assert!(kept > 0 && kept < 200);
+    for n in (200 - kept..200).rev() {
+        assert_eq!(buffer.pop(), Some(state(n)), "snapshot {}", n);
+    }
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn shrinking_the_budget_rebases_deltas() {
+    let mut buffer = RewindBuffer::new(1, usize::MAX);
+    for n in 0..10 {
+        buffer.push(&state(n));
+    }
+    // Room for one keyframe only, so the first keyframe goes and frame 1
+    // becomes the new one
+    buffer.set_budget(buffer.used_bytes() - 1);
+    assert_eq!(buffer.len(), 9);
+    for n in (1..10).rev() {
+        assert_eq!(buffer.pop(), Some(state(n)), "snapshot {}", n);
+    }
+
+    buffer.set_budget(0);
+    assert!(!buffer.tick());
+}
+
+#[test]
+fn ticks_every_interval() {
+    let mut buffer = RewindBuffer::new(3, usize::MAX);
+    let ticks: Vec<bool> = (0..6).map(|_| buffer.tick()).collect();
+    assert_eq!(ticks, [false, false, true, false, false, true]);
+}
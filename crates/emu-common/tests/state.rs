@@ -0,0 +1,85 @@
+//! `StateReader::open` must reject anything but an intact blob of the
+//! right format, version and length.
+
+use emu_common::state::{crc32, StateError, StateReader, StateWriter};
+
+const MAGIC: &[u8; 4] = b"TEST";
+const VERSION: u16 = 3;
+const PAYLOAD_LEN: usize = 1 + 2 + 4 + 1;
+
+fn blob() -> Vec<u8> {
+    let mut writer = StateWriter::new(MAGIC, VERSION);
+    writer.put_u8(0x12);
+    writer.put_u16(0x3456);
+    writer.put_u32(0x789A_BCDE);
+    writer.put_bool(true);
+    writer.finish()
+}
+
+fn open(data: &[u8]) -> Result<StateReader<'_>, StateError> {
+    StateReader::open(data, MAGIC, VERSION, PAYLOAD_LEN)
+}
+
+/// Rewrites the trailing CRC so only the intended corruption is detected.
+fn reseal(mut data: Vec<u8>) -> Vec<u8> {
+    let body = data.len() - 4;
+    let checksum = crc32(&data[..body]);
+    data[body..].copy_from_slice(&checksum.to_le_bytes());
+    data
+}
+
+#[test]
+fn round_trips_fields() {
+    let data = blob();
+    let mut reader = open(&data).unwrap();
+    assert_eq!(reader.u8(), 0x12);
+    assert_eq!(reader.u16(), 0x3456);
+    assert_eq!(reader.u32(), 0x789A_BCDE);
+    assert_eq!(reader.bool("flag"), Ok(true));
+}
+
+#[test]
+fn rejects_bad_magic() {
+    let mut data = blob();
+    data[0] = b'X';
+    assert!(matches!(open(&data), Err(StateError::BadMagic)));
+    assert!(matches!(open(b"TES"), Err(StateError::BadMagic)));
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut data = blob();
+    data[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    assert!(matches!(open(&reseal(data)), Err(StateError::UnsupportedVersion(4))));
+}
+
+#[test]
+fn rejects_checksum_mismatch() {
+    let mut data = blob();
+    data[7] ^= 0x01;
+    assert!(matches!(open(&data), Err(StateError::ChecksumMismatch)));
+}
+
+#[test]
+fn rejects_truncated_and_trailing_bytes() {
+    let expected = 6 + PAYLOAD_LEN + 4;
+    let data = blob();
+    let truncated = data[..data.len() - 1].to_vec();
+    assert!(matches!(open(&truncated), Err(StateError::WrongLength { expected: e, actual }) if e == expected && actual == expected - 1));
+
+    let mut trailing = data.clone();
+    trailing.push(0);
+    assert!(matches!(open(&trailing), Err(StateError::WrongLength { actual, .. }) if actual == expected + 1));
+}
+
+#[test]
+fn rejects_invalid_booleans() {
+    let mut data = blob();
+    data[6 + PAYLOAD_LEN - 1] = 2;
+    let data = reseal(data);
+    let mut reader = open(&data).unwrap();
+    reader.u8();
+    reader.u16();
+    reader.u32();
+    assert_eq!(reader.bool("flag"), Err(StateError::InvalidField("flag")));
+}
@@ -1,5 +1,16 @@
-use getrandom::getrandom;
-use crate::console_log;
+#![allow(non_snake_case)]
+
+use emu_common::{log_debug, log_info, log_trace};
+use emu_common::audio::AudioBuffer;
+use emu_common::{CoreError, EmulatorCore, FrameBufferInfo, Input, PixelFormat};
+use crate::display::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::quirks::Quirks;
+use emu_common::rewind::RewindBuffer;
+use crate::rng::Rng;
+use emu_common::state::{StateError, StateReader, StateWriter};
+use crate::trace::{self, Tracer};
+use std::collections::BTreeSet;
+use std::fmt;
 const FONTSET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -20,9 +31,86 @@ const FONTSET: [u8; 80] = [
 ];
 
 const FONTSET_START_ADDRESS: usize = 0x50;
+const MEMORY_SIZE: usize = 4096;
+const PROGRAM_START_ADDRESS: usize = 0x200;
+/// Instructions per 60 Hz frame in `run_frame`, about 600 a second.
+const INSTRUCTIONS_PER_FRAME: u32 = 10;
+
+const STATE_MAGIC: &[u8; 4] = b"CH8S";
+const STATE_VERSION: u16 = 1;
+const STATE_PAYLOAD_LEN: usize = MEMORY_SIZE // memory
+    + 16                                     // V0-VF
+    + 16 * 2                                 // stack
+    + 1                                      // sp
+    + 2                                      // index
+    + 2                                      // pc
+    + 2                                      // delay and sound timers
+    + DISPLAY_WIDTH * DISPLAY_HEIGHT         // display
+    + 16                                     // keypad
+    + 8;                                     // rng
+
+/// Reasons the interpreter can stop executing a ROM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// `00EE` executed with an empty call stack.
+    StackUnderflow,
+    /// `2nnn` executed with all 16 stack slots in use.
+    StackOverflow,
+    /// The ROM does not fit between 0x200 and the end of memory.
+    RomTooLarge { size: usize, max: usize },
+    /// An instruction tried to read or write past the end of memory.
+    MemoryOutOfBounds { address: usize },
+    /// The program counter left addressable memory.
+    PcOutOfBounds { pc: u16 },
+    /// `Ex9E`/`ExA1` referenced a key outside 0x0-0xF.
+    InvalidKey(u8),
+    /// The opcode does not decode to any CHIP-8 instruction.
+    UnknownOpcode(u16),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::StackUnderflow => write!(f, "stack underflow"),
+            Chip8Error::StackOverflow => write!(f, "stack overflow"),
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, maximum is {} bytes", size, max)
+            }
+            Chip8Error::MemoryOutOfBounds { address } => {
+                write!(f, "memory access out of bounds at {:#05X}", address)
+            }
+            Chip8Error::PcOutOfBounds { pc } => write!(f, "program counter out of bounds at {:#06X}", pc),
+            Chip8Error::InvalidKey(key) => write!(f, "invalid key {:#04X}", key),
+            Chip8Error::UnknownOpcode(opcode) => write!(f, "unknown opcode {:04X}", opcode),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+/// The instruction that faulted, kept on the machine until the next `load_rom`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fault {
+    pub opcode: u16,
+    pub pc: u16,
+    pub error: Chip8Error,
+}
+
+/// Register snapshot for the debugger UI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterDump {
+    pub v: [u8; 16],
+    pub index: u16,
+    pub pc: u16,
+    pub sp: usize,
+    /// Return addresses; only the first `sp` entries are live.
+    pub stack: [u16; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
 
 pub struct Chip8 {
-    memory: [u8; 4096],
+    memory: [u8; MEMORY_SIZE],
     register: [u8; 16],
     stack: [u16; 16],
     sp: usize,
@@ -30,16 +118,42 @@ pub struct Chip8 {
     delay_timer: u8,
     sound_timer: u8,
     pc: u16,
-    pub display: [[bool; 64]; 32],
+    pub display: Display,
     pub keypad: [bool; 16],
-    pub flattened_display: [bool; 64 * 32],
+    fault: Option<Fault>,
+    rng: Rng,
+    pub rewind: RewindBuffer,
+    pub breakpoints: BTreeSet<u16>,
+    /// The breakpoint `run` last stopped at; the next `run` starting there
+    /// executes it instead of stopping again.
+    paused_at: Option<u16>,
+    pub tracer: Option<Tracer>,
+    /// Platform behaviours to emulate; kept across `load_rom`.
+    pub quirks: Quirks,
+    /// Instructions `run_frame` executes per timer tick; kept across
+    /// `load_rom`.
+    pub instructions_per_frame: u32,
+    /// The loaded ROM, for `reset`.
+    rom: Vec<u8>,
+    /// Buzzer samples not yet drained, and the square wave's position.
+    audio: AudioBuffer,
+    audio_phase: u32,
 }
 
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Chip8 {
     pub fn new() -> Self {
+        Chip8::with_rng(Rng::from_entropy())
+    }
+
+    fn with_rng(rng: Rng) -> Self {
         let mut chip8 = Chip8 {
-            memory: [0; 4096],
+            memory: [0; MEMORY_SIZE],
             register: [0; 16],
             pc: 0x200,
             stack: [0; 16],
@@ -47,9 +161,19 @@ impl Chip8 {
             index: 0,
             delay_timer: 0,
             sound_timer: 0,
-            display: [[false; 64]; 32],
+            display: Display::new(),
             keypad: [false; 16],
-            flattened_display: [false; 64 * 32]
+            fault: None,
+            rng,
+            rewind: RewindBuffer::default(),
+            breakpoints: BTreeSet::new(),
+            paused_at: None,
+            tracer: None,
+            quirks: Quirks::default(),
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            rom: Vec::new(),
+            audio: AudioBuffer::new(AUDIO_SAMPLE_RATE as usize),
+            audio_phase: 0,
         };
 
         chip8.initilize_memory();
@@ -57,36 +181,252 @@ impl Chip8 {
 
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        let start_address = 0x200;
-        console_log!("Loading ROM of size {} bytes", rom.len());
-
-        self.memory[start_address..start_address + rom.len()].copy_from_slice(rom);
-        self.pc = start_address as u16;
-        console_log!("Program counter set to: {:04X}", self.pc);
-
-    }
-
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let max = MEMORY_SIZE - PROGRAM_START_ADDRESS;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
+        }
+        log_info!("Loading ROM of size {} bytes", rom.len());
+
+        let mut rewind = std::mem::take(&mut self.rewind);
+        rewind.clear();
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        let tracer = self.tracer.take();
+        let quirks = self.quirks;
+        let instructions_per_frame = self.instructions_per_frame;
+        let palette = self.display.palette();
+        *self = Chip8::with_rng(self.rng);
+        self.rewind = rewind;
+        self.breakpoints = breakpoints;
+        self.tracer = tracer;
+        self.quirks = quirks;
+        self.instructions_per_frame = instructions_per_frame;
+        self.display.set_palette(palette);
+        self.rom = rom.to_vec();
+        self.memory[PROGRAM_START_ADDRESS..PROGRAM_START_ADDRESS + rom.len()].copy_from_slice(rom);
+        self.pc = PROGRAM_START_ADDRESS as u16;
+        log_debug!("Program counter set to: {:04X}", self.pc);
+
+        Ok(())
+    }
+
+    /// Reseeds the random number generator used by `Cxkk`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::from_seed(seed);
+    }
+
+    /// Serializes the full machine into a versioned, checksummed blob.
+    ///
+    /// A pending fault is not part of the state; restoring always resumes a
+    /// running machine.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new(STATE_MAGIC, STATE_VERSION);
+        writer.put_bytes(&self.memory);
+        writer.put_bytes(&self.register);
+        for &address in &self.stack {
+            writer.put_u16(address);
+        }
+        writer.put_u8(self.sp as u8);
+        writer.put_u16(self.index);
+        writer.put_u16(self.pc);
+        writer.put_u8(self.delay_timer);
+        writer.put_u8(self.sound_timer);
+        writer.put_bools(self.display.pixels());
+        writer.put_bools(&self.keypad);
+        writer.put_u64(self.rng.state());
+        writer.finish()
+    }
+
+    /// Restores a blob produced by `save_state`. The machine is left
+    /// untouched if the blob is rejected.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::open(data, STATE_MAGIC, STATE_VERSION, STATE_PAYLOAD_LEN)?;
+
+        let mut memory = [0u8; MEMORY_SIZE];
+        reader.bytes(&mut memory);
+        let mut register = [0u8; 16];
+        reader.bytes(&mut register);
+        let mut stack = [0u16; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16();
+        }
+        let sp = reader.u8() as usize;
+        if sp > stack.len() {
+            return Err(StateError::InvalidField("stack pointer"));
+        }
+        let index = reader.u16();
+        let pc = reader.u16();
+        if pc as usize >= MEMORY_SIZE {
+            return Err(StateError::InvalidField("program counter"));
+        }
+        let delay_timer = reader.u8();
+        let sound_timer = reader.u8();
+        let mut pixels = [false; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        reader.bools(&mut pixels, "display")?;
+        let mut keypad = [false; 16];
+        reader.bools(&mut keypad, "keypad")?;
+        let rng = Rng::from_state(reader.u64()).ok_or(StateError::InvalidField("rng state"))?;
+
+        self.memory = memory;
+        self.register = register;
+        self.stack = stack;
+        self.sp = sp;
+        self.index = index;
+        self.pc = pc;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.display.restore(&pixels);
+        self.keypad = keypad;
+        self.rng = rng;
+        self.fault = None;
+        // A breakpoint at the restored PC should stop it again
+        self.paused_at = None;
+        Ok(())
+    }
+
+    /// The fault that halted the machine, if any.
+    pub fn fault(&self) -> Option<&Fault> {
+        self.fault.as_ref()
+    }
+
+    /// Executes one instruction and ticks the timers, so every instruction
+    /// counts as a frame. `run_frame` runs several instructions per tick
+    /// instead.
+    ///
+    /// Once an instruction faults the machine stays halted and every further
+    /// call returns the same error until a new ROM is loaded.
+    pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+        self.step_instruction()?;
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// Executes one instruction, recording a fault if it fails.
+    fn step_instruction(&mut self) -> Result<(), Chip8Error> {
+        if let Some(fault) = self.fault {
+            return Err(fault.error);
+        }
 
-    pub fn cycle(&mut self) {
-        let opcode = self.opcode_fetch();
-        console_log!("Fetched opcode: {:04X} at PC: {:04X}", opcode, self.pc);
+        if let Some(mut tracer) = self.tracer.take() {
+            let pc = self.pc as usize;
+            let pcmem = [0, 1].map(|offset| self.memory.get(pc + offset).copied().unwrap_or(0));
+            tracer.record(trace::trace_line(&self.register_dump(), pcmem));
+            self.tracer = Some(tracer);
+        }
 
+        let pc = self.pc;
+        let opcode = match self.opcode_fetch() {
+            Ok(opcode) => opcode,
+            Err(error) => {
+                self.fault = Some(Fault { opcode: 0, pc, error });
+                return Err(error);
+            }
+        };
 
         self.pc += 2;
 
-        self.execute_opcode(opcode as u16);
-    
+        if let Err(error) = self.execute_opcode(opcode) {
+            self.fault = Some(Fault { opcode, pc, error });
+            return Err(error);
+        }
+        Ok(())
+    }
 
+    /// Counts the delay and sound timers down by one, as happens 60 times
+    /// a second.
+    fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
         if self.sound_timer > 0 {
             if self.sound_timer == 1 {
-                println!("BEEP!");
+                log_debug!("BEEP!");
             }
             self.sound_timer -= 1;
         }
+
+        // A timer tick is also one frame as far as rewind is concerned
+        if self.rewind.tick() {
+            let state = self.save_state();
+            self.rewind.push(&state);
+        }
+    }
+
+    /// Runs up to `cycles` instructions, stopping before any instruction at
+    /// a breakpoint. Returns true if a breakpoint stopped it; calling `run`
+    /// again resumes past that breakpoint.
+    pub fn run(&mut self, cycles: u32) -> Result<bool, Chip8Error> {
+        for _ in 0..cycles {
+            if self.at_breakpoint() {
+                return Ok(true);
+            }
+            self.cycle()?;
+        }
+        Ok(false)
+    }
+
+    /// Runs a frame like `EmulatorCore::run_frame`, but stops before any
+    /// instruction at a breakpoint. The timers only tick, and the frame's
+    /// audio is only added, once all `instructions_per_frame` have run.
+    /// Returns true if a breakpoint stopped it.
+    pub fn debug_run_to_frame(&mut self) -> Result<bool, Chip8Error> {
+        for _ in 0..self.instructions_per_frame {
+            if self.at_breakpoint() {
+                return Ok(true);
+            }
+            self.step_instruction()?;
+        }
+        self.tick_timers();
+        self.generate_audio();
+        Ok(false)
+    }
+
+    /// True if PC is on a breakpoint that hasn't just stopped execution, in
+    /// which case it pauses there.
+    fn at_breakpoint(&mut self) -> bool {
+        let resuming = self.paused_at.take() == Some(self.pc);
+        if !resuming && self.breakpoints.contains(&self.pc) {
+            self.paused_at = Some(self.pc);
+            return true;
+        }
+        false
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Writes a byte of memory, e.g. to preselect a test in a test ROM.
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<(), Chip8Error> {
+        let address = self.memory_address(address as usize)?;
+        self.memory[address] = value;
+        Ok(())
+    }
+
+    pub fn register_dump(&self) -> RegisterDump {
+        RegisterDump {
+            v: self.register,
+            index: self.index,
+            pc: self.pc,
+            sp: self.sp,
+            stack: self.stack,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    /// Restores the most recent rewind snapshot, clearing any fault.
+    /// Returns false once the history is exhausted.
+    pub fn rewind_step(&mut self) -> bool {
+        match self.rewind.pop() {
+            // Snapshots come from `save_state`, so they always load
+            Some(state) => self.load_state(&state).is_ok(),
+            None => false,
+        }
     }
 
 
@@ -95,14 +435,25 @@ impl Chip8 {
         .copy_from_slice(&FONTSET);
     }
 
-    pub fn opcode_fetch(&mut self) -> u16{
+    pub fn opcode_fetch(&mut self) -> Result<u16, Chip8Error> {
+        if self.pc as usize + 1 >= MEMORY_SIZE {
+            return Err(Chip8Error::PcOutOfBounds { pc: self.pc });
+        }
         let high_byte = self.memory[self.pc as usize] as u16;
         let low_byte = self.memory[(self.pc + 1) as usize] as u16;
 
-        (high_byte << 8) | low_byte
+        Ok((high_byte << 8) | low_byte)
+    }
+
+    fn memory_address(&self, address: usize) -> Result<usize, Chip8Error> {
+        if address < MEMORY_SIZE {
+            Ok(address)
+        } else {
+            Err(Chip8Error::MemoryOutOfBounds { address })
+        }
     }
 
-    pub fn execute_opcode(&mut self, opcode: u16) {
+    pub fn execute_opcode(&mut self, opcode: u16) -> Result<(), Chip8Error> {
         let nibbles = (
             (opcode & 0xF000) >> 12,
             (opcode & 0x0F00) >> 8,
@@ -117,9 +468,9 @@ impl Chip8 {
     
         match nibbles {
             (0x0, 0x0, 0xE, 0x0) => self.OP_00E0(),                // Clear display
-            (0x0, 0x0, 0xE, 0xE) => self.OP_00EE(),                // Return from subroutine
+            (0x0, 0x0, 0xE, 0xE) => self.OP_00EE()?,               // Return from subroutine
             (0x1, _, _, _) => self.OP_1nnn(nnn),                  // Jump to address
-            (0x2, _, _, _) => self.OP_2nnn(nnn),                  // Call subroutine
+            (0x2, _, _, _) => self.OP_2nnn(nnn)?,                 // Call subroutine
             (0x3, _, _, _) => self.OP_3xkk(x, kk),                // Skip if VX == kk
             (0x4, _, _, _) => self.OP_4xkk(x, kk),                // Skip if VX != kk
             (0x5, _, _, 0x0) => self.OP_5xy0(x, y),               // Skip if VX == VY
@@ -131,60 +482,64 @@ impl Chip8 {
             (0x8, _, _, 0x3) => self.OP_8xy3(x, y),               // Set VX = VX XOR VY
             (0x8, _, _, 0x4) => self.OP_8xy4(x, y),               // Add VY to VX, set VF = carry
             (0x8, _, _, 0x5) => self.OP_8xy5(x, y),               // Subtract VY from VX, set VF = borrow
-            (0x8, _, _, 0x6) => self.OP_8xy6(x),                  // Shift VX right by 1
+            (0x8, _, _, 0x6) => self.OP_8xy6(x, y),               // Shift VX right by 1
             (0x8, _, _, 0x7) => self.OP_8xy7(x, y),               // Set VX = VY - VX
-            (0x8, _, _, 0xE) => self.OP_8xyE(x),                  // Shift VX left by 1
+            (0x8, _, _, 0xE) => self.OP_8xyE(x, y),               // Shift VX left by 1
             (0x9, _, _, 0x0) => self.OP_9xy0(x, y),               // Skip if VX != VY
             (0xA, _, _, _) => self.OP_Annn(nnn),                  // Set I = nnn
             (0xB, _, _, _) => self.OP_Bnnn(nnn),                  // Jump to V0 + nnn
             (0xC, _, _, _) => self.OP_Cxkk(kk, x),                // Set VX = random byte AND kk
-            (0xD, _, _, _) => {self.OP_Dxyn(x, y, n);},              // Draw sprite
-            (0xE, _, 0x9, 0xE) => self.OP_Ex9E(x),                // Skip if key in VX is pressed
-            (0xE, _, 0xA, 0x1) => self.OP_ExA1(x),                // Skip if key in VX is not pressed
+            (0xD, _, _, _) => self.OP_Dxyn(x, y, n)?,                 // Draw sprite
+            (0xE, _, 0x9, 0xE) => self.OP_Ex9E(x)?,               // Skip if key in VX is pressed
+            (0xE, _, 0xA, 0x1) => self.OP_ExA1(x)?,               // Skip if key in VX is not pressed
             (0xF, _, 0x0, 0x7) => self.OP_Fx07(x),                // Set VX = delay timer
             (0xF, _, 0x0, 0xA) => self.OP_Fx0A(x),                // Wait for key press
             (0xF, _, 0x1, 0x5) => self.OP_Fx15(x),                // Set delay timer = VX
             (0xF, _, 0x1, 0x8) => self.OP_Fx18(x),                // Set sound timer = VX
             (0xF, _, 0x1, 0xE) => self.OP_Fx1E(x),                // Set I = I + VX
             (0xF, _, 0x2, 0x9) => self.OP_Fx29(x),                // Set I = location of sprite for digit VX
-            (0xF, _, 0x3, 0x3) => self.OP_Fx33(x),                // Store BCD of VX in memory at I
-            (0xF, _, 0x5, 0x5) => self.OP_Fx55(x),                // Store registers V0 to VX in memory
-            (0xF, _, 0x6, 0x5) => self.OP_Fx65(x),                // Load registers V0 to VX from memory
-            _ => println!("Unknown opcode: {:04X}", opcode),
+            (0xF, _, 0x3, 0x3) => self.OP_Fx33(x)?,               // Store BCD of VX in memory at I
+            (0xF, _, 0x5, 0x5) => self.OP_Fx55(x)?,               // Store registers V0 to VX in memory
+            (0xF, _, 0x6, 0x5) => self.OP_Fx65(x)?,               // Load registers V0 to VX from memory
+            _ => return Err(Chip8Error::UnknownOpcode(opcode)),
         }
+
+        Ok(())
     }
         
     fn OP_00E0(&mut self) {
-        self.display = [[false; 64]; 32];
+        self.display.clear();
     }
 
     fn OP_1nnn(&mut self, address: u16) {
         self.pc = address;
     }
 
-    fn OP_00EE(&mut self) {
+    fn OP_00EE(&mut self) -> Result<(), Chip8Error> {
         if self.sp == 0 {
-            panic!("Stack underflow detected!");
+            return Err(Chip8Error::StackUnderflow);
         }
         
         self.sp -= 1;
         self.pc = self.stack[self.sp];
     
-        console_log!("Returning to address: {:04X}", self.pc);
+        log_trace!("Returning to address: {:04X}", self.pc);
     
         // self.pc += 2;
+        Ok(())
     }
         
 
-    fn OP_2nnn(&mut self, address: u16) {
+    fn OP_2nnn(&mut self, address: u16) -> Result<(), Chip8Error> {
         if self.sp >= self.stack.len() {
-            panic!("Stack overflow detected!");
+            return Err(Chip8Error::StackOverflow);
         }
         self.stack[self.sp] = self.pc;
         self.sp += 1;
     
         self.pc = address;
-        console_log!("Calling subroutine at address: {:04X}", address);
+        log_trace!("Calling subroutine at address: {:04X}", address);
+        Ok(())
     }
     
 
@@ -201,7 +556,7 @@ impl Chip8 {
     }
 
     fn OP_5xy0(&mut self, vx: u8, vy: u8) {
-        if self.register[vx as usize] != self.register[vy as usize] {
+        if self.register[vx as usize] == self.register[vy as usize] {
             self.pc += 2;
         }
     }
@@ -211,7 +566,7 @@ impl Chip8 {
     }
 
     fn OP_7xkk(&mut self, vx: u8, byte: u8) {
-        self.register[vx as usize] += byte;
+        self.register[vx as usize] = self.register[vx as usize].wrapping_add(byte);
     }   
 
     fn OP_8xy0(&mut self, vx: u8, vy: u8){
@@ -220,54 +575,65 @@ impl Chip8 {
 
     fn OP_8xy1(&mut self, vx: u8, vy: u8){
         self.register[vx as usize] |= self.register[vy as usize];
+        self.reset_vf();
     }
 
     fn OP_8xy2(&mut self, vx: u8, vy: u8){
         self.register[vx as usize] &= self.register[vy as usize];
+        self.reset_vf();
     }
 
     fn OP_8xy3(&mut self, vx: u8, vy: u8){
         self.register[vx as usize] ^= self.register[vy as usize];
+        self.reset_vf();
     }
 
-
-    fn OP_8xy4(&mut self, vx: u8, vy: u8) {
-        let sum: u16 = self.register[vx as usize] as u16 + self.register[vy as usize] as u16;
-        self.register[0xF] = if sum > 0xFF { 1 } else { 0 }; 
-        self.register[vx as usize] = (sum & 0xFF) as u8; 
-    }
-    
-    fn OP_8xy5(&mut self, vx: u8, vy: u8){
-        if self.register[vx as usize] > self.register[vy as usize] {
-            self.register[0xF] = 1;
-        }
-        else {
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
             self.register[0xF] = 0;
         }
-        self.register[vx as usize] -= self.register[vy as usize]
+    }
+
+    // The arithmetic ops below write VF after the result, so that with VF
+    // as the destination the flag is what remains.
 
+    fn OP_8xy4(&mut self, vx: u8, vy: u8) {
+        let (sum, carry) = self.register[vx as usize].overflowing_add(self.register[vy as usize]);
+        self.register[vx as usize] = sum;
+        self.register[0xF] = carry as u8;
     }
 
-    fn OP_8xy6(&mut self, vx: u8) {
-        self.register[0xF] = (self.register[vx as usize] & 0x1);
-        self.register[vx as usize] >>= 1;
+    /// VF is 1 when there is no borrow, including when VX equals VY.
+    fn OP_8xy5(&mut self, vx: u8, vy: u8){
+        let (difference, borrow) = self.register[vx as usize].overflowing_sub(self.register[vy as usize]);
+        self.register[vx as usize] = difference;
+        self.register[0xF] = !borrow as u8;
     }
 
+    fn OP_8xy6(&mut self, vx: u8, vy: u8) {
+        let value = self.shift_source(vx, vy);
+        self.register[vx as usize] = value >> 1;
+        self.register[0xF] = value & 0x1;
+    }
 
     fn OP_8xy7(&mut self, vx: u8, vy: u8){
-        if self.register[vy as usize] > self.register[vx as usize] {
-            self.register[0xF] = 1;
-        }
-        else {
-            self.register[0xF] = 0;
-        }
-        self.register[vx as usize] = self.register[vy as usize] - self.register[vx as usize];
+        let (difference, borrow) = self.register[vy as usize].overflowing_sub(self.register[vx as usize]);
+        self.register[vx as usize] = difference;
+        self.register[0xF] = !borrow as u8;
+    }
 
+    fn OP_8xyE(&mut self, vx: u8, vy: u8) {
+        let value = self.shift_source(vx, vy);
+        self.register[vx as usize] = value << 1;
+        self.register[0xF] = value >> 7;
     }
 
-    fn OP_8xyE(&mut self, vx: u8) {
-        self.register[0xF] = (self.register[vx as usize] & 0x80) >> 7;
-        self.register[vx as usize] <<= 1;
+    fn shift_source(&self, vx: u8, vy: u8) -> u8 {
+        if self.quirks.shift_in_place {
+            self.register[vx as usize]
+        } else {
+            self.register[vy as usize]
+        }
     }
 
     fn OP_9xy0(&mut self, vx: u8, vy: u8){
@@ -281,57 +647,65 @@ impl Chip8 {
     }
 
     fn OP_Bnnn(&mut self, address: u16) {
-        console_log!("Jumping to address: {:04X} + V0 ({:02X})", address, self.register[0]);
-        self.pc = (self.register[0] as u16 + address) as u16;
+        let offset = if self.quirks.jump_with_vx { (address >> 8) as usize } else { 0 };
+        log_trace!("Jumping to address: {:04X} + V{:X} ({:02X})", address, offset, self.register[offset]);
+        self.pc = self.register[offset] as u16 + address;
     }
       
 
     fn OP_Cxkk(&mut self, byte: u8, vx: u8) {
-        let mut rand_byte = [0u8; 1];
-        getrandom(&mut rand_byte).expect("Failed to generate a random byte");
-        self.register[vx as usize] = rand_byte[0] & byte;
+        self.register[vx as usize] = self.rng.next_u8() & byte;
     }
 
-    fn OP_Dxyn(&mut self, vx: u8, vy: u8, height: u8) {
-        let x = self.register[vx as usize] as usize;
-        let y = self.register[vy as usize] as usize;
+    fn OP_Dxyn(&mut self, vx: u8, vy: u8, height: u8) -> Result<(), Chip8Error> {
+        // The starting position always wraps; the sprite itself wraps or is
+        // clipped at the edges depending on the quirk
+        let x = self.register[vx as usize] as usize % DISPLAY_WIDTH;
+        let y = self.register[vy as usize] as usize % DISPLAY_HEIGHT;
     
         self.register[0xF] = 0;
     
         for byte_index in 0..height {
-            let sprite_byte = self.memory[(self.index + byte_index as u16) as usize];
+            // Clipped rows are never fetched, so they can't fault
+            let row = y + byte_index as usize;
+            if self.quirks.clipping && row >= DISPLAY_HEIGHT {
+                break;
+            }
+            let address = self.memory_address(self.index as usize + byte_index as usize)?;
+            let sprite_byte = self.memory[address];
     
             for bit_index in 0..8 {
                 let pixel = (sprite_byte >> (7 - bit_index)) & 1;
-                let display_x = (x + bit_index) % 64;
-                let display_y = (y + byte_index as usize) % 32;
-                let index = display_y * 64 + display_x;
+                let column = x + bit_index;
+                if self.quirks.clipping && column >= DISPLAY_WIDTH {
+                    break;
+                }
     
                 // Check for collision
-                if pixel == 1 {
-                    if self.display[display_y][display_x] {
-                        self.register[0xF] = 1;
-                    }
-                    self.display[display_y][display_x] ^= true;
-                    self.flattened_display[index] = self.display[display_y][display_x];
-
+                if pixel == 1 && self.display.toggle(column, row) {
+                    self.register[0xF] = 1;
                 }
             }
         }
+        Ok(())
     }
         
-    fn OP_Ex9E(&mut self, vx: u8) {
+    fn OP_Ex9E(&mut self, vx: u8) -> Result<(), Chip8Error> {
         let key = self.register[vx as usize];
-        if self.keypad[key as usize] {
+        let pressed = self.keypad.get(key as usize).ok_or(Chip8Error::InvalidKey(key))?;
+        if *pressed {
             self.pc += 2;
         }
+        Ok(())
     }
 
-    fn OP_ExA1(&mut self, vx: u8) {
+    fn OP_ExA1(&mut self, vx: u8) -> Result<(), Chip8Error> {
         let key = self.register[vx as usize];
-        if !self.keypad[key as usize] {
+        let pressed = self.keypad.get(key as usize).ok_or(Chip8Error::InvalidKey(key))?;
+        if !*pressed {
             self.pc += 2;
         }
+        Ok(())
     }
 
     fn OP_Fx07(&mut self, vx: u8) {
@@ -358,7 +732,7 @@ impl Chip8 {
     }
 
     fn OP_Fx1E(&mut self, vx: u8) {
-        self.index += self.register[vx as usize] as u16;
+        self.index = self.index.wrapping_add(self.register[vx as usize] as u16);
     }
 
     fn OP_Fx29(&mut self, vx: u8) {
@@ -366,33 +740,132 @@ impl Chip8 {
         self.index = (FONTSET_START_ADDRESS + (5 * digit as usize)) as u16;
     }
     
-    fn OP_Fx33(&mut self, vx: u8) {
+    fn OP_Fx33(&mut self, vx: u8) -> Result<(), Chip8Error> {
         let mut value = self.register[vx as usize];
+        let address = self.index as usize;
+        self.memory_address(address + 2)?;
 
-        self.memory[self.index as usize + 2] = value % 10;
+        self.memory[address + 2] = value % 10;
         value /= 10;
 
-        self.memory[self.index as usize + 1] = value % 10;
+        self.memory[address + 1] = value % 10;
         value /= 10;
 
-        self.memory[self.index as usize] = value % 10;
+        self.memory[address] = value % 10;
+        Ok(())
     }
 
-    fn OP_Fx55(&mut self, vx: u8) {
+    fn OP_Fx55(&mut self, vx: u8) -> Result<(), Chip8Error> {
+        self.memory_address(self.index as usize + vx as usize)?;
         for i in 0..=vx {
             self.memory[self.index as usize + i as usize] = self.register[i as usize];
         }
-        self.index += vx as u16 + 1;
+        if self.quirks.memory_increment {
+            self.index += vx as u16 + 1;
+        }
+        Ok(())
     }
     
-    fn OP_Fx65(&mut self, vx: u8) {
+    fn OP_Fx65(&mut self, vx: u8) -> Result<(), Chip8Error> {
+        self.memory_address(self.index as usize + vx as usize)?;
         for i in 0..=vx {
             self.register[i as usize] = self.memory[self.index as usize + i as usize];
         }
-        self.index += vx as u16 + 1;
+        if self.quirks.memory_increment {
+            self.index += vx as u16 + 1;
+        }
+        Ok(())
     }
     
 
     
 
-}
\ No newline at end of file
+}
+
+const AUDIO_SAMPLE_RATE: u32 = 48_000;
+const BEEP_FREQUENCY: u32 = 440;
+/// Samples per frame; a frame is one timer tick.
+const SAMPLES_PER_FRAME: u32 = AUDIO_SAMPLE_RATE / 60;
+
+impl Chip8 {
+    /// Adds a frame of buzzer output: a square wave while the sound timer
+    /// runs, silence otherwise. Up to a second of it is kept undrained.
+    fn generate_audio(&mut self) {
+        let beeping = self.sound_timer > 0;
+        for _ in 0..SAMPLES_PER_FRAME {
+            let high = self.audio_phase < AUDIO_SAMPLE_RATE / 2;
+            self.audio.push(match (beeping, high) {
+                (false, _) => 0.0,
+                (true, true) => 0.25,
+                (true, false) => -0.25,
+            });
+            self.audio_phase = (self.audio_phase + BEEP_FREQUENCY) % AUDIO_SAMPLE_RATE;
+        }
+    }
+}
+
+impl EmulatorCore for Chip8 {
+    fn system(&self) -> &'static str {
+        "chip8"
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) -> Result<(), CoreError> {
+        Ok(Chip8::load_rom(self, rom)?)
+    }
+
+    fn reset(&mut self) {
+        let rom = std::mem::take(&mut self.rom);
+        // It loaded before, so it fits
+        let _ = Chip8::load_rom(self, &rom);
+    }
+
+    /// Runs `instructions_per_frame` instructions, then ticks the timers
+    /// once and adds a frame of audio.
+    fn run_frame(&mut self) -> Result<(), CoreError> {
+        for _ in 0..self.instructions_per_frame {
+            self.step_instruction()?;
+        }
+        self.tick_timers();
+        self.generate_audio();
+        Ok(())
+    }
+
+    fn frame_buffer_info(&self) -> FrameBufferInfo {
+        FrameBufferInfo {
+            width: DISPLAY_WIDTH as u32,
+            height: DISPLAY_HEIGHT as u32,
+            format: if self.display.palette().is_some() { PixelFormat::Rgba8888 } else { PixelFormat::Gray8 },
+        }
+    }
+
+    fn frame_buffer(&self) -> Vec<u8> {
+        if self.display.palette().is_some() {
+            return self.display.rgba().to_vec();
+        }
+        self.display.pixels().iter().map(|&lit| if lit { 0xFF } else { 0x00 }).collect()
+    }
+
+    fn audio_sample_rate(&self) -> u32 {
+        AUDIO_SAMPLE_RATE
+    }
+
+    fn drain_audio(&mut self, out: &mut Vec<f32>) {
+        self.audio.drain_into(out);
+    }
+
+    fn set_input(&mut self, input: Input, pressed: bool) {
+        if let Input::Key(key) = input {
+            if let Some(slot) = self.keypad.get_mut(key as usize) {
+                *slot = pressed;
+            }
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        Chip8::save_state(self)
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CoreError> {
+        Ok(Chip8::load_state(self, data)?)
+    }
+}
@@ -0,0 +1,133 @@
+/// Mnemonic style for disassembly listings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// Octo assembler syntax: `v3 := 0x1F`, `if v0 != v1 then`, `sprite v0 v1 5`.
+    Octo,
+    /// Classic mnemonics from Cowgod's technical reference: `LD V3, #1F`.
+    Cowgod,
+}
+
+/// One line of a disassembly listing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    pub opcode: u16,
+    pub text: String,
+}
+
+/// Disassembles up to `count` instructions starting at `address`. The
+/// listing stops early at the end of memory.
+///
+/// CHIP-8 code and data share memory and every opcode is two bytes, so the
+/// listing simply walks in two-byte steps; words that aren't instructions
+/// come out as data.
+pub fn disassemble(memory: &[u8], address: u16, count: usize, syntax: Syntax) -> Vec<DisassembledInstruction> {
+    (0..count)
+        .map(|line| address as usize + line * 2)
+        .take_while(|&address| address + 1 < memory.len())
+        .map(|address| {
+            let opcode = u16::from_be_bytes([memory[address], memory[address + 1]]);
+            DisassembledInstruction {
+                address: address as u16,
+                opcode,
+                text: mnemonic(opcode, syntax),
+            }
+        })
+        .collect()
+}
+
+/// Formats a single opcode. The nibble patterns mirror
+/// `Chip8::execute_opcode`.
+pub fn mnemonic(opcode: u16, syntax: Syntax) -> String {
+    let nibbles = (
+        (opcode & 0xF000) >> 12,
+        (opcode & 0x0F00) >> 8,
+        (opcode & 0x00F0) >> 4,
+        opcode & 0x000F,
+    );
+    let nnn = opcode & 0x0FFF;
+    let kk = opcode & 0x00FF;
+    let x = nibbles.1;
+    let y = nibbles.2;
+    let n = nibbles.3;
+
+    match syntax {
+        Syntax::Octo => match nibbles {
+            (0x0, 0x0, 0xE, 0x0) => "clear".to_string(),
+            (0x0, 0x0, 0xE, 0xE) => "return".to_string(),
+            (0x1, _, _, _) => format!("jump 0x{:03X}", nnn),
+            (0x2, _, _, _) => format!(":call 0x{:03X}", nnn),
+            // Octo's `if ... then` runs the next instruction when the
+            // condition holds, so each skip prints its opposite
+            (0x3, _, _, _) => format!("if v{:x} != 0x{:02X} then", x, kk),
+            (0x4, _, _, _) => format!("if v{:x} == 0x{:02X} then", x, kk),
+            (0x5, _, _, 0x0) => format!("if v{:x} != v{:x} then", x, y),
+            (0x6, _, _, _) => format!("v{:x} := 0x{:02X}", x, kk),
+            (0x7, _, _, _) => format!("v{:x} += 0x{:02X}", x, kk),
+            (0x8, _, _, 0x0) => format!("v{:x} := v{:x}", x, y),
+            (0x8, _, _, 0x1) => format!("v{:x} |= v{:x}", x, y),
+            (0x8, _, _, 0x2) => format!("v{:x} &= v{:x}", x, y),
+            (0x8, _, _, 0x3) => format!("v{:x} ^= v{:x}", x, y),
+            (0x8, _, _, 0x4) => format!("v{:x} += v{:x}", x, y),
+            (0x8, _, _, 0x5) => format!("v{:x} -= v{:x}", x, y),
+            (0x8, _, _, 0x6) => format!("v{:x} >>= v{:x}", x, y),
+            (0x8, _, _, 0x7) => format!("v{:x} =- v{:x}", x, y),
+            (0x8, _, _, 0xE) => format!("v{:x} <<= v{:x}", x, y),
+            (0x9, _, _, 0x0) => format!("if v{:x} == v{:x} then", x, y),
+            (0xA, _, _, _) => format!("i := 0x{:03X}", nnn),
+            (0xB, _, _, _) => format!("jump0 0x{:03X}", nnn),
+            (0xC, _, _, _) => format!("v{:x} := random 0x{:02X}", x, kk),
+            (0xD, _, _, _) => format!("sprite v{:x} v{:x} {}", x, y, n),
+            (0xE, _, 0x9, 0xE) => format!("if v{:x} -key then", x),
+            (0xE, _, 0xA, 0x1) => format!("if v{:x} key then", x),
+            (0xF, _, 0x0, 0x7) => format!("v{:x} := delay", x),
+            (0xF, _, 0x0, 0xA) => format!("v{:x} := key", x),
+            (0xF, _, 0x1, 0x5) => format!("delay := v{:x}", x),
+            (0xF, _, 0x1, 0x8) => format!("buzzer := v{:x}", x),
+            (0xF, _, 0x1, 0xE) => format!("i += v{:x}", x),
+            (0xF, _, 0x2, 0x9) => format!("i := hex v{:x}", x),
+            (0xF, _, 0x3, 0x3) => format!("bcd v{:x}", x),
+            (0xF, _, 0x5, 0x5) => format!("save v{:x}", x),
+            (0xF, _, 0x6, 0x5) => format!("load v{:x}", x),
+            _ => format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF),
+        },
+        Syntax::Cowgod => match nibbles {
+            (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
+            (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+            (0x0, _, _, _) => format!("SYS #{:03X}", nnn),
+            (0x1, _, _, _) => format!("JP #{:03X}", nnn),
+            (0x2, _, _, _) => format!("CALL #{:03X}", nnn),
+            (0x3, _, _, _) => format!("SE V{:X}, #{:02X}", x, kk),
+            (0x4, _, _, _) => format!("SNE V{:X}, #{:02X}", x, kk),
+            (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+            (0x6, _, _, _) => format!("LD V{:X}, #{:02X}", x, kk),
+            (0x7, _, _, _) => format!("ADD V{:X}, #{:02X}", x, kk),
+            (0x8, _, _, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+            (0x8, _, _, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+            (0x8, _, _, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+            (0x8, _, _, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+            (0x8, _, _, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+            (0x8, _, _, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+            (0x8, _, _, 0x6) => format!("SHR V{:X}, V{:X}", x, y),
+            (0x8, _, _, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+            (0x8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+            (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+            (0xA, _, _, _) => format!("LD I, #{:03X}", nnn),
+            (0xB, _, _, _) => format!("JP V0, #{:03X}", nnn),
+            (0xC, _, _, _) => format!("RND V{:X}, #{:02X}", x, kk),
+            (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
+            (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+            (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+            (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
+            (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+            (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+            (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+            (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+            (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+            (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+            (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+            _ => format!("DW #{:04X}", opcode),
+        },
+    }
+}
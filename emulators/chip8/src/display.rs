@@ -0,0 +1,109 @@
+use emu_common::palette::Palette;
+
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+
+/// Monochrome 64x32 framebuffer, stored row-major so JS can view it directly.
+///
+/// Every write that actually flips a pixel marks the frame dirty; the
+/// frontend polls `frame_changed` to decide whether a redraw is needed.
+///
+/// With a palette set, an RGBA8888 copy is kept in step with every write so
+/// the frontend can hand it to `putImageData` as is.
+pub struct Display {
+    pixels: [bool; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    dirty: bool,
+    palette: Option<Palette>,
+    rgba: Vec<u8>,
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Display {
+            pixels: [false; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            dirty: true,
+            palette: None,
+            rgba: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if self.pixels.iter().any(|&pixel| pixel) {
+            self.pixels = [false; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+            self.dirty = true;
+            self.render_rgba();
+        }
+    }
+
+    /// XORs a lit sprite pixel onto the screen, wrapping at the edges.
+    /// Returns true if the pixel was already on (a collision).
+    pub fn toggle(&mut self, x: usize, y: usize) -> bool {
+        let index = (y % DISPLAY_HEIGHT) * DISPLAY_WIDTH + (x % DISPLAY_WIDTH);
+        let collision = self.pixels[index];
+        self.pixels[index] = !collision;
+        self.dirty = true;
+        self.put_rgba(index);
+        collision
+    }
+
+    pub fn pixels(&self) -> &[bool] {
+        &self.pixels
+    }
+
+    /// Replaces every pixel, e.g. when restoring a save state.
+    pub fn restore(&mut self, pixels: &[bool; DISPLAY_WIDTH * DISPLAY_HEIGHT]) {
+        self.pixels = *pixels;
+        self.dirty = true;
+        self.render_rgba();
+    }
+
+    pub fn as_ptr(&self) -> *const bool {
+        self.pixels.as_ptr()
+    }
+
+    /// Enables RGBA output in the given colours, or turns it off with `None`.
+    /// Unlit pixels use the palette's first colour and lit ones its last.
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        self.palette = palette;
+        self.dirty = true;
+        self.render_rgba();
+    }
+
+    pub fn palette(&self) -> Option<Palette> {
+        self.palette
+    }
+
+    /// The screen as RGBA8888, or an empty slice without a palette.
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    fn render_rgba(&mut self) {
+        if self.palette.is_none() {
+            self.rgba = Vec::new();
+            return;
+        }
+        self.rgba.resize(DISPLAY_WIDTH * DISPLAY_HEIGHT * 4, 0);
+        for index in 0..self.pixels.len() {
+            self.put_rgba(index);
+        }
+    }
+
+    fn put_rgba(&mut self, index: usize) {
+        if let Some(palette) = self.palette {
+            let shade = if self.pixels[index] { 3 } else { 0 };
+            self.rgba[index * 4..index * 4 + 4].copy_from_slice(&palette.color(shade));
+        }
+    }
+
+    /// Returns whether any pixel changed since the last call, and resets the flag.
+    pub fn frame_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
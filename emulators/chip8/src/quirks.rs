@@ -0,0 +1,75 @@
+/// Behaviours that differ between CHIP-8 implementations, as named by the
+/// Timendus quirks test. ROMs written for one platform often misbehave on
+/// another, so the frontend can pick a profile to match the ROM. The
+/// default, `LEGACY`, keeps this core's behaviour from before profiles
+/// existed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy1`, `8xy2` and `8xy3` reset VF to 0.
+    pub vf_reset: bool,
+    /// `Fx55` and `Fx65` leave I pointing past the last register copied.
+    pub memory_increment: bool,
+    /// Sprites are cut off at the screen edges instead of wrapping around.
+    pub clipping: bool,
+    /// `8xy6` and `8xyE` shift VX in place rather than copying VY shifted.
+    pub shift_in_place: bool,
+    /// `Bnnn` jumps to `nnn + VX`, X being the top nibble of `nnn`, rather
+    /// than `nnn + V0`.
+    pub jump_with_vx: bool,
+}
+
+impl Quirks {
+    /// What this core always did: VF untouched by the logic ops, shifts in
+    /// place, sprites wrapping around the screen.
+    pub const LEGACY: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: true,
+        clipping: false,
+        shift_in_place: true,
+        jump_with_vx: false,
+    };
+
+    /// The original COSMAC VIP interpreter.
+    pub const CHIP8: Quirks = Quirks {
+        vf_reset: true,
+        memory_increment: true,
+        clipping: true,
+        shift_in_place: false,
+        jump_with_vx: false,
+    };
+
+    /// SUPER-CHIP 1.1, as most "modern" CHIP-8 games expect.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: false,
+        clipping: true,
+        shift_in_place: true,
+        jump_with_vx: true,
+    };
+
+    /// Octo's XO-CHIP.
+    pub const XO_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        memory_increment: true,
+        clipping: false,
+        shift_in_place: false,
+        jump_with_vx: false,
+    };
+
+    /// Looks up a profile by name: `legacy`, `chip8`, `schip` or `xochip`.
+    pub fn profile(name: &str) -> Option<Quirks> {
+        match name {
+            "legacy" => Some(Quirks::LEGACY),
+            "chip8" => Some(Quirks::CHIP8),
+            "schip" => Some(Quirks::SUPER_CHIP),
+            "xochip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::LEGACY
+    }
+}
@@ -0,0 +1,47 @@
+use getrandom::getrandom;
+
+/// xorshift64* generator used by `Cxkk`.
+///
+/// The whole state is a single `u64`, so it can be captured alongside the
+/// rest of the machine and replayed bit-for-bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn from_seed(seed: u64) -> Self {
+        // Run the seed through one SplitMix64 round so that small or zero
+        // seeds still give a well-mixed, non-zero xorshift state.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng { state: if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z } }
+    }
+
+    pub fn from_entropy() -> Self {
+        let mut seed = [0u8; 8];
+        // Without an entropy source we still want a working machine, just a
+        // predictable one.
+        let _ = getrandom(&mut seed);
+        Rng::from_seed(u64::from_le_bytes(seed))
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Restores a raw state captured with `state`. Zero is never produced by
+    /// the generator and would lock it up, so it is rejected.
+    pub fn from_state(state: u64) -> Option<Self> {
+        (state != 0).then_some(Rng { state })
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
@@ -0,0 +1,24 @@
+use crate::chip8::RegisterDump;
+pub use emu_common::trace::Tracer;
+
+/// Formats machine state before an instruction, in the same spirit as Game
+/// Boy Doctor logs:
+/// `V0:00 V1:00 ... VF:00 I:0000 SP:0 DT:00 ST:00 PC:0200 PCMEM:00,E0`,
+/// where PCMEM holds the two opcode bytes at PC.
+pub fn trace_line(registers: &RegisterDump, pcmem: [u8; 2]) -> String {
+    let mut line = String::with_capacity(128);
+    for (index, value) in registers.v.iter().enumerate() {
+        line.push_str(&format!("V{:X}:{:02X} ", index, value));
+    }
+    line.push_str(&format!(
+        "I:{:04X} SP:{:X} DT:{:02X} ST:{:02X} PC:{:04X} PCMEM:{:02X},{:02X}",
+        registers.index,
+        registers.sp,
+        registers.delay_timer,
+        registers.sound_timer,
+        registers.pc,
+        pcmem[0],
+        pcmem[1],
+    ));
+    line
+}
@@ -0,0 +1,386 @@
+//! The JavaScript API: the `Chip8` class and, for older frontends, free
+//! functions driving one shared machine.
+
+use std::cell::RefCell;
+use emu_common::EmulatorCore;
+use wasm_bindgen::prelude::*;
+
+use crate::disassembler::{self, Syntax};
+use emu_common::logging;
+use emu_common::palette::Palette;
+use crate::trace::Tracer;
+use crate::{Fault, Quirks};
+
+/// A CHIP-8 machine. Each instance is fully independent, so a page can run
+/// several side by side.
+#[wasm_bindgen]
+pub struct Chip8 {
+    machine: crate::Chip8,
+}
+
+#[wasm_bindgen]
+impl Chip8 {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Chip8 { machine: crate::Chip8::new() }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), JsValue> {
+        self.machine
+            .load_rom(rom)
+            .map_err(|error| JsValue::from_str(&error.to_string()))
+    }
+
+    /// Seeds the generator behind `Cxkk` so runs can be reproduced exactly.
+    /// The seed survives `load_rom`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.machine.set_seed(seed);
+    }
+
+    /// Selects the platform to emulate: `legacy` (the default), `chip8`,
+    /// `schip` or `xochip`. The choice survives `load_rom`.
+    pub fn set_quirks(&mut self, profile: &str) -> Result<(), JsValue> {
+        let quirks = Quirks::profile(profile)
+            .ok_or_else(|| JsValue::from_str(&format!("unknown quirk profile `{}`", profile)))?;
+        self.machine.quirks = quirks;
+        Ok(())
+    }
+
+    /// Snapshots the whole machine as a versioned binary blob.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.machine.save_state()
+    }
+
+    /// Restores a blob from `save_state`. Throws, leaving the machine as it
+    /// was, if the blob is corrupted or from another version.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.machine
+            .load_state(data)
+            .map_err(|error| JsValue::from_str(&error.to_string()))
+    }
+
+    /// Steps back to the latest rewind snapshot. Returns false when there is
+    /// no history left.
+    pub fn rewind_step(&mut self) -> bool {
+        self.machine.rewind_step()
+    }
+
+    /// Takes a rewind snapshot every `frames` cycles.
+    pub fn set_rewind_interval(&mut self, frames: u32) {
+        self.machine.rewind.set_interval(frames);
+    }
+
+    /// Caps the memory used by rewind history; 0 turns rewinding off.
+    pub fn set_rewind_budget(&mut self, bytes: usize) {
+        self.machine.rewind.set_budget(bytes);
+    }
+
+    /// Runs one instruction and ticks the timers. Throws the fault object
+    /// if the machine is halted.
+    pub fn cycle(&mut self) -> Result<(), JsValue> {
+        self.machine.cycle().map_err(|_| self.get_fault())
+    }
+
+    /// Runs one 60 Hz frame: `set_instructions_per_frame` instructions
+    /// (10 by default), one timer tick and a frame of buzzer audio. Throws
+    /// the fault object if the machine is halted.
+    pub fn run_frame(&mut self) -> Result<(), JsValue> {
+        EmulatorCore::run_frame(&mut self.machine).map_err(|_| self.get_fault())
+    }
+
+    /// Sets how many instructions `run_frame` executes, which sets the
+    /// game's speed. The choice survives `load_rom`.
+    pub fn set_instructions_per_frame(&mut self, count: u32) {
+        self.machine.instructions_per_frame = count;
+    }
+
+    /// Restarts the loaded ROM from power-on.
+    pub fn reset(&mut self) {
+        EmulatorCore::reset(&mut self.machine);
+    }
+
+    /// Runs up to `cycles` instructions, stopping early at a breakpoint.
+    /// Returns true if a breakpoint was hit; the next call resumes past it.
+    /// Throws the fault object if the machine halts.
+    pub fn run(&mut self, cycles: u32) -> Result<bool, JsValue> {
+        self.machine.run(cycles).map_err(|_| self.get_fault())
+    }
+
+    /// Executes exactly one instruction, ignoring breakpoints, and returns
+    /// the new PC.
+    pub fn step(&mut self) -> Result<u16, JsValue> {
+        self.cycle()?;
+        Ok(self.machine.pc())
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.machine.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.machine.breakpoints.remove(&address);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.machine.breakpoints.clear();
+    }
+
+    pub fn get_breakpoints(&self) -> Vec<u16> {
+        self.machine.breakpoints.iter().copied().collect()
+    }
+
+    /// Returns `{ v, i, pc, sp, stack, delay_timer, sound_timer }`, where `v`
+    /// holds V0-VF and `stack` only the live return addresses.
+    pub fn get_registers(&self) -> JsValue {
+        let registers = self.machine.register_dump();
+        let object = js_sys::Object::new();
+        set_property(&object, "v", js_sys::Uint8Array::from(&registers.v[..]).into());
+        set_property(&object, "i", registers.index.into());
+        set_property(&object, "pc", registers.pc.into());
+        set_property(&object, "sp", registers.sp.into());
+        set_property(&object, "stack", js_sys::Uint16Array::from(&registers.stack[..registers.sp]).into());
+        set_property(&object, "delay_timer", registers.delay_timer.into());
+        set_property(&object, "sound_timer", registers.sound_timer.into());
+        object.into()
+    }
+
+    /// Disassembles `count` instructions from `address` in Octo syntax, or
+    /// Cowgod's classic mnemonics when `octo` is false. Returns an array of
+    /// `{ address, opcode, text }`.
+    pub fn disassemble(&self, address: u16, count: usize, octo: bool) -> JsValue {
+        let syntax = if octo { Syntax::Octo } else { Syntax::Cowgod };
+        let array = js_sys::Array::new();
+        for line in disassembler::disassemble(self.machine.memory(), address, count, syntax) {
+            let object = js_sys::Object::new();
+            set_property(&object, "address", line.address.into());
+            set_property(&object, "opcode", line.opcode.into());
+            set_property(&object, "text", line.text.into());
+            array.push(&object);
+        }
+        array.into()
+    }
+
+    /// Starts logging every instruction, keeping the most recent `capacity`
+    /// lines. Tracing survives `load_rom`.
+    pub fn start_trace(&mut self, capacity: usize) {
+        self.machine.tracer = Some(Tracer::ring(capacity));
+    }
+
+    pub fn stop_trace(&mut self) {
+        self.machine.tracer = None;
+    }
+
+    /// Returns and clears the buffered trace, one instruction per line.
+    pub fn take_trace(&mut self) -> String {
+        self.machine
+            .tracer
+            .as_mut()
+            .map_or_else(Vec::new, |tracer| tracer.drain())
+            .join("\n")
+    }
+
+    /// Returns the current fault as `{ opcode, pc, reason }`, or `null`.
+    pub fn get_fault(&self) -> JsValue {
+        self.machine.fault().map_or(JsValue::NULL, fault_to_js)
+    }
+
+    pub fn get_display_buffer(&self) -> *const bool {
+        self.machine.display.as_ptr()
+    }
+
+    /// Turns on RGBA output in a preset palette: `chip8`, `dmg`, `pocket`
+    /// or `grey`. The choice survives `load_rom`.
+    pub fn set_palette(&mut self, name: &str) -> Result<(), JsValue> {
+        let palette = Palette::named(name).ok_or_else(|| JsValue::from_str(&format!("unknown palette `{}`", name)))?;
+        self.machine.display.set_palette(Some(palette));
+        Ok(())
+    }
+
+    /// Turns on RGBA output with `0xRRGGBB` background and foreground colours.
+    pub fn set_colors(&mut self, background: u32, foreground: u32) {
+        self.machine.display.set_palette(Some(Palette::monochrome(background, foreground)));
+    }
+
+    /// Address of the 64x32 RGBA buffer, for wrapping in an `ImageData`. Ask
+    /// again after changing the palette or loading a ROM, as it moves.
+    pub fn get_rgba_buffer(&self) -> *const u8 {
+        self.machine.display.rgba().as_ptr()
+    }
+
+    /// True if the display changed since the last call; the frontend can skip
+    /// redrawing otherwise.
+    pub fn frame_changed(&mut self) -> bool {
+        self.machine.display.frame_changed()
+    }
+
+    pub fn key_down(&mut self, key: u8) {
+        if let Some(pressed) = self.machine.keypad.get_mut(key as usize) {
+            *pressed = true;
+        }
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        if let Some(pressed) = self.machine.keypad.get_mut(key as usize) {
+            *pressed = false;
+        }
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Converts a fault into a `{ opcode, pc, reason }` object for JS.
+fn fault_to_js(fault: &Fault) -> JsValue {
+    let object = js_sys::Object::new();
+    set_property(&object, "opcode", fault.opcode.into());
+    set_property(&object, "pc", fault.pc.into());
+    set_property(&object, "reason", fault.error.to_string().into());
+    object.into()
+}
+
+fn set_property(object: &js_sys::Object, key: &str, value: JsValue) {
+    let _ = js_sys::Reflect::set(object, &key.into(), &value);
+}
+
+// The free functions below drive one shared machine and are kept for
+// frontends written before the `Chip8` class existed.
+
+thread_local! {
+    static CHIP8: RefCell<Chip8> = RefCell::new(Chip8::new());
+}
+
+#[wasm_bindgen]
+pub fn load_rom(rom: &[u8]) -> Result<(), JsValue> {
+    CHIP8.with(|chip8| chip8.borrow_mut().load_rom(rom))
+}
+
+#[wasm_bindgen]
+pub fn set_seed(seed: u64) {
+    CHIP8.with(|chip8| chip8.borrow_mut().set_seed(seed));
+}
+
+#[wasm_bindgen]
+pub fn set_quirks(profile: &str) -> Result<(), JsValue> {
+    CHIP8.with(|chip8| chip8.borrow_mut().set_quirks(profile))
+}
+
+#[wasm_bindgen]
+pub fn cycle() -> Result<(), JsValue> {
+    CHIP8.with(|chip8| chip8.borrow_mut().cycle())
+}
+
+#[wasm_bindgen]
+pub fn save_state() -> Vec<u8> {
+    CHIP8.with(|chip8| chip8.borrow().save_state())
+}
+
+#[wasm_bindgen]
+pub fn load_state(data: &[u8]) -> Result<(), JsValue> {
+    CHIP8.with(|chip8| chip8.borrow_mut().load_state(data))
+}
+
+#[wasm_bindgen]
+pub fn rewind_step() -> bool {
+    CHIP8.with(|chip8| chip8.borrow_mut().rewind_step())
+}
+
+#[wasm_bindgen]
+pub fn set_rewind_interval(frames: u32) {
+    CHIP8.with(|chip8| chip8.borrow_mut().set_rewind_interval(frames));
+}
+
+#[wasm_bindgen]
+pub fn set_rewind_budget(bytes: usize) {
+    CHIP8.with(|chip8| chip8.borrow_mut().set_rewind_budget(bytes));
+}
+
+#[wasm_bindgen]
+pub fn run(cycles: u32) -> Result<bool, JsValue> {
+    CHIP8.with(|chip8| chip8.borrow_mut().run(cycles))
+}
+
+#[wasm_bindgen]
+pub fn step() -> Result<u16, JsValue> {
+    CHIP8.with(|chip8| chip8.borrow_mut().step())
+}
+
+#[wasm_bindgen]
+pub fn add_breakpoint(address: u16) {
+    CHIP8.with(|chip8| chip8.borrow_mut().add_breakpoint(address));
+}
+
+#[wasm_bindgen]
+pub fn remove_breakpoint(address: u16) {
+    CHIP8.with(|chip8| chip8.borrow_mut().remove_breakpoint(address));
+}
+
+#[wasm_bindgen]
+pub fn clear_breakpoints() {
+    CHIP8.with(|chip8| chip8.borrow_mut().clear_breakpoints());
+}
+
+#[wasm_bindgen]
+pub fn get_breakpoints() -> Vec<u16> {
+    CHIP8.with(|chip8| chip8.borrow().get_breakpoints())
+}
+
+#[wasm_bindgen]
+pub fn get_registers() -> JsValue {
+    CHIP8.with(|chip8| chip8.borrow().get_registers())
+}
+
+#[wasm_bindgen]
+pub fn disassemble(address: u16, count: usize, octo: bool) -> JsValue {
+    CHIP8.with(|chip8| chip8.borrow().disassemble(address, count, octo))
+}
+
+#[wasm_bindgen]
+pub fn start_trace(capacity: usize) {
+    CHIP8.with(|chip8| chip8.borrow_mut().start_trace(capacity));
+}
+
+#[wasm_bindgen]
+pub fn stop_trace() {
+    CHIP8.with(|chip8| chip8.borrow_mut().stop_trace());
+}
+
+#[wasm_bindgen]
+pub fn take_trace() -> String {
+    CHIP8.with(|chip8| chip8.borrow_mut().take_trace())
+}
+
+#[wasm_bindgen]
+pub fn get_fault() -> JsValue {
+    CHIP8.with(|chip8| chip8.borrow().get_fault())
+}
+
+#[wasm_bindgen]
+pub fn get_display_buffer() -> *const bool {
+    CHIP8.with(|chip8| chip8.borrow().get_display_buffer())
+}
+
+#[wasm_bindgen]
+pub fn frame_changed() -> bool {
+    CHIP8.with(|chip8| chip8.borrow_mut().frame_changed())
+}
+
+#[wasm_bindgen]
+pub fn key_down(key: u8) {
+    CHIP8.with(|chip8| chip8.borrow_mut().key_down(key));
+}
+
+#[wasm_bindgen]
+pub fn key_up(key: u8) {
+    CHIP8.with(|chip8| chip8.borrow_mut().key_up(key));
+}
+
+/// Sets the runtime log level: 0 silences logging, 1-5 select error, warn,
+/// info, debug or trace. Levels compiled out with the `max_level_*` cargo
+/// features stay silent regardless.
+#[wasm_bindgen]
+pub fn set_log_level(level: u8) {
+    logging::set_level(logging::Level::from_u8(level));
+}
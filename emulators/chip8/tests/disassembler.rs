@@ -0,0 +1,66 @@
+use chip8::disassembler::{disassemble, mnemonic, Syntax};
+use chip8::Chip8;
+
+#[test]
+fn formats_both_syntaxes() {
+    // Opcode, Octo, Cowgod
+    let cases = [
+        (0x00E0, "clear", "CLS"),
+        (0x00EE, "return", "RET"),
+        (0x1234, "jump 0x234", "JP #234"),
+        (0x2ABC, ":call 0xABC", "CALL #ABC"),
+        // Octo prints the condition under which the next line runs
+        (0x3A1F, "if va != 0x1F then", "SE VA, #1F"),
+        (0x4A1F, "if va == 0x1F then", "SNE VA, #1F"),
+        (0x5120, "if v1 != v2 then", "SE V1, V2"),
+        (0x9120, "if v1 == v2 then", "SNE V1, V2"),
+        (0x631F, "v3 := 0x1F", "LD V3, #1F"),
+        (0x8124, "v1 += v2", "ADD V1, V2"),
+        (0x8127, "v1 =- v2", "SUBN V1, V2"),
+        (0x812E, "v1 <<= v2", "SHL V1, V2"),
+        (0xA2F0, "i := 0x2F0", "LD I, #2F0"),
+        (0xB300, "jump0 0x300", "JP V0, #300"),
+        (0xC40F, "v4 := random 0x0F", "RND V4, #0F"),
+        (0xD015, "sprite v0 v1 5", "DRW V0, V1, 5"),
+        (0xE59E, "if v5 -key then", "SKP V5"),
+        (0xF00A, "v0 := key", "LD V0, K"),
+        (0xF133, "bcd v1", "LD B, V1"),
+        (0xF265, "load v2", "LD V2, [I]"),
+        (0x5121, "0x51 0x21", "DW #5121"),
+    ];
+    for (opcode, octo, cowgod) in cases {
+        assert_eq!(mnemonic(opcode, Syntax::Octo), octo, "{:04X}", opcode);
+        assert_eq!(mnemonic(opcode, Syntax::Cowgod), cowgod, "{:04X}", opcode);
+    }
+    assert_eq!(mnemonic(0x0123, Syntax::Cowgod), "SYS #123");
+}
+
+#[test]
+fn lists_a_loaded_rom() {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(&[0x60, 0x05, 0x70, 0x03, 0x12, 0x04]).unwrap();
+
+    let listing = disassemble(chip8.memory(), 0x200, 3, Syntax::Cowgod);
+    let lines: Vec<(u16, u16, &str)> = listing
+        .iter()
+        .map(|line| (line.address, line.opcode, line.text.as_str()))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (0x200, 0x6005, "LD V0, #05"),
+            (0x202, 0x7003, "ADD V0, #03"),
+            (0x204, 0x1204, "JP #204"),
+        ]
+    );
+}
+
+#[test]
+fn stops_at_the_end_of_memory() {
+    let memory = [0x00, 0xE0, 0x00, 0xEE, 0x12];
+    let listing = disassemble(&memory, 0, 10, Syntax::Octo);
+    assert_eq!(listing.len(), 2);
+    assert_eq!(listing[1].text, "return");
+
+    assert!(disassemble(&memory, 4, 1, Syntax::Octo).is_empty());
+}
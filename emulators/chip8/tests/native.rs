@@ -0,0 +1,118 @@
+use chip8::Chip8;
+use emu_common::palette::Palette;
+use emu_common::{EmulatorCore, PixelFormat};
+
+#[test]
+fn runs_natively() {
+    let mut chip8 = Chip8::new();
+    // V0 = 5; V0 += 3; jump to self
+    chip8.load_rom(&[0x60, 0x05, 0x70, 0x03, 0x12, 0x04]).unwrap();
+    chip8.breakpoints.insert(0x204);
+
+    assert!(chip8.run(10).unwrap());
+    assert_eq!(chip8.register_dump().v[0], 8);
+    assert_eq!(chip8.pc(), 0x204);
+
+    let state = chip8.save_state();
+    chip8.cycle().unwrap();
+    chip8.load_state(&state).unwrap();
+    assert_eq!(chip8.pc(), 0x204);
+}
+
+#[test]
+fn replacing_pc_rearms_the_breakpoint_there() {
+    // V0 = 5; jump to self
+    let rom = [0x60, 0x05, 0x12, 0x02];
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(&rom).unwrap();
+    chip8.breakpoints.insert(0x200);
+    let state = chip8.save_state();
+    assert!(chip8.run(1).unwrap());
+
+    chip8.load_state(&state).unwrap();
+    assert!(chip8.run(1).unwrap(), "after load_state");
+    chip8.load_rom(&rom).unwrap();
+    assert!(chip8.run(1).unwrap(), "after load_rom");
+    EmulatorCore::reset(&mut chip8);
+    assert!(chip8.run(1).unwrap(), "after reset");
+    assert_eq!(chip8.register_dump().v[0], 0);
+}
+
+#[test]
+fn drives_through_emulator_core() {
+    let mut core: Box<dyn EmulatorCore> = Box::new(Chip8::new());
+    // V0 = 5; sound timer = V0; jump to self
+    core.load_rom(&[0x60, 0x05, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+    for _ in 0..3 {
+        core.run_frame().unwrap();
+    }
+
+    let mut audio = Vec::new();
+    core.drain_audio(&mut audio);
+    assert_eq!(audio.len(), 3 * core.audio_sample_rate() as usize / 60);
+    assert!(audio.iter().any(|&sample| sample != 0.0));
+    assert_eq!(core.frame_buffer().len(), core.frame_buffer_info().len());
+
+    let state = core.save_state();
+    core.reset();
+    core.load_state(&state).unwrap();
+}
+
+#[test]
+fn frames_run_several_instructions_per_timer_tick() {
+    let mut core = Chip8::new();
+    // V0 = 10; delay timer = V0; then V1 += 1 in a loop
+    core.load_rom(&[0x60, 0x0A, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04]).unwrap();
+    assert_eq!(core.instructions_per_frame, 10);
+
+    EmulatorCore::run_frame(&mut core).unwrap();
+    let registers = core.register_dump();
+    assert_eq!((registers.v[1], registers.delay_timer), (4, 9));
+
+    core.instructions_per_frame = 20;
+    EmulatorCore::run_frame(&mut core).unwrap();
+    let registers = core.register_dump();
+    assert_eq!((registers.v[1], registers.delay_timer), (14, 8));
+
+    // A frame's worth of audio either way
+    let mut audio = Vec::new();
+    core.drain_audio(&mut audio);
+    assert_eq!(audio.len(), 2 * core.audio_sample_rate() as usize / 60);
+}
+
+#[test]
+fn debug_frames_stop_at_breakpoints() {
+    let mut chip8 = Chip8::new();
+    // V0 = 10; delay timer = V0; then V1 += 1 in a loop
+    chip8.load_rom(&[0x60, 0x0A, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04]).unwrap();
+    chip8.breakpoints.insert(0x206);
+
+    // Stopped partway through the frame, so the timer hasn't ticked
+    assert!(chip8.debug_run_to_frame().unwrap());
+    let registers = chip8.register_dump();
+    assert_eq!((chip8.pc(), registers.v[1], registers.delay_timer), (0x206, 1, 10));
+
+    chip8.breakpoints.clear();
+    assert!(!chip8.debug_run_to_frame().unwrap());
+    let registers = chip8.register_dump();
+    assert_eq!((registers.v[1], registers.delay_timer), (6, 9));
+}
+
+#[test]
+fn renders_rgba_in_palette() {
+    let mut chip8 = Chip8::new();
+    chip8.display.set_palette(Some(Palette::monochrome(0x112233, 0xAABBCC)));
+    // Draw the "0" glyph at the top left, whose first row is lit
+    chip8.load_rom(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05]).unwrap();
+    chip8.run(3).unwrap();
+
+    assert_eq!(chip8.frame_buffer_info().format, PixelFormat::Rgba8888);
+    let rgba = chip8.frame_buffer();
+    assert_eq!(rgba.len(), chip8.frame_buffer_info().len());
+    assert_eq!(rgba[..4], [0xAA, 0xBB, 0xCC, 0xFF]);
+    assert_eq!(rgba[4 * 63..4 * 64], [0x11, 0x22, 0x33, 0xFF]);
+
+    chip8.display.set_palette(None);
+    assert_eq!(chip8.frame_buffer_info().format, PixelFormat::Gray8);
+    assert_eq!(chip8.frame_buffer()[0], 0xFF);
+}
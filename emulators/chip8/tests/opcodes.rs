@@ -0,0 +1,192 @@
+//! Regressions for individual opcodes, so fixes don't depend on the Timendus
+//! ROMs being around.
+
+use chip8::{Chip8, Chip8Error, Quirks};
+
+/// Loads `program`, runs one instruction per opcode and returns the machine.
+fn run(quirks: Quirks, program: &[u16]) -> Chip8 {
+    let rom: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+    let mut machine = Chip8::new();
+    machine.quirks = quirks;
+    machine.load_rom(&rom).unwrap();
+    machine.run(program.len() as u32).unwrap();
+    machine
+}
+
+#[test]
+fn skip_if_registers_equal() {
+    // V0 = V1 = 7; 5010 skips the next instruction
+    let machine = run(Quirks::CHIP8, &[0x6007, 0x6107, 0x5010]);
+    assert_eq!(machine.pc(), 0x208);
+
+    let machine = run(Quirks::CHIP8, &[0x6007, 0x6108, 0x5010]);
+    assert_eq!(machine.pc(), 0x206);
+}
+
+#[test]
+fn subtraction_sets_vf_without_borrow() {
+    // 5 - 5 doesn't borrow
+    let machine = run(Quirks::CHIP8, &[0x6005, 0x6105, 0x8015]);
+    assert_eq!(machine.register_dump().v[0], 0);
+    assert_eq!(machine.register_dump().v[0xF], 1);
+
+    // 4 - 5 borrows
+    let machine = run(Quirks::CHIP8, &[0x6005, 0x6104, 0x8017]);
+    assert_eq!(machine.register_dump().v[0], 0xFF);
+    assert_eq!(machine.register_dump().v[0xF], 0);
+}
+
+#[test]
+fn flag_wins_when_vf_is_the_destination() {
+    // VF = 0xFF; VF += 1 leaves the carry, not the sum
+    let machine = run(Quirks::CHIP8, &[0x6FFF, 0x6101, 0x8F14]);
+    assert_eq!(machine.register_dump().v[0xF], 1);
+}
+
+#[test]
+fn shift_quirk_selects_source() {
+    // V0 = 1, V1 = 4; 8016 shifts V1 into V0 on the COSMAC VIP
+    let machine = run(Quirks::CHIP8, &[0x6001, 0x6104, 0x8016]);
+    assert_eq!(machine.register_dump().v[0], 2);
+    assert_eq!(machine.register_dump().v[0xF], 0);
+
+    let machine = run(Quirks::SUPER_CHIP, &[0x6001, 0x6104, 0x8016]);
+    assert_eq!(machine.register_dump().v[0], 0);
+    assert_eq!(machine.register_dump().v[0xF], 1);
+}
+
+#[test]
+fn default_quirks_keep_the_legacy_behaviour() {
+    let default = |program: &[u16]| {
+        let rom: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let mut machine = Chip8::new();
+        machine.load_rom(&rom).unwrap();
+        machine.run(program.len() as u32).unwrap();
+        machine
+    };
+    assert_eq!(Quirks::default(), Quirks::LEGACY);
+
+    // V0 = 1, V1 = 4; 8016 shifts V0 in place
+    let machine = default(&[0x6001, 0x6104, 0x8016]);
+    assert_eq!(machine.register_dump().v[0], 0);
+    assert_eq!(machine.register_dump().v[0xF], 1);
+
+    // VF = 5; V0 |= V1 leaves VF alone
+    let machine = default(&[0x6F05, 0x8011]);
+    assert_eq!(machine.register_dump().v[0xF], 5);
+
+    // The "0" glyph drawn at x = 62 wraps onto the left edge
+    let machine = default(&[0x603E, 0x6100, 0x6200, 0xF229, 0xD015]);
+    assert!(machine.display.pixels()[0]);
+}
+
+#[test]
+fn clear_screen() {
+    // Draw the "0" glyph, then clear
+    let machine = run(Quirks::CHIP8, &[0x6000, 0xF029, 0xD005, 0x00E0]);
+    assert!(machine.display.pixels().iter().all(|&lit| !lit));
+}
+
+#[test]
+fn clipped_sprite_rows_are_not_read() {
+    // Two rows on the bottom line from I = 0xFFF: the second row would come
+    // from past the end of memory, but clipping drops it
+    let machine = run(Quirks::CHIP8, &[0x6000, 0x611F, 0xAFFF, 0xD012]);
+    assert!(machine.fault().is_none());
+
+    let (_, error) = run_to_fault(&[0x6000, 0x611F, 0xAFFF, 0xD012]);
+    assert_eq!(error, Chip8Error::MemoryOutOfBounds { address: 0x1000 }, "wrapping still reads it");
+}
+
+/// Loads `program` and runs it until the first error, which it returns
+/// along with the machine.
+fn run_to_fault(program: &[u16]) -> (Chip8, Chip8Error) {
+    let rom: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+    let mut machine = Chip8::new();
+    machine.load_rom(&rom).unwrap();
+    let error = machine.run(100).expect_err("program ran without faulting");
+    (machine, error)
+}
+
+#[test]
+fn faults_are_reported_with_the_failing_instruction() {
+    let cases: [(&[u16], Chip8Error, u16); 5] = [
+        (&[0x00EE], Chip8Error::StackUnderflow, 0x200),
+        // Calls itself until all 16 stack slots are used
+        (&[0x2200], Chip8Error::StackOverflow, 0x200),
+        // 0nnn machine code routines aren't supported
+        (&[0x0123], Chip8Error::UnknownOpcode(0x0123), 0x200),
+        // I = 0xFFF, then read V0 and V1 from 0xFFF and 0x1000
+        (&[0xAFFF, 0xF165], Chip8Error::MemoryOutOfBounds { address: 0x1000 }, 0x202),
+        // Jumping to the last byte leaves no room for an opcode
+        (&[0x1FFF], Chip8Error::PcOutOfBounds { pc: 0xFFF }, 0xFFF),
+    ];
+    for (program, error, pc) in cases {
+        let (machine, reported) = run_to_fault(program);
+        assert_eq!(reported, error, "{:04X?}", program);
+        let fault = machine.fault().expect("fault not recorded");
+        assert_eq!((fault.error, fault.pc), (error, pc), "{:04X?}", program);
+    }
+}
+
+#[test]
+fn faulted_machine_stays_halted_until_reset() {
+    let (mut machine, error) = run_to_fault(&[0x6005, 0x00EE]);
+    let registers = machine.register_dump();
+    for _ in 0..3 {
+        assert_eq!(machine.cycle(), Err(error));
+    }
+    assert_eq!(machine.register_dump(), registers);
+
+    // Reloading the ROM clears the fault and starts over
+    machine.load_rom(&[0x60, 0x05, 0x00, 0xEE]).unwrap();
+    assert!(machine.fault().is_none());
+    machine.cycle().unwrap();
+    assert_eq!(machine.register_dump().v[0], 5);
+}
+
+#[test]
+fn oversized_roms_are_rejected() {
+    let mut machine = Chip8::new();
+    assert_eq!(machine.load_rom(&[0; 3584]), Ok(()));
+    assert_eq!(machine.load_rom(&[0; 3585]), Err(Chip8Error::RomTooLarge { size: 3585, max: 3584 }));
+}
+
+#[test]
+fn frame_changed_tracks_screen_writes() {
+    // Draw the "0" glyph, clear, then clear an already blank screen
+    let rom: Vec<u8> = [0x6000u16, 0xF029, 0xD005, 0x00E0, 0x00E0].iter().flat_map(|op| op.to_be_bytes()).collect();
+    let mut machine = Chip8::new();
+    machine.load_rom(&rom).unwrap();
+    assert!(machine.display.frame_changed(), "a new display needs a first draw");
+    assert!(!machine.display.frame_changed(), "reading the flag clears it");
+
+    machine.run(2).unwrap();
+    assert!(!machine.display.frame_changed());
+    machine.run(1).unwrap();
+    assert!(machine.display.frame_changed(), "DRW marks the frame");
+    machine.run(1).unwrap();
+    assert!(machine.display.frame_changed(), "CLS marks the frame");
+    machine.run(1).unwrap();
+    assert!(!machine.display.frame_changed(), "clearing a blank screen changes nothing");
+}
+
+#[test]
+fn seeded_random_numbers_repeat() {
+    // V0..V7 = random bytes, then stop
+    let program: Vec<u16> = (0..8).map(|x| 0xC0FF | x << 8).collect();
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    let random_bytes = |seed: u64| {
+        let mut machine = Chip8::new();
+        machine.load_rom(&rom).unwrap();
+        machine.set_seed(seed);
+        machine.run(8).unwrap();
+        machine.register_dump().v
+    };
+
+    assert_eq!(random_bytes(42), random_bytes(42));
+    assert_ne!(random_bytes(42), random_bytes(43));
+    // Seed 0 still produces numbers rather than a stuck generator
+    let zero = random_bytes(0);
+    assert!(zero[..8].iter().any(|&byte| byte != zero[0]));
+}
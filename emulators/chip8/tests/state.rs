@@ -0,0 +1,20 @@
+use chip8::{Chip8, StateError};
+
+#[test]
+fn rejected_states_leave_the_machine_alone() {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(&[0x60, 0x05, 0x70, 0x03, 0x12, 0x04]).unwrap();
+    let state = chip8.save_state();
+    chip8.cycle().unwrap();
+    chip8.cycle().unwrap();
+    let current = chip8.save_state();
+
+    let mut corrupt = state.clone();
+    corrupt[100] ^= 0xFF;
+    assert_eq!(chip8.load_state(&corrupt), Err(StateError::ChecksumMismatch));
+    assert!(matches!(chip8.load_state(&state[..state.len() - 1]), Err(StateError::WrongLength { .. })));
+    assert_eq!(chip8.save_state(), current);
+
+    chip8.load_state(&state).unwrap();
+    assert_eq!(chip8.save_state(), state);
+}
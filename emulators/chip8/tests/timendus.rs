@@ -0,0 +1,108 @@
+//! Runs the Timendus CHIP-8 test suite and compares the final screen of each
+//! ROM against a golden image.
+//!
+//! The ROMs are read from `$CHIP8_TEST_ROMS` if set, otherwise `tests/roms`,
+//! under their names in the suite (`3-corax+.ch8`, ...). Missing ROMs are
+//! skipped. Golden images live in `tests/golden` as text, `#` for a lit
+//! pixel; after checking a changed screen by eye, rerun with
+//! `CHIP8_BLESS=1` to record it.
+
+use std::path::{Path, PathBuf};
+
+use chip8::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use chip8::{Chip8, Quirks};
+
+/// Enough instructions for every test to reach its result screen.
+const CYCLES: u32 = 20_000;
+
+struct Case {
+    rom: &'static str,
+    profile: &'static str,
+    /// Value stored at 0x1FF, which the suite reads to skip its menus.
+    select: Option<u8>,
+}
+
+const CASES: &[Case] = &[
+    Case { rom: "1-chip8-logo.ch8", profile: "chip8", select: None },
+    Case { rom: "2-ibm-logo.ch8", profile: "chip8", select: None },
+    Case { rom: "3-corax+.ch8", profile: "chip8", select: None },
+    Case { rom: "4-flags.ch8", profile: "chip8", select: None },
+    Case { rom: "5-quirks.ch8", profile: "chip8", select: Some(1) },
+    Case { rom: "5-quirks.ch8", profile: "schip", select: Some(2) },
+    Case { rom: "5-quirks.ch8", profile: "xochip", select: Some(3) },
+    Case { rom: "6-keypad.ch8", profile: "chip8", select: Some(1) },
+];
+
+#[test]
+fn timendus() {
+    let roms = std::env::var_os("CHIP8_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| manifest_path("tests/roms"));
+    let bless = std::env::var_os("CHIP8_BLESS").is_some();
+
+    let mut failures = Vec::new();
+    for case in CASES {
+        let name = format!("{}.{}", case.rom.trim_end_matches(".ch8"), case.profile);
+        let Ok(rom) = std::fs::read(roms.join(case.rom)) else {
+            eprintln!("skip  {}: ROM not found", name);
+            continue;
+        };
+
+        let screen = match run(case, &rom) {
+            Ok(screen) => screen,
+            Err(error) => {
+                eprintln!("FAIL  {}: {}", name, error);
+                failures.push(name);
+                continue;
+            }
+        };
+
+        let golden_path = manifest_path("tests/golden").join(format!("{}.txt", name));
+        if bless {
+            std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+            std::fs::write(&golden_path, &screen).unwrap();
+            eprintln!("bless {}", name);
+            continue;
+        }
+        match std::fs::read_to_string(&golden_path) {
+            Ok(golden) if golden == screen => eprintln!("pass  {}", name),
+            Ok(_) => {
+                eprintln!("FAIL  {}: screen differs from {}, got:\n{}", name, golden_path.display(), screen);
+                failures.push(name);
+            }
+            Err(_) => {
+                eprintln!("FAIL  {}: no golden image, rerun with CHIP8_BLESS=1 to record one", name);
+                failures.push(name);
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{} Timendus cases failed: {}", failures.len(), failures.join(", "));
+}
+
+/// Runs one case and returns its final screen in golden image form.
+fn run(case: &Case, rom: &[u8]) -> Result<String, String> {
+    let mut machine = Chip8::new();
+    machine.set_seed(0);
+    machine.quirks = Quirks::profile(case.profile).expect("known profile");
+    machine.load_rom(rom).map_err(|error| error.to_string())?;
+    if let Some(select) = case.select {
+        machine.write_byte(0x1FF, select).map_err(|error| error.to_string())?;
+    }
+    machine.run(CYCLES).map_err(|error| {
+        let fault = machine.fault().expect("a failed run leaves a fault");
+        format!("{} (opcode {:04X} at {:#05X})", error, fault.opcode, fault.pc)
+    })?;
+
+    let pixels = machine.display.pixels();
+    let mut screen = String::with_capacity((DISPLAY_WIDTH + 1) * DISPLAY_HEIGHT);
+    for row in pixels.chunks(DISPLAY_WIDTH) {
+        screen.extend(row.iter().map(|&lit| if lit { '#' } else { '.' }));
+        screen.push('\n');
+    }
+    Ok(screen)
+}
+
+fn manifest_path(relative: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(relative)
+}
@@ -0,0 +1,22 @@
+use chip8::Chip8;
+use chip8::trace::Tracer;
+
+#[test]
+fn traces_each_instruction() {
+    let mut chip8 = Chip8::new();
+    // V0 = 5; I = 0x2F0; jump to self
+    chip8.load_rom(&[0x60, 0x05, 0xA2, 0xF0, 0x12, 0x04]).unwrap();
+    chip8.tracer = Some(Tracer::ring(2));
+    for _ in 0..3 {
+        chip8.cycle().unwrap();
+    }
+
+    let zeros = "V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 V8:00 V9:00 VA:00 VB:00 VC:00 VD:00 VE:00 VF:00";
+    assert_eq!(
+        chip8.tracer.as_mut().unwrap().drain(),
+        [
+            format!("V0:05 {} I:0000 SP:0 DT:00 ST:00 PC:0202 PCMEM:A2,F0", zeros),
+            format!("V0:05 {} I:02F0 SP:0 DT:00 ST:00 PC:0204 PCMEM:12,04", zeros),
+        ]
+    );
+}
@@ -1,6 +1,11 @@
+#![allow(clippy::upper_case_acronyms)]
+
 use crate::memory::MemoryBus;
 use crate::ppu::GPU;
-use crate::console_log;
+use crate::debugger::RegisterDump;
+use emu_common::{log_trace, log_warn};
+use crate::instruction::{decode, ArithmeticTarget, Condition, Indirect, Instruction, RegisterPair};
+use emu_common::state::{StateError, StateReader, StateWriter};
 use std::rc::Rc;
 use std::cell::RefCell;
 
@@ -92,198 +97,122 @@ impl Registers {
         ((self.h as u16) << 8) | self.l as u16
     }
 
-    fn reset(&mut self) {
-        self.a = 0;
-        self.b = 0;
-        self.c = 0;
-        self.d = 0;
-        self.e = 0;
-        self.h = 0;
-        self.l = 0;
-        self.f = FlagRegister {
-            zero: false,
-            subtract: false,
-            half_carry: false,
-            carry: false,
-        };
-    }
-
-
-    fn read_register_8(&self, reg: u8) -> u8 {
-        match reg {
-            0 => self.b,
-            1 => self.c,
-            2 => self.d,
-            3 => self.e,
-            4 => self.h,
-            5 => self.l,
-            6 => u8::from(self.f), 
-            7 => self.a,
-            _ => 0,
-        }
-    }
-
-    fn write_register_8(&mut self, reg: u8, value: u8) {
-        match reg {
-            0 => self.b = value,
-            1 => self.c = value,
-            2 => self.d = value,
-            3 => self.e = value,
-            4 => self.h = value,
-            5 => self.l = value,
-            6 => self.f = FlagRegister::from(value), 
-            7 => self.a = value,
-            _ => {}
-        }
-    }
-
-}
-
-
-#[derive(Debug, Clone, Copy)]
-enum Instruction {
-    ADD(ArithmeticTarget),        // Add register to A
-    ADDHL,                        // Add 16-bit register to HL
-    ADC(ArithmeticTarget),        // Add with carry
-    SUB(ArithmeticTarget),        // Subtract from A
-    SBC(ArithmeticTarget),        // Subtract with carry
-    AND(ArithmeticTarget),        // Logical AND with A
-    OR(ArithmeticTarget),         // Logical OR with A
-    XOR(ArithmeticTarget),        // Logical XOR with A
-    CP(ArithmeticTarget),         // Compare A with register
-    INC(ArithmeticTarget),        // Increment register
-    DEC(ArithmeticTarget),        // Decrement register
-    CCF,                          // Complement Carry Flag
-    SCF,                          // Set Carry Flag
-    RRA,                          // Rotate A right through carry
-    RLA,                          // Rotate A left through carry
-    RRCA,                         // Rotate A right (no carry)
-    RLCA,                         // Rotate A left (no carry)
-    CPL,                          // Complement A
-    BIT(u8, ArithmeticTarget),    // Test bit
-    RESET(u8, ArithmeticTarget),  // Reset bit
-    SET(u8, ArithmeticTarget),    // Set bit
-    SRL(ArithmeticTarget),        // Shift right logical
-    RR(ArithmeticTarget),         // Rotate right through carry
-    RL(ArithmeticTarget),         // Rotate left through carry
-    RRC(ArithmeticTarget),        // Rotate right (no carry)
-    RLC(ArithmeticTarget),        // Rotate left (no carry)
-    SRA(ArithmeticTarget),        // Shift right arithmetic
-    SLA(ArithmeticTarget),        // Shift left arithmetic
-    SWAP(ArithmeticTarget),       // Swap nibbles
-
-    LD(ArithmeticTarget, ArithmeticTarget), // Load from one register to another
-    LDFromSP(u16),                          // Load memory from stack pointer
-    LDSPFromHL,                             // Load stack pointer from HL
-    LDHLFromSP(i8),                         // Load HL from SP + signed offset
-    POP(RegisterPair),                      // Pop value from stack into register pair
-    PUSH(RegisterPair),
-    PUSHAF,                    // Push value from register pair onto stack
-    LDHLADecrement,
-    INCHL,
-    LDAFromHLIncrement,
-    POPAF,
-    INCBC,
-        // New instructions for immediate values and memory access
-    LDImmediate8(ArithmeticTarget, u8),
-    LDImmediate16(RegisterPair, u16),
-    LDIOOffsetFromA(u8),
-    LDIOOffsetToA(u8),
-    ADDImmediate(u8),
-    ANDImmediate(u8),
-    CPImmediate(u8),
-    ADCImmediate(u8), // Add immediate value to A with carry
-    SBCImmediate(u8), // Subtract immediate value from A with carry
-    SUBImmediate(u8), // Subtract immediate value from A
-    ORImmediate(u8),  // OR immediate value with A
-    XORImmediate(u8), // XOR immediate value with A
-
-    LDSPImmediate16(u16),
-    LDAFromAddress(u16),
-    LDAddressFromA(u16),
-    JP(u16),
-    NOP,
-    HALT,
-    DI,
-    EI,
-
-    JPConditional(Condition, u16),
-    JR(i8),
-    JRConditional(Condition, i8),
-    CALL(u16),
-    CALLConditional(Condition, u16),
-    RET,
-    RETConditional(Condition),
-    RETI,
-    RST(u8),
-    STOP,
-    JRZ(i8),
-    JRNZ(i8),
-
-}
-
-#[derive(Clone, Copy, Debug)]
-enum Condition {
-    NZ,  // Not Zero
-    Z,   // Zero
-    NC,  // Not Carry
-    C,   // Carry
 }
 
 
-#[derive(Clone, Copy, Debug)]
-enum RegisterPair {
-    BC,
-    DE,
-    HL,
-}
-
-
-#[derive(Clone, Copy, Debug)]
-enum ArithmeticTarget {
-    A, B, C, D, E, H, L,
-}
-
-
-
 pub struct CPU {
     registers: Registers,
     pc: u16,
     bus: Rc<RefCell<MemoryBus>>,
     sp: u16,
     ime: bool,
+    halted: bool,
+    locked: bool, // Hit an illegal opcode; only a reset wakes it
+    cycles: u64,  // CPU clocks since power-on, including DMA stalls
     gpu: Rc<RefCell<GPU>>
 }
 
 impl CPU {
     pub fn new(bus: Rc<RefCell<MemoryBus>>, gpu: Rc<RefCell<GPU>>) -> Self {
+        // Registers as the boot ROM leaves them, which is also what
+        // reference traces start from. CGB games look for A = $11 to tell
+        // they are running on a CGB.
+        let registers = if bus.borrow().is_cgb() {
+            Registers {
+                a: 0x11,
+                b: 0x00,
+                c: 0x00,
+                d: 0xFF,
+                e: 0x56,
+                h: 0x00,
+                l: 0x0D,
+                f: FlagRegister::from(0x80),
+            }
+        } else {
+            Registers {
+                a: 0x01,
+                b: 0x00,
+                c: 0x13,
+                d: 0x00,
+                e: 0xD8,
+                h: 0x01,
+                l: 0x4D,
+                f: FlagRegister::from(0xB0),
+            }
+        };
         Self {
-            registers: Registers {
-                a: 0,
-                b: 0,
-                c: 0,
-                d: 0,
-                e: 0,
-                h: 0, 
-                l: 0,
-                f: FlagRegister {
-                    zero: false,
-                    subtract: false,
-                    half_carry: false,
-                    carry: false
-                },
-            },
+            registers,
             pc: 0x0100,
             bus,
-            sp: 0,
+            sp: 0xFFFE,
             ime: true,
+            halted: false,
+            locked: false,
+            cycles: 0,
             gpu
         }
     }
 
+    /// Bytes written by `save_state`.
+    pub const STATE_LEN: usize = 4 * 2 + 2 + 2 + 1 + 1 + 1 + 8;
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_u16(self.registers.get_af());
+        writer.put_u16(self.registers.get_bc());
+        writer.put_u16(self.registers.get_de());
+        writer.put_u16(self.registers.get_hl());
+        writer.put_u16(self.sp);
+        writer.put_u16(self.pc);
+        writer.put_bool(self.ime);
+        writer.put_bool(self.halted);
+        writer.put_bool(self.locked);
+        writer.put_u64(self.cycles);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.registers.set_af(reader.u16());
+        self.registers.set_bc(reader.u16());
+        self.registers.set_de(reader.u16());
+        self.registers.set_hl(reader.u16());
+        self.sp = reader.u16();
+        self.pc = reader.u16();
+        self.ime = reader.bool("IME")?;
+        self.halted = reader.bool("HALT flag")?;
+        self.locked = reader.bool("lock-up flag")?;
+        self.cycles = reader.u64();
+        Ok(())
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    /// CPU clocks elapsed since power-on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn register_dump(&self) -> RegisterDump {
+        RegisterDump {
+            af: self.registers.get_af(),
+            bc: self.registers.get_bc(),
+            de: self.registers.get_de(),
+            hl: self.registers.get_hl(),
+            sp: self.sp,
+            pc: self.pc,
+            ime: self.ime,
+            halted: self.halted,
+            locked: self.locked,
+        }
+    }
+
     fn handle_interrupts(&mut self) {
-        let interrupt_enable = self.bus.borrow_mut().read_byte(0xFFFF);
-        let interrupt_flag = self.bus.borrow_mut().read_byte(0xFF0F);
+        let interrupt_enable = self.bus.borrow().peek_byte(0xFFFF);
+        let interrupt_flag = self.bus.borrow().peek_byte(0xFF0F);
 
         let pending_interrupts = interrupt_enable & interrupt_flag;
         if pending_interrupts == 0 {
@@ -307,14 +236,14 @@ impl CPU {
     }
 
     fn service_interrupt(&mut self, interrupt_bit: u8, vector: u16) {
-        let interrupt_flag = self.bus.borrow_mut().read_byte(0xFF0F);
+        let interrupt_flag = self.bus.borrow().peek_byte(0xFF0F);
         self.bus.borrow_mut().write_byte(0xFF0F, interrupt_flag & !(1 << interrupt_bit));
 
         self.push_stack(self.pc);
 
         self.pc = vector;
 
-        println!("Interrupt handled: bit {} -> vector {:04X}", interrupt_bit, vector);
+        log_trace!("Interrupt handled: bit {} -> vector {:04X}", interrupt_bit, vector);
     }
 
     fn push_stack(&mut self, value: u16) {
@@ -333,854 +262,408 @@ impl CPU {
     }
 
     pub fn step(&mut self) {
+        // A locked-up CPU never runs again, but the rest of the hardware
+        // carries on
+        if self.locked {
+            self.step_hardware(4);
+            return;
+        }
+
+        // A halted CPU idles until an enabled interrupt is requested,
+        // whether or not IME lets it be serviced
+        if self.halted {
+            let pending = {
+                let bus = self.bus.borrow();
+                bus.interrupt_enable & bus.interrupt_flag & 0x1F
+            };
+            if pending == 0 {
+                self.step_hardware(4);
+                return;
+            }
+            self.halted = false;
+        }
+
         // Check if interrupts are enabled, and handle them if so
         if self.ime {
 
             self.handle_interrupts();
         }
-    
-        // Read the opcode at the current program counter (PC)
-        let opcode = self.bus.borrow_mut().read_byte(self.pc);
 
-    
-        // Increment the program counter to point to the next instruction
-        self.pc = self.pc.wrapping_add(1);
-    
-        // Decode the opcode into an instruction
-        let instruction = self.decode_opcode(opcode);
-
-    
-        // Execute the decoded instruction and get the number of cycles taken
-        let cycles = self.execute(instruction);
+        // Decode the instruction at the current program counter (PC).
+        // Fetches aren't data reads, so they don't trip watchpoints.
+        let (instruction, length) = {
+            let bus = self.bus.borrow();
+            decode(|address| bus.peek_byte(address), self.pc)
+        };
 
-    
-        // Step the GPU with the number of cycles used by the instruction
+        // Move PC past the instruction first, so that jumps, calls and
+        // relative offsets all work from the next instruction
+        self.pc = self.pc.wrapping_add(length);
 
-        self.gpu.borrow_mut().step(cycles);
-    
+        let branch_taken = self.execute(instruction);
 
+        // Step the rest of the hardware by the cycles the instruction took
+        self.step_hardware(instruction.cycles(branch_taken));
     }
-    
-    fn decode_opcode(&mut self, opcode: u8) -> Instruction {
-        match opcode {
-            // NOP (No Operation)
-            0x00 => Instruction::NOP,
-
-            // ADD A, r
-            0x80 => Instruction::ADD(ArithmeticTarget::B),
-            0x81 => Instruction::ADD(ArithmeticTarget::C),
-            0x82 => Instruction::ADD(ArithmeticTarget::D),
-            0x83 => Instruction::ADD(ArithmeticTarget::E),
-            0x84 => Instruction::ADD(ArithmeticTarget::H),
-            0x85 => Instruction::ADD(ArithmeticTarget::L),
-            0x87 => Instruction::ADD(ArithmeticTarget::A),
-
-            // ADC A, r
-            0x88 => Instruction::ADC(ArithmeticTarget::B),
-            0x89 => Instruction::ADC(ArithmeticTarget::C),
-            0x8A => Instruction::ADC(ArithmeticTarget::D),
-            0x8B => Instruction::ADC(ArithmeticTarget::E),
-            0x8C => Instruction::ADC(ArithmeticTarget::H),
-            0x8D => Instruction::ADC(ArithmeticTarget::L),
-            0x8F => Instruction::ADC(ArithmeticTarget::A),
-
-            // SUB A, r
-            0x90 => Instruction::SUB(ArithmeticTarget::B),
-            0x91 => Instruction::SUB(ArithmeticTarget::C),
-            0x92 => Instruction::SUB(ArithmeticTarget::D),
-            0x93 => Instruction::SUB(ArithmeticTarget::E),
-            0x94 => Instruction::SUB(ArithmeticTarget::H),
-            0x95 => Instruction::SUB(ArithmeticTarget::L),
-            0x97 => Instruction::SUB(ArithmeticTarget::A),
-
-            // SBC A, r
-            0x98 => Instruction::SBC(ArithmeticTarget::B),
-            0x99 => Instruction::SBC(ArithmeticTarget::C),
-            0x9A => Instruction::SBC(ArithmeticTarget::D),
-            0x9B => Instruction::SBC(ArithmeticTarget::E),
-            0x9C => Instruction::SBC(ArithmeticTarget::H),
-            0x9D => Instruction::SBC(ArithmeticTarget::L),
-            0x9F => Instruction::SBC(ArithmeticTarget::A),
-
-            // AND A, r
-            0xA0 => Instruction::AND(ArithmeticTarget::B),
-            0xA1 => Instruction::AND(ArithmeticTarget::C),
-            0xA2 => Instruction::AND(ArithmeticTarget::D),
-            0xA3 => Instruction::AND(ArithmeticTarget::E),
-            0xA4 => Instruction::AND(ArithmeticTarget::H),
-            0xA5 => Instruction::AND(ArithmeticTarget::L),
-            0xA7 => Instruction::AND(ArithmeticTarget::A),
-
-            // OR A, r
-            0xB0 => Instruction::OR(ArithmeticTarget::B),
-            0xB1 => Instruction::OR(ArithmeticTarget::C),
-            0xB2 => Instruction::OR(ArithmeticTarget::D),
-            0xB3 => Instruction::OR(ArithmeticTarget::E),
-            0xB4 => Instruction::OR(ArithmeticTarget::H),
-            0xB5 => Instruction::OR(ArithmeticTarget::L),
-            0xB7 => Instruction::OR(ArithmeticTarget::A),
-
-            // XOR A, r
-            0xA8 => Instruction::XOR(ArithmeticTarget::B),
-            0xA9 => Instruction::XOR(ArithmeticTarget::C),
-            0xAA => Instruction::XOR(ArithmeticTarget::D),
-            0xAB => Instruction::XOR(ArithmeticTarget::E),
-            0xAC => Instruction::XOR(ArithmeticTarget::H),
-            0xAD => Instruction::XOR(ArithmeticTarget::L),
-            0xAF => Instruction::XOR(ArithmeticTarget::A),
-
-            // CP A, r
-            0xB8 => Instruction::CP(ArithmeticTarget::B),
-            0xB9 => Instruction::CP(ArithmeticTarget::C),
-            0xBA => Instruction::CP(ArithmeticTarget::D),
-            0xBB => Instruction::CP(ArithmeticTarget::E),
-            0xBC => Instruction::CP(ArithmeticTarget::H),
-            0xBD => Instruction::CP(ArithmeticTarget::L),
-            0xBF => Instruction::CP(ArithmeticTarget::A),
-
-            // INC r
-            0x04 => Instruction::INC(ArithmeticTarget::B),
-            0x0C => Instruction::INC(ArithmeticTarget::C),
-            0x14 => Instruction::INC(ArithmeticTarget::D),
-            0x1C => Instruction::INC(ArithmeticTarget::E),
-            0x24 => Instruction::INC(ArithmeticTarget::H),
-            0x2C => Instruction::INC(ArithmeticTarget::L),
-            0x3C => Instruction::INC(ArithmeticTarget::A),
-
-            // DEC r
-            0x05 => Instruction::DEC(ArithmeticTarget::B),
-            0x0D => Instruction::DEC(ArithmeticTarget::C),
-            0x15 => Instruction::DEC(ArithmeticTarget::D),
-            0x1D => Instruction::DEC(ArithmeticTarget::E),
-            0x25 => Instruction::DEC(ArithmeticTarget::H),
-            0x2D => Instruction::DEC(ArithmeticTarget::L),
-            0x3D => Instruction::DEC(ArithmeticTarget::A),
-
-            // CPL (Complement A)
-            0x2F => Instruction::CPL,
-
-            // CCF (Complement Carry Flag)
-            0x3F => Instruction::CCF,
-
-            // SCF (Set Carry Flag)
-            0x37 => Instruction::SCF,
-
-            // Rotate A instructions
-            0x07 => Instruction::RLCA,
-            0x0F => Instruction::RRCA,
-            0x17 => Instruction::RLA,
-            0x1F => Instruction::RRA,
-
-            0x3E => {
-                let value = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::LDImmediate8(ArithmeticTarget::A, value)
-            }
-            0xE0 => {
-                let offset = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::LDIOOffsetFromA(offset)
-            }
-
-            0x0E => {
-                let value = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::LDImmediate8(ArithmeticTarget::C, value)
-            }
-    
-    
-            0x01 => {
-                let value = self.fetch_word();
-                Instruction::LDImmediate16(RegisterPair::BC, value)
-            }
-            
-            0x06 => {
-                let value = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::LDImmediate8(ArithmeticTarget::B, value)
-            }
-    
-            0xC6 => {
-                let value = self.fetch_byte();
-                Instruction::ADDImmediate(value)
-            }
-    
-            0xFA => {
-                let address = self.fetch_word();
-                Instruction::LDAFromAddress(address)
-            }
-    
-            0xEA => {
-                let low = self.bus.borrow_mut().read_byte(self.pc);
-                let high = self.bus.borrow_mut().read_byte(self.pc + 1);
-                let address = ((high as u16) << 8) | (low as u16);
-                self.pc = self.pc.wrapping_add(2);
-
-                Instruction::LDAddressFromA(address)
-            }
-            0x7C => Instruction::LD(ArithmeticTarget::A, ArithmeticTarget::H),
-
-            0xF3 => {
-
-                Instruction::DI
-            }
-    
-            0xC3 => {
-                let low = self.bus.borrow_mut().read_byte(self.pc);
-                let high = self.bus.borrow_mut().read_byte(self.pc + 1);
-                let address = ((high as u16) << 8) | (low as u16);
-
-                self.pc = self.pc.wrapping_add(2); 
-                Instruction::JP(address)
-            }
 
-            0x21 => {
-                let low = self.bus.borrow_mut().read_byte(self.pc);
-                let high = self.bus.borrow_mut().read_byte(self.pc + 1);
-                let value = ((high as u16) << 8) | (low as u16);
-
-                self.pc = self.pc.wrapping_add(2);
-                Instruction::LDImmediate16(RegisterPair::HL, value)
-            }
-    
-            
-            0xF0 => {
-                let offset = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::LDIOOffsetToA(offset)
-            }
-
-            0xE6 => {
-                let value = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::ANDImmediate(value)
-            }
-
-            0xFE => {
-                let value = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::CPImmediate(value)
-            }
-    
-    
-            0x06 => {
-                let value = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                Instruction::LDImmediate8(ArithmeticTarget::B, value)
-            }
-            0x0E => {
-                let value = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                Instruction::LDImmediate8(ArithmeticTarget::C, value)
-            }
-            0x21 => {
-                let low = self.bus.borrow_mut().read_byte(self.pc);
-                let high = self.bus.borrow_mut().read_byte(self.pc + 1);
-                let value = ((high as u16) << 8) | low as u16;
-                self.pc = self.pc.wrapping_add(2);
-                Instruction::LDImmediate16(RegisterPair::HL, value)
-            }
-
-            0xC3 => {
-                let low = self.bus.borrow_mut().read_byte(self.pc);
-                let high = self.bus.borrow_mut().read_byte(self.pc + 1);
-                let address = ((high as u16) << 8) | low as u16;
-                self.pc = self.pc.wrapping_add(2);
-                Instruction::JP(address)
-            }
-            0xC9 => Instruction::RET,
-            0xCD => {
-                let low = self.bus.borrow_mut().read_byte(self.pc);
-                let high = self.bus.borrow_mut().read_byte(self.pc + 1);
-                let address = ((high as u16) << 8) | low as u16;
-                self.pc = self.pc.wrapping_add(2);
-                Instruction::CALL(address)
-            }
-            0xE0 => {
-                let offset = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                Instruction::LDIOOffsetFromA(offset)
-            }
-            0xE6 => {
-                let value = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                Instruction::ANDImmediate(value)
-            }
-            0xF0 => {
-                let offset = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                Instruction::LDIOOffsetToA(offset)
-            }
-            0xF3 => Instruction::DI,
-            0xFB => Instruction::EI,
-            0xFE => {
-                let value = self.bus.borrow_mut().read_byte(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                Instruction::CPImmediate(value)
-            }
-            0x32 => Instruction::LDHLADecrement,
-
-            // Halt (stop CPU until an interrupt occurs)
-            0x76 => Instruction::HALT,
-            0x31 => {
-                // Read the next two bytes to form a 16-bit value
-                let low = self.bus.borrow_mut().read_byte(self.pc);
-                let high = self.bus.borrow_mut().read_byte(self.pc + 1);
-                let value = ((high as u16) << 8) | (low as u16);
-                self.pc = self.pc.wrapping_add(2); // Advance the program counter by 2 bytes
-
-                Instruction::LDSPImmediate16(value)
-            }
-            0x7D => Instruction::LD(ArithmeticTarget::A, ArithmeticTarget::L),
-            0x18 => {
-                // Read the signed 8-bit offset
-                let offset = self.bus.borrow_mut().read_byte(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::JR(offset)
-            }
-            0xE5 => Instruction::PUSH(RegisterPair::HL),
-            0xE1 => Instruction::POP(RegisterPair::HL),
-            0xF5 => Instruction::PUSHAF, // Handle AF separately
-            0x23 => Instruction::INCHL,
-            0x2A => Instruction::LDAFromHLIncrement,
-            0xF1 => Instruction::POPAF,
-            0xC5 => Instruction::PUSH(RegisterPair::BC),
-            0x03 => Instruction::INCBC,
-            0x78 => Instruction::LD(ArithmeticTarget::A, ArithmeticTarget::B),
-            0x28 => {
-                // Read the signed 8-bit offset
-                let offset = self.bus.borrow_mut().read_byte(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::JRZ(offset)
-            }
-            0x20 => {
-                // Read the signed 8-bit offset
-                let offset = self.bus.borrow_mut().read_byte(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-
-                Instruction::JRNZ(offset)
-            }
-    
-    
-            // Extended opcodes (0xCB prefix)
-            0xCB => self.decode_extended_opcode(),
-
-            _ => panic!("Unknown opcode: 0x{:02X}", opcode),
+    /// Advances the serial port, the timer and the PPU by `cycles` CPU
+    /// clocks. The serial port and timer follow the CPU's speed, but in
+    /// double-speed mode the PPU keeps its pace, so it sees half as many.
+    ///
+    /// Also runs HBlank DMA, and lets the PPU catch up while DMA holds the
+    /// CPU, in small steps so no mode change is skipped.
+    fn step_hardware(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        {
+            let mut bus = self.bus.borrow_mut();
+            bus.step_serial(cycles);
+            bus.step_timer(cycles);
+        }
+        let cycles = if self.bus.borrow().double_speed() { cycles / 2 } else { cycles };
+        let hblank_started = {
+            let mut gpu = self.gpu.borrow_mut();
+            gpu.step(cycles);
+            gpu.take_hblank_started()
+        };
+        if hblank_started {
+            self.bus.borrow_mut().hblank_dma();
         }
-    }
-
-    fn fetch_byte(&mut self) -> u8 {
-        let value = self.bus.borrow_mut().read_byte(self.pc);
-        self.pc = self.pc.wrapping_add(1);
-        value
-    }
-
-    fn fetch_word(&mut self) -> u16 {
-        let low = self.fetch_byte() as u16;
-        let high = self.fetch_byte() as u16;
-        (high << 8) | low
-    }
-
 
-    fn decode_extended_opcode(&mut self) -> Instruction {
-        let opcode = self.bus.borrow_mut().read_byte(self.pc);
-        self.pc = self.pc.wrapping_add(1);
-        match opcode {
-            0x11 => Instruction::RL(ArithmeticTarget::C),
-            0x12 => Instruction::RL(ArithmeticTarget::D),
-            0x13 => Instruction::RL(ArithmeticTarget::E),
-            0x14 => Instruction::RL(ArithmeticTarget::H),
-            0x15 => Instruction::RL(ArithmeticTarget::L),
-            _ => panic!("Unknown extended opcode: 0xCB{:02X}", opcode),
+        let stall = self.bus.borrow_mut().take_dma_stall();
+        for _ in 0..stall / 4 {
+            self.step_hardware(4);
         }
     }
 
-    fn execute(&mut self, instruction: Instruction) -> u32 {
+    /// Runs a decoded instruction. Returns true if it was a conditional
+    /// jump, call or return whose condition held.
+    fn execute(&mut self, instruction: Instruction) -> bool {
         match instruction {
-            Instruction::ADD(target) => { self.add(target); 4 },
-            Instruction::ADDHL => { self.add_hl(); 8 },
-            Instruction::ADC(target) => { self.adc(target); 4 },
-            Instruction::SUB(target) => { self.sub(target); 4 },
-            Instruction::SBC(target) => { self.sbc(target); 4 },
-            Instruction::AND(target) => { self.and(target); 4 },
-            Instruction::OR(target) => { self.or(target); 4 },
-            Instruction::XOR(target) => { self.xor(target); 4 },
-            Instruction::CP(target) => { self.cp(target); 4 },
-            Instruction::INC(target) => { self.inc(target); 4 },
-            Instruction::DEC(target) => { self.dec(target); 4 },
-            Instruction::CCF => { self.ccf(); 4 },
-            Instruction::SCF => { self.scf(); 4 },
-            Instruction::RRA => { self.rra(); 4 },
-            Instruction::RLA => { self.rla(); 4 },
-            Instruction::RRCA => { self.rrca(); 4 },
-            Instruction::RLCA => { self.rlca(); 4 },
-            Instruction::CPL => { self.cpl(); 4 },
-            Instruction::BIT(bit, target) => { self.bit(bit, target); 8 },
-            Instruction::RESET(bit, target) => { self.reset_bit(bit, target); 8 },
-            Instruction::SET(bit, target) => { self.set_bit(bit, target); 8 },
-            Instruction::SRL(target) => { self.srl(target); 8 },
-            Instruction::RR(target) => { self.rr(target); 8 },
-            Instruction::RL(target) => { self.rl(target); 8 },
-            Instruction::RRC(target) => { self.rrc(target); 8 },
-            Instruction::RLC(target) => { self.rlc(target); 8 },
-            Instruction::SRA(target) => { self.sra(target); 8 },
-            Instruction::SLA(target) => { self.sla(target); 8 },
-            Instruction::SWAP(target) => { self.swap(target); 8 },
-            Instruction::LDImmediate8(target, value) => { self.set_register_value(target, value); 8 },
-            Instruction::LDImmediate16(pair, value) => { self.set_register_pair(pair, value); 12 },
-            Instruction::ADDImmediate(value) => {
-                let (result, carry) = self.registers.a.overflowing_add(value);
-                self.update_flags(result, false, carry, (self.registers.a & 0xF) + (value & 0xF) > 0xF);
-                self.registers.a = result;
-                8
+            Instruction::ADD(target) => { let value = self.get_register_value(target); self.add(value) },
+            Instruction::ADC(target) => { let value = self.get_register_value(target); self.adc(value) },
+            Instruction::SUB(target) => { let value = self.get_register_value(target); self.sub(value) },
+            Instruction::SBC(target) => { let value = self.get_register_value(target); self.sbc(value) },
+            Instruction::AND(target) => { let value = self.get_register_value(target); self.and(value) },
+            Instruction::OR(target) => { let value = self.get_register_value(target); self.or(value) },
+            Instruction::XOR(target) => { let value = self.get_register_value(target); self.xor(value) },
+            Instruction::CP(target) => { let value = self.get_register_value(target); self.cp(value) },
+            Instruction::ADDImmediate(value) => self.add(value),
+            Instruction::ADCImmediate(value) => self.adc(value),
+            Instruction::SUBImmediate(value) => self.sub(value),
+            Instruction::SBCImmediate(value) => self.sbc(value),
+            Instruction::ANDImmediate(value) => self.and(value),
+            Instruction::ORImmediate(value) => self.or(value),
+            Instruction::XORImmediate(value) => self.xor(value),
+            Instruction::CPImmediate(value) => self.cp(value),
+            Instruction::INC(target) => self.inc(target),
+            Instruction::DEC(target) => self.dec(target),
+            Instruction::INC16(pair) => {
+                let value = self.get_register_pair(pair).wrapping_add(1);
+                self.set_register_pair(pair, value);
+            }
+            Instruction::DEC16(pair) => {
+                let value = self.get_register_pair(pair).wrapping_sub(1);
+                self.set_register_pair(pair, value);
+            }
+            Instruction::ADDHL(pair) => self.add_hl(pair),
+            Instruction::ADDSP(offset) => self.sp = self.sp_plus_offset(offset),
+            Instruction::DAA => self.daa(),
+            Instruction::CPL => self.cpl(),
+            Instruction::CCF => self.ccf(),
+            Instruction::SCF => self.scf(),
+            Instruction::RLCA => self.rlca(),
+            Instruction::RRCA => self.rrca(),
+            Instruction::RLA => self.rla(),
+            Instruction::RRA => self.rra(),
+
+            Instruction::RLC(target) => self.rlc(target),
+            Instruction::RRC(target) => self.rrc(target),
+            Instruction::RL(target) => self.rl(target),
+            Instruction::RR(target) => self.rr(target),
+            Instruction::SLA(target) => self.sla(target),
+            Instruction::SRA(target) => self.sra(target),
+            Instruction::SWAP(target) => self.swap(target),
+            Instruction::SRL(target) => self.srl(target),
+            Instruction::BIT(bit, target) => self.bit(bit, target),
+            Instruction::RESET(bit, target) => self.reset_bit(bit, target),
+            Instruction::SET(bit, target) => self.set_bit(bit, target),
+
+            Instruction::LD(destination, source) => {
+                let value = self.get_register_value(source);
+                self.set_register_value(destination, value);
+            }
+            Instruction::LDImmediate8(target, value) => self.set_register_value(target, value),
+            Instruction::LDImmediate16(pair, value) => self.set_register_pair(pair, value),
+            Instruction::LDIndirectFromA(indirect) => {
+                let address = self.indirect_address(indirect);
+                self.bus.borrow_mut().write_byte(address, self.registers.a);
             }
-            Instruction::LDAFromAddress(address) => {
-                let value = self.bus.borrow_mut().read_byte(address);
-                self.registers.a = value;
-                16
+            Instruction::LDAFromIndirect(indirect) => {
+                let address = self.indirect_address(indirect);
+                self.registers.a = self.bus.borrow().read_byte(address);
             }
             Instruction::LDAddressFromA(address) => {
                 self.bus.borrow_mut().write_byte(address, self.registers.a);
-                16
             }
-
-            Instruction::LDIOOffsetFromA(offset) => {
-                let address = 0xFF00 + (offset as u16);
-
-                self.bus.borrow_mut().write_byte(address, self.registers.a);
-                12 // LD (0xFF00 + n), A takes 12 cycles
-            }    
-            Instruction::LDIOOffsetToA(offset) => {
-                let address = 0xFF00 + (offset as u16);
-
-                self.registers.a = self.bus.borrow_mut().read_byte(address);
-
-                12 // LD A, (0xFF00 + n) takes 12 cycles
-            }    
-    
-            Instruction::HALT => {
-                println!("CPU HALT");
-                loop {
-                    std::thread::sleep(std::time::Duration::from_millis(10));
-                }
-            }
-            Instruction::LD(ArithmeticTarget::A, ArithmeticTarget::L) => {
-
-                self.registers.a = self.registers.l;
-                4 // LD A, L takes 4 cycles
-            }
-            Instruction::LD(ArithmeticTarget::A, ArithmeticTarget::B) => {
-
-    
-                // Copy the value from register B to register A
-                self.registers.a = self.registers.b;
-
-    
-                4 // LD A, B takes 4 cycles
-            }
-    
-    
-            Instruction::ANDImmediate(value) => {
-
-                
-                // Perform the AND operation
-                self.registers.a &= value;
-                
-                // Update flags
-                self.registers.f.zero = self.registers.a == 0;
-                self.registers.f.subtract = false;
-                self.registers.f.half_carry = true;
-                self.registers.f.carry = false;
-    
-                console_log!("Result of AND: {:#04X}, Flags - Z:{} N:{} H:{} C:{}",
-                    self.registers.a,
-                    self.registers.f.zero,
-                    self.registers.f.subtract,
-                    self.registers.f.half_carry,
-                    self.registers.f.carry,
-                );
-    
-                8 // AND n takes 8 cycles
-            }
-
-            Instruction::CPImmediate(value) => {
-
-    
-                // Perform the comparison (A - value) and set flags
-                let result = self.registers.a.wrapping_sub(value);
-    
-                // Set flags based on the result
-                self.registers.f.zero = self.registers.a == value;        // Set if A == n
-                self.registers.f.subtract = true;                         // Always set for CP
-                self.registers.f.half_carry = (self.registers.a & 0x0F) < (value & 0x0F); // Borrow from bit 4
-                self.registers.f.carry = self.registers.a < value;        // Borrow from bit 7
-    
-                console_log!(
-                    "Result of CP: {:#04X}, Flags - Z:{} N:{} H:{} C:{}",
-                    result,
-                    self.registers.f.zero,
-                    self.registers.f.subtract,
-                    self.registers.f.half_carry,
-                    self.registers.f.carry,
-                );
-    
-                8 // CP n takes 8 cycles
-            }
-
-
-            Instruction::LDImmediate16(RegisterPair::HL, value) => {
-
-                self.registers.set_hl(value);
-                12 // LD HL, nn takes 12 cycles
-            }
-
-            Instruction::LDImmediate8(ArithmeticTarget::C, value) => {
-
-                self.registers.c = value;
-                8 // LD C, n takes 8 cycles
-            }
-            Instruction::JP(address) => {
-                self.pc = address;
-                16
-            }
-            Instruction::RET => {
-                let low = self.pop_stack();
-                let high = self.pop_stack();
-                self.pc = ((high as u16) << 8) | low as u16;
-                16
-            }
-            Instruction::CALL(address) => {
-                let pc_high = (self.pc >> 8) as u8;
-                let pc_low = self.pc as u8;
-                self.push_stack(pc_high as u16);
-                self.push_stack(pc_low as u16);
-                self.pc = address;
-                24
+            Instruction::LDAFromAddress(address) => {
+                self.registers.a = self.bus.borrow().read_byte(address);
             }
             Instruction::LDIOOffsetFromA(offset) => {
-                let address = 0xFF00 + offset as u16;
-                self.bus.borrow_mut().write_byte(address, self.registers.a);
-                12
+                self.bus.borrow_mut().write_byte(0xFF00 | offset as u16, self.registers.a);
             }
             Instruction::LDIOOffsetToA(offset) => {
-                let address = 0xFF00 + offset as u16;
-                self.registers.a = self.bus.borrow_mut().read_byte(address);
-                12
-            }
-            Instruction::CPImmediate(value) => {
-                let result = self.registers.a.wrapping_sub(value);
-                self.registers.f.zero = self.registers.a == value;
-                self.registers.f.subtract = true;
-                self.registers.f.half_carry = (self.registers.a & 0x0F) < (value & 0x0F);
-                self.registers.f.carry = self.registers.a < value;
-                8
+                self.registers.a = self.bus.borrow().read_byte(0xFF00 | offset as u16);
             }
-            Instruction::DI => {
-                self.ime = false;
-                4
+            Instruction::LDIOCFromA => {
+                self.bus.borrow_mut().write_byte(0xFF00 | self.registers.c as u16, self.registers.a);
             }
-            Instruction::EI => {
-                self.ime = true;
-                4
+            Instruction::LDIOCToA => {
+                self.registers.a = self.bus.borrow().read_byte(0xFF00 | self.registers.c as u16);
             }
-    
-            Instruction::EI => { self.ime = true; 4 },
-            Instruction::DI => { self.ime = false; 4 },
-            Instruction::NOP => { 4 },
-            Instruction::JP(addr) => {
-                self.pc = addr;
-                16
+            Instruction::LDFromSP(address) => {
+                let mut bus = self.bus.borrow_mut();
+                bus.write_byte(address, self.sp as u8);
+                bus.write_byte(address.wrapping_add(1), (self.sp >> 8) as u8);
             }
-            Instruction::LDHLADecrement => {
-                // Store the value in register A to the address pointed by HL
-                let address = self.registers.get_hl();
-
-    
-                self.bus.borrow_mut().write_byte(address, self.registers.a);
-    
-                // Decrement HL after the write
-                let new_hl = address.wrapping_sub(1);
-                self.registers.set_hl(new_hl);
-
-    
-                8 // LD (HL-), A takes 8 cycles
+            Instruction::LDSPFromHL => self.sp = self.registers.get_hl(),
+            Instruction::LDHLFromSP(offset) => {
+                let value = self.sp_plus_offset(offset);
+                self.registers.set_hl(value);
             }
-            Instruction::LDSPImmediate16(value) => {
-
-                self.sp = value;
-                12 // LD SP, nn takes 12 cycles
+            Instruction::PUSH(pair) => {
+                let value = self.get_register_pair(pair);
+                self.push_stack(value);
             }
-            Instruction::LD(ArithmeticTarget::A, ArithmeticTarget::H) => {
-
-                self.registers.a = self.registers.h;
-                4 // LD A, H takes 4 cycles
+            Instruction::POP(pair) => {
+                let value = self.pop_stack();
+                self.set_register_pair(pair, value);
             }
-            Instruction::JR(offset) => {
 
-                
-                // Calculate the new program counter value with the offset
-                self.pc = self.pc.wrapping_add(offset as u16);
-    
-                12 // JR n takes 12 cycles
+            Instruction::JP(address) => self.pc = address,
+            Instruction::JPConditional(condition, address) => {
+                return self.jump_if(condition, address);
             }
-    
-            Instruction::PUSH(RegisterPair::HL) => {
-
-    
-                // Get the high and low bytes of HL
-                let hl = self.registers.get_hl();
-                let high_byte = (hl >> 8) as u8;
-                let low_byte = (hl & 0xFF) as u8;
-    
-                // Push the high byte onto the stack
-                self.sp = self.sp.wrapping_sub(1);
-                self.bus.borrow_mut().write_byte(self.sp, high_byte);
-
-    
-                // Push the low byte onto the stack
-                self.sp = self.sp.wrapping_sub(1);
-                self.bus.borrow_mut().write_byte(self.sp, low_byte);
-
-    
-                16 // PUSH nn takes 16 cycles
+            Instruction::JPHL => self.pc = self.registers.get_hl(),
+            // Offsets are relative to the next instruction; `as u16`
+            // sign-extends, so the wrapping add handles backward jumps
+            Instruction::JR(offset) => self.pc = self.pc.wrapping_add(offset as u16),
+            Instruction::JRConditional(condition, offset) => {
+                return self.jump_if(condition, self.pc.wrapping_add(offset as u16));
             }
-            Instruction::POP(RegisterPair::HL) => {
-
-    
-                // Read the low byte from the stack into register L
-                let low_byte = self.bus.borrow_mut().read_byte(self.sp);
-                self.sp = self.sp.wrapping_add(1);
-                self.registers.l = low_byte;
-
-    
-                // Read the high byte from the stack into register H
-                let high_byte = self.bus.borrow_mut().read_byte(self.sp);
-                self.sp = self.sp.wrapping_add(1);
-                self.registers.h = high_byte;
-
-    
-                12 // POP nn takes 12 cycles
+            Instruction::CALL(address) => self.call(address),
+            Instruction::CALLConditional(condition, address) => {
+                if !self.condition_met(condition) {
+                    return false;
+                }
+                self.call(address);
+                return true;
             }
-    
-            Instruction::PUSHAF => {
-
-    
-                // Get the high and low bytes of the AF register pair
-                let high_byte = self.registers.a;
-                let low_byte = u8::from(self.registers.f);
-    
-                // Push the high byte (A) onto the stack
-                self.sp = self.sp.wrapping_sub(1);
-                self.bus.borrow_mut().write_byte(self.sp, high_byte);
-
-    
-                // Push the low byte (F) onto the stack
-                self.sp = self.sp.wrapping_sub(1);
-                self.bus.borrow_mut().write_byte(self.sp, low_byte);
-
-    
-                16 // PUSH AF takes 16 cycles
+            Instruction::RET => self.pc = self.pop_stack(),
+            Instruction::RETConditional(condition) => {
+                if !self.condition_met(condition) {
+                    return false;
+                }
+                self.pc = self.pop_stack();
+                return true;
             }
-            Instruction::INCHL => {
-
-                
-                // Increment the HL register pair
-                let hl = self.registers.get_hl();
-                let new_hl = hl.wrapping_add(1);
-                self.registers.set_hl(new_hl);
-                
-
-    
-                8 // INC HL takes 8 cycles
+            Instruction::RETI => {
+                self.pc = self.pop_stack();
+                self.ime = true;
             }
-            Instruction::LDAFromHLIncrement => {
-
-    
-                // Get the address from the HL register
-                let address = self.registers.get_hl();
-    
-                // Load the value from memory into register A
-                let value = self.bus.borrow_mut().read_byte(address);
-                self.registers.a = value;
-
-    
-                // Increment the HL register by 1
-                let new_hl = address.wrapping_add(1);
-                self.registers.set_hl(new_hl);
+            Instruction::RST(vector) => self.call(vector as u16),
 
-    
-                8 // LD A, (HL+) takes 8 cycles
+            Instruction::NOP => {}
+            Instruction::HALT => self.halted = true,
+            Instruction::STOP => {
+                // Only the CGB speed switch is emulated; otherwise STOP
+                // behaves as a NOP
+                if self.bus.borrow_mut().switch_speed() {
+                    log_trace!("Switched CPU speed");
+                }
             }
-            Instruction::POPAF => {
-
-    
-                // Read the low byte from the stack into the F register
-                let low_byte = self.bus.borrow_mut().read_byte(self.sp);
-                self.sp = self.sp.wrapping_add(1);
-                self.registers.f = FlagRegister::from(low_byte);
-
-    
-                // Read the high byte from the stack into the A register
-                let high_byte = self.bus.borrow_mut().read_byte(self.sp);
-                self.sp = self.sp.wrapping_add(1);
-                self.registers.a = high_byte;
-
-    
-                12 // POP AF takes 12 cycles
+            Instruction::DI => self.ime = false,
+            Instruction::EI => self.ime = true,
+            // The hardware locks up on these. PC stays on the opcode so the
+            // debugger shows where it happened.
+            Instruction::Illegal(opcode) => {
+                log_warn!("Illegal opcode 0x{:02X} at {:04X}, CPU locked up", opcode, self.pc.wrapping_sub(1));
+                self.pc = self.pc.wrapping_sub(1);
+                self.locked = true;
             }
-            Instruction::PUSH(RegisterPair::BC) => {
-
-    
-                // Get the high and low bytes of the BC register pair
-                let high_byte = self.registers.b;
-                let low_byte = self.registers.c;
-    
-                // Push the high byte onto the stack
-                self.sp = self.sp.wrapping_sub(1);
-                self.bus.borrow_mut().write_byte(self.sp, high_byte);
+        }
+        false
+    }
 
-    
-                // Push the low byte onto the stack
-                self.sp = self.sp.wrapping_sub(1);
-                self.bus.borrow_mut().write_byte(self.sp, low_byte);
+    fn condition_met(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::NZ => !self.registers.f.zero,
+            Condition::Z => self.registers.f.zero,
+            Condition::NC => !self.registers.f.carry,
+            Condition::C => self.registers.f.carry,
+        }
+    }
 
-    
-                16 // PUSH nn takes 16 cycles
-            }
-            Instruction::INCBC => {
+    fn jump_if(&mut self, condition: Condition, address: u16) -> bool {
+        let taken = self.condition_met(condition);
+        if taken {
+            self.pc = address;
+        }
+        taken
+    }
 
-    
-                // Increment the BC register pair
-                let bc = self.registers.get_bc();
-                let new_bc = bc.wrapping_add(1);
-                self.registers.set_bc(new_bc);
-    
+    fn call(&mut self, address: u16) {
+        self.push_stack(self.pc);
+        self.pc = address;
+    }
 
-    
-                8 // INC BC takes 8 cycles
+    fn indirect_address(&mut self, indirect: Indirect) -> u16 {
+        match indirect {
+            Indirect::BC => self.registers.get_bc(),
+            Indirect::DE => self.registers.get_de(),
+            Indirect::HLIncrement => {
+                let hl = self.registers.get_hl();
+                self.registers.set_hl(hl.wrapping_add(1));
+                hl
             }
-            Instruction::JRZ(offset) => {
-
-    
-                if self.registers.f.zero {
-
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    12 // If the jump is taken, it takes 12 cycles
-                } else {
-
-                    8 // If the jump is not taken, it takes 8 cycles
-                }
+            Indirect::HLDecrement => {
+                let hl = self.registers.get_hl();
+                self.registers.set_hl(hl.wrapping_sub(1));
+                hl
             }
-            Instruction::JRNZ(offset) => {
-
-    
-                if !self.registers.f.zero {
-
-                    self.pc = self.pc.wrapping_add(offset as u16);
-                    12 // If the jump is taken, it takes 12 cycles
-                } else {
+        }
+    }
 
-                    8 // If the jump is not taken, it takes 8 cycles
-                }
-            }
-    
-    
-            _ => { 4 },
+    fn get_register_pair(&self, pair: RegisterPair) -> u16 {
+        match pair {
+            RegisterPair::BC => self.registers.get_bc(),
+            RegisterPair::DE => self.registers.get_de(),
+            RegisterPair::HL => self.registers.get_hl(),
+            RegisterPair::SP => self.sp,
+            RegisterPair::AF => self.registers.get_af(),
         }
     }
-    
+
     fn set_register_pair(&mut self, pair: RegisterPair, value: u16) {
         match pair {
             RegisterPair::BC => self.registers.set_bc(value),
             RegisterPair::DE => self.registers.set_de(value),
             RegisterPair::HL => self.registers.set_hl(value),
+            RegisterPair::SP => self.sp = value,
+            RegisterPair::AF => self.registers.set_af(value),
         }
     }
 
-    // ADD A, register
-    fn add(&mut self, target: ArithmeticTarget) {
-        let value = self.get_register_value(target);
+    // ADD A, value
+    fn add(&mut self, value: u8) {
         let (result, carry) = self.registers.a.overflowing_add(value);
         self.update_flags(result, false, carry, (self.registers.a & 0xF) + (value & 0xF) > 0xF);
         self.registers.a = result;
     }
 
-    fn add_hl(&mut self) {
+    fn add_hl(&mut self, pair: RegisterPair) {
         let hl = self.registers.get_hl();
-        let bc = self.registers.get_bc();
-        let (result, carry) = hl.overflowing_add(bc);
+        let value = self.get_register_pair(pair);
+        let (result, carry) = hl.overflowing_add(value);
         self.registers.set_hl(result);
-        self.update_flags(0, false, carry, ((hl & 0xFFF) + (bc & 0xFFF)) > 0xFFF);
+        // Z is left alone
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = (hl & 0xFFF) + (value & 0xFFF) > 0xFFF;
+        self.registers.f.carry = carry;
     }
 
-    fn adc(&mut self, target: ArithmeticTarget) {
-        let value = self.get_register_value(target);
-        let carry = if self.registers.f.carry { 1 } else { 0 };
-        let (result, carry_out) = self.registers.a.overflowing_add(value + carry);
+    /// SP plus a signed offset, as used by `ADD SP, e` and `LD HL, SP+e`.
+    /// Both set H and C from the unsigned add of the low byte.
+    fn sp_plus_offset(&mut self, offset: i8) -> u16 {
+        let value = offset as u16;
+        self.registers.f.zero = false;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = (self.sp & 0xF) + (value & 0xF) > 0xF;
+        self.registers.f.carry = (self.sp & 0xFF) + (value & 0xFF) > 0xFF;
+        self.sp.wrapping_add(value)
+    }
+
+    fn adc(&mut self, value: u8) {
+        let carry = self.registers.f.carry as u8;
+        let result = self.registers.a.wrapping_add(value).wrapping_add(carry);
+        let carry_out = self.registers.a as u16 + value as u16 + carry as u16 > 0xFF;
         self.update_flags(result, false, carry_out, ((self.registers.a & 0xF) + (value & 0xF) + carry) > 0xF);
         self.registers.a = result;
     }
 
-    fn sub(&mut self, target: ArithmeticTarget) {
-        let value = self.get_register_value(target);
+    fn sub(&mut self, value: u8) {
         let (result, carry) = self.registers.a.overflowing_sub(value);
         self.update_flags(result, true, carry, (self.registers.a & 0xF) < (value & 0xF));
         self.registers.a = result;
     }
 
-    fn sbc(&mut self, target: ArithmeticTarget) {
-        let value = self.get_register_value(target);
-        let carry = if self.registers.f.carry { 1 } else { 0 };
-        let (result, carry_out) = self.registers.a.overflowing_sub(value + carry);
+    fn sbc(&mut self, value: u8) {
+        let carry = self.registers.f.carry as u8;
+        let result = self.registers.a.wrapping_sub(value).wrapping_sub(carry);
+        let carry_out = (self.registers.a as u16) < value as u16 + carry as u16;
         self.update_flags(result, true, carry_out, (self.registers.a & 0xF) < (value & 0xF) + carry);
         self.registers.a = result;
     }
 
-    fn and(&mut self, target: ArithmeticTarget) {
-        let value = self.get_register_value(target);
+    fn and(&mut self, value: u8) {
         self.registers.a &= value;
         self.update_flags(self.registers.a, false, false, true);
     }
 
-    fn or(&mut self, target: ArithmeticTarget) {
-        let value = self.get_register_value(target);
+    fn or(&mut self, value: u8) {
         self.registers.a |= value;
         self.update_flags(self.registers.a, false, false, false);
     }
 
-    fn xor(&mut self, target: ArithmeticTarget) {
-        let value = self.get_register_value(target);
+    fn xor(&mut self, value: u8) {
         self.registers.a ^= value;
         self.update_flags(self.registers.a, false, false, false);
     }
 
-    fn cp(&mut self, target: ArithmeticTarget) {
-        let value = self.get_register_value(target);
+    fn cp(&mut self, value: u8) {
         let (result, carry) = self.registers.a.overflowing_sub(value);
         self.update_flags(result, true, carry, (self.registers.a & 0xF) < (value & 0xF));
     }
 
+    // INC and DEC leave the carry flag alone
     fn inc(&mut self, target: ArithmeticTarget) {
         let value = self.get_register_value(target);
         let result = value.wrapping_add(1);
         self.set_register_value(target, result);
-        self.update_flags(result, false, false, (value & 0xF) + 1 > 0xF);
+        self.update_flags(result, false, self.registers.f.carry, (value & 0xF) + 1 > 0xF);
     }
     
     fn dec(&mut self, target: ArithmeticTarget) {
         let value = self.get_register_value(target);
         let result = value.wrapping_sub(1);
         self.set_register_value(target, result);
-        self.update_flags(result, true, false, (value & 0xF) < 1);
+        self.update_flags(result, true, self.registers.f.carry, (value & 0xF) < 1);
+    }
+
+    // Turns A back into packed BCD after an addition or subtraction,
+    // using N, H and C to tell which one it was
+    fn daa(&mut self) {
+        let mut a = self.registers.a;
+        let mut carry = self.registers.f.carry;
+        if !self.registers.f.subtract {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.registers.f.half_carry || (a & 0x0F) > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if self.registers.f.half_carry {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+        self.registers.a = a;
+        self.registers.f.zero = a == 0;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = carry;
     }
 
     fn ccf(&mut self) {
@@ -1202,32 +685,32 @@ impl CPU {
     }
     
 
+    // Unlike their 0xCB counterparts, the A-only rotates always clear Z
     fn rra(&mut self) {
         let carry_in = if self.registers.f.carry { 0x80 } else { 0 };
         let carry_out = (self.registers.a & 0x01) != 0;
         self.registers.a = (self.registers.a >> 1) | carry_in;
-        self.update_flags(self.registers.a, false, carry_out, false);
+        self.update_flags(1, false, carry_out, false);
     }
 
     fn rla(&mut self) {
         let carry_in = if self.registers.f.carry { 1 } else { 0 };
         let carry_out = (self.registers.a & 0x80) != 0;
         self.registers.a = (self.registers.a << 1) | carry_in;
-        self.update_flags(self.registers.a, false, carry_out, false);
+        self.update_flags(1, false, carry_out, false);
     }
 
     fn rrca(&mut self) {
         let carry_out = (self.registers.a & 0x01) != 0;
-        self.registers.a = (self.registers.a >> 1) | (self.registers.a << 7);
-        self.update_flags(self.registers.a, false, carry_out, false);
+        self.registers.a = self.registers.a.rotate_right(1);
+        self.update_flags(1, false, carry_out, false);
     }
 
     fn rlca(&mut self) {
         let carry_out = (self.registers.a & 0x80) != 0;
-        self.registers.a = (self.registers.a << 1) | (self.registers.a >> 7);
-        self.update_flags(self.registers.a, false, carry_out, false);
+        self.registers.a = self.registers.a.rotate_left(1);
+        self.update_flags(1, false, carry_out, false);
     }
-        
     fn bit(&mut self, bit: u8, target: ArithmeticTarget) {
         let value = self.get_register_value(target);
         self.registers.f.zero = (value & (1 << bit)) == 0;
@@ -1276,7 +759,7 @@ impl CPU {
     fn rrc(&mut self, target: ArithmeticTarget) {
         let value = self.get_register_value(target);
         let carry_out = (value & 0x01) != 0;
-        let result = (value >> 1) | (value << 7);
+        let result = value.rotate_right(1);
         self.set_register_value(target, result);
         self.update_flags(result, false, carry_out, false);
     }
@@ -1284,7 +767,7 @@ impl CPU {
     fn rlc(&mut self, target: ArithmeticTarget) {
         let value = self.get_register_value(target);
         let carry_out = (value & 0x80) != 0;
-        let result = (value << 1) | (value >> 7);
+        let result = value.rotate_left(1);
         self.set_register_value(target, result);
         self.update_flags(result, false, carry_out, false);
     }
@@ -1307,7 +790,7 @@ impl CPU {
 
     fn swap(&mut self, target: ArithmeticTarget) {
         let value = self.get_register_value(target);
-        let result = (value >> 4) | (value << 4);
+        let result = value.rotate_left(4);
         self.set_register_value(target, result);
         self.update_flags(result, false, false, false);
     }
@@ -1322,6 +805,7 @@ impl CPU {
             ArithmeticTarget::E => self.registers.e,
             ArithmeticTarget::H => self.registers.h,
             ArithmeticTarget::L => self.registers.l,
+            ArithmeticTarget::HLI => self.bus.borrow().read_byte(self.registers.get_hl()),
         }
     }
 
@@ -1334,6 +818,7 @@ impl CPU {
             ArithmeticTarget::E => self.registers.e = value,
             ArithmeticTarget::H => self.registers.h = value,
             ArithmeticTarget::L => self.registers.l = value,
+            ArithmeticTarget::HLI => self.bus.borrow_mut().write_byte(self.registers.get_hl(), value),
         }
     }
 
@@ -0,0 +1,71 @@
+/// Upper bound on instructions executed by one debugger command, so a
+/// step-out that never returns can't lock up the page.
+pub const MAX_DEBUG_INSTRUCTIONS: u32 = 2_000_000;
+
+/// Stops execution when an address in `start..=end` is accessed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, address: u16, write: bool) -> bool {
+        (self.start..=self.end).contains(&address) && if write { self.on_write } else { self.on_read }
+    }
+}
+
+/// The access that tripped a watchpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub address: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+/// Why a debugger command returned control.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested step, step-over or step-out finished.
+    Step,
+    /// PC reached a breakpoint; the instruction there has not run yet.
+    Breakpoint(u16),
+    /// An instruction accessed a watched address.
+    Watchpoint(WatchHit),
+    /// The PPU entered VBlank.
+    FrameComplete,
+    /// `MAX_DEBUG_INSTRUCTIONS` ran without any other stop condition.
+    InstructionLimit,
+}
+
+/// Register snapshot for the debugger UI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterDump {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: bool,
+    pub halted: bool,
+    /// Set after an illegal opcode, until the next reset.
+    pub locked: bool,
+}
+
+/// Opcodes after which execution continues at the next instruction once the
+/// callee returns: CALL, CALL cc and RST. Returns the instruction length.
+pub fn call_length(opcode: u8) -> Option<u16> {
+    match opcode {
+        0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => Some(3),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some(1),
+        _ => None,
+    }
+}
+
+/// RET, RETI and RET cc.
+pub fn is_return(opcode: u8) -> bool {
+    matches!(opcode, 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8)
+}
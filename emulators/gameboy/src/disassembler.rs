@@ -0,0 +1,174 @@
+use crate::instruction::{decode, ArithmeticTarget, Condition, Indirect, Instruction, RegisterPair};
+
+/// One line of a disassembly listing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    /// The encoded instruction, opcode first.
+    pub bytes: Vec<u8>,
+    /// Mnemonic and operands, e.g. `LD A,(FF00+$44)` or `JR NZ,$+5`.
+    pub text: String,
+    /// Cycles taken; for conditional branches, when the branch is not taken.
+    pub cycles: u32,
+    /// Cycles a conditional branch takes when it is taken.
+    pub branch_cycles: Option<u32>,
+}
+
+/// Disassembles `count` consecutive instructions starting at `address`,
+/// fetching bytes through `read`. Nothing is executed, so this works just
+/// as well on a bare ROM image as on a live bus.
+pub fn disassemble(read: impl Fn(u16) -> u8, address: u16, count: usize) -> Vec<DisassembledInstruction> {
+    let mut listing = Vec::with_capacity(count);
+    let mut address = address;
+    for _ in 0..count {
+        let (instruction, length) = decode(&read, address);
+        let cycles = instruction.cycles(false);
+        let taken = instruction.cycles(true);
+        listing.push(DisassembledInstruction {
+            address,
+            bytes: (0..length).map(|offset| read(address.wrapping_add(offset))).collect(),
+            text: mnemonic(instruction),
+            cycles,
+            branch_cycles: (taken != cycles).then_some(taken),
+        });
+        address = address.wrapping_add(length);
+    }
+    listing
+}
+
+fn mnemonic(instruction: Instruction) -> String {
+    use Instruction::*;
+
+    match instruction {
+        ADD(target) => format!("ADD A,{}", operand(target)),
+        ADC(target) => format!("ADC A,{}", operand(target)),
+        SUB(target) => format!("SUB {}", operand(target)),
+        SBC(target) => format!("SBC A,{}", operand(target)),
+        AND(target) => format!("AND {}", operand(target)),
+        OR(target) => format!("OR {}", operand(target)),
+        XOR(target) => format!("XOR {}", operand(target)),
+        CP(target) => format!("CP {}", operand(target)),
+        ADDImmediate(value) => format!("ADD A,${:02X}", value),
+        ADCImmediate(value) => format!("ADC A,${:02X}", value),
+        SUBImmediate(value) => format!("SUB ${:02X}", value),
+        SBCImmediate(value) => format!("SBC A,${:02X}", value),
+        ANDImmediate(value) => format!("AND ${:02X}", value),
+        ORImmediate(value) => format!("OR ${:02X}", value),
+        XORImmediate(value) => format!("XOR ${:02X}", value),
+        CPImmediate(value) => format!("CP ${:02X}", value),
+        INC(target) => format!("INC {}", operand(target)),
+        DEC(target) => format!("DEC {}", operand(target)),
+        INC16(pair) => format!("INC {}", pair_name(pair)),
+        DEC16(pair) => format!("DEC {}", pair_name(pair)),
+        ADDHL(pair) => format!("ADD HL,{}", pair_name(pair)),
+        ADDSP(offset) => format!("ADD SP,{}", offset),
+        DAA => "DAA".to_string(),
+        CPL => "CPL".to_string(),
+        CCF => "CCF".to_string(),
+        SCF => "SCF".to_string(),
+        RLCA => "RLCA".to_string(),
+        RRCA => "RRCA".to_string(),
+        RLA => "RLA".to_string(),
+        RRA => "RRA".to_string(),
+
+        RLC(target) => format!("RLC {}", operand(target)),
+        RRC(target) => format!("RRC {}", operand(target)),
+        RL(target) => format!("RL {}", operand(target)),
+        RR(target) => format!("RR {}", operand(target)),
+        SLA(target) => format!("SLA {}", operand(target)),
+        SRA(target) => format!("SRA {}", operand(target)),
+        SWAP(target) => format!("SWAP {}", operand(target)),
+        SRL(target) => format!("SRL {}", operand(target)),
+        BIT(bit, target) => format!("BIT {},{}", bit, operand(target)),
+        RESET(bit, target) => format!("RES {},{}", bit, operand(target)),
+        SET(bit, target) => format!("SET {},{}", bit, operand(target)),
+
+        LD(destination, source) => format!("LD {},{}", operand(destination), operand(source)),
+        LDImmediate8(target, value) => format!("LD {},${:02X}", operand(target), value),
+        LDImmediate16(pair, value) => format!("LD {},${:04X}", pair_name(pair), value),
+        LDIndirectFromA(indirect) => format!("LD {},A", indirect_name(indirect)),
+        LDAFromIndirect(indirect) => format!("LD A,{}", indirect_name(indirect)),
+        LDAddressFromA(address) => format!("LD (${:04X}),A", address),
+        LDAFromAddress(address) => format!("LD A,(${:04X})", address),
+        LDIOOffsetFromA(offset) => format!("LD (FF00+${:02X}),A", offset),
+        LDIOOffsetToA(offset) => format!("LD A,(FF00+${:02X})", offset),
+        LDIOCFromA => "LD (FF00+C),A".to_string(),
+        LDIOCToA => "LD A,(FF00+C)".to_string(),
+        LDFromSP(address) => format!("LD (${:04X}),SP", address),
+        LDSPFromHL => "LD SP,HL".to_string(),
+        LDHLFromSP(offset) => format!("LD HL,SP{:+}", offset),
+        PUSH(pair) => format!("PUSH {}", pair_name(pair)),
+        POP(pair) => format!("POP {}", pair_name(pair)),
+
+        JP(address) => format!("JP ${:04X}", address),
+        JPConditional(condition, address) => format!("JP {},${:04X}", condition_name(condition), address),
+        JPHL => "JP HL".to_string(),
+        JR(offset) => format!("JR {}", relative(offset)),
+        JRConditional(condition, offset) => format!("JR {},{}", condition_name(condition), relative(offset)),
+        CALL(address) => format!("CALL ${:04X}", address),
+        CALLConditional(condition, address) => format!("CALL {},${:04X}", condition_name(condition), address),
+        RET => "RET".to_string(),
+        RETConditional(condition) => format!("RET {}", condition_name(condition)),
+        RETI => "RETI".to_string(),
+        RST(vector) => format!("RST ${:02X}", vector),
+
+        NOP => "NOP".to_string(),
+        HALT => "HALT".to_string(),
+        STOP => "STOP".to_string(),
+        DI => "DI".to_string(),
+        EI => "EI".to_string(),
+        Illegal(opcode) => format!("DB ${:02X}", opcode),
+    }
+}
+
+/// JR target relative to the JR itself (`$`), which is how assemblers
+/// write it; the encoded offset counts from the following instruction.
+fn relative(offset: i8) -> String {
+    let distance = offset as i16 + 2;
+    if distance < 0 {
+        format!("$-{}", -distance)
+    } else {
+        format!("$+{}", distance)
+    }
+}
+
+fn operand(target: ArithmeticTarget) -> &'static str {
+    match target {
+        ArithmeticTarget::A => "A",
+        ArithmeticTarget::B => "B",
+        ArithmeticTarget::C => "C",
+        ArithmeticTarget::D => "D",
+        ArithmeticTarget::E => "E",
+        ArithmeticTarget::H => "H",
+        ArithmeticTarget::L => "L",
+        ArithmeticTarget::HLI => "(HL)",
+    }
+}
+
+fn pair_name(pair: RegisterPair) -> &'static str {
+    match pair {
+        RegisterPair::BC => "BC",
+        RegisterPair::DE => "DE",
+        RegisterPair::HL => "HL",
+        RegisterPair::SP => "SP",
+        RegisterPair::AF => "AF",
+    }
+}
+
+fn indirect_name(indirect: Indirect) -> &'static str {
+    match indirect {
+        Indirect::BC => "(BC)",
+        Indirect::DE => "(DE)",
+        Indirect::HLIncrement => "(HL+)",
+        Indirect::HLDecrement => "(HL-)",
+    }
+}
+
+fn condition_name(condition: Condition) -> &'static str {
+    match condition {
+        Condition::NZ => "NZ",
+        Condition::Z => "Z",
+        Condition::NC => "NC",
+        Condition::C => "C",
+    }
+}
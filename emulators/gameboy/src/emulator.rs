@@ -0,0 +1,479 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use emu_common::{CoreError, EmulatorCore, FrameBufferInfo, Input, PixelFormat};
+
+use crate::cpu::CPU;
+use crate::debugger::{self, RegisterDump, StopReason, Watchpoint, MAX_DEBUG_INSTRUCTIONS};
+use crate::disassembler::{self, DisassembledInstruction};
+use crate::joypad::Button;
+use crate::memory::MemoryBus;
+use crate::ppu::{Renderer, GPU};
+use crate::serial::{SerialDevice, SerialPort};
+use crate::trace::{self, Tracer};
+use emu_common::log_info;
+use emu_common::palette::Palette;
+use emu_common::rewind::RewindBuffer;
+use emu_common::state::{StateError, StateReader, StateWriter};
+
+const STATE_MAGIC: &[u8; 4] = b"GBST";
+const STATE_VERSION: u16 = 7;
+const STATE_PAYLOAD_LEN: usize = CPU::STATE_LEN + MemoryBus::STATE_LEN + GPU::STATE_LEN;
+
+/// A complete Game Boy: CPU, bus and PPU plus the debugger, rewind and
+/// tracing state that hangs off them.
+pub struct Emulator {
+    cpu: CPU,
+    gpu: Rc<RefCell<GPU>>,
+    memory: Rc<RefCell<MemoryBus>>,
+    rewind: RewindBuffer,
+    breakpoints: BTreeSet<u16>,
+    tracer: Option<Tracer>,
+}
+
+impl Emulator {
+    /// Powers on a Game Boy with `rom_data` inserted, ready to step.
+    pub fn new(rom_data: Vec<u8>) -> Self {
+        let (cpu, gpu, memory) = power_on(rom_data);
+        Emulator { cpu, gpu, memory, rewind: RewindBuffer::default(), breakpoints: BTreeSet::new(), tracer: None }
+    }
+
+    pub fn load_rom(&mut self, rom_data: Vec<u8>) {
+        let watchpoints = std::mem::take(&mut self.memory.borrow_mut().watchpoints);
+        let palette = self.gpu.borrow().palette();
+        let color_correction = self.gpu.borrow().color_correction();
+        let renderer = self.gpu.borrow().renderer();
+        // The same port stays in place, so whatever is plugged into it
+        // stays connected
+        let serial = Rc::clone(&self.memory.borrow().serial);
+        serial.borrow_mut().reset();
+        (self.cpu, self.gpu, self.memory) = power_on(rom_data);
+        self.memory.borrow_mut().watchpoints = watchpoints;
+        self.memory.borrow_mut().serial = serial;
+
+        self.gpu.borrow_mut().set_palette(palette);
+        self.gpu.borrow_mut().set_color_correction(color_correction);
+        self.gpu.borrow_mut().set_renderer(renderer);
+
+        self.rewind.clear();
+
+        log_info!("ROM loaded, VRAM initialized, and LCD control set up");
+    }
+
+    pub fn step(&mut self) {
+        self.execute_instruction();
+    }
+
+    /// Runs until the next VBlank, ignoring breakpoints and watchpoints. A
+    /// frame that never reaches VBlank, e.g. with the LCD off, ends after
+    /// `MAX_DEBUG_INSTRUCTIONS`.
+    pub fn run_frame(&mut self) {
+        for _ in 0..MAX_DEBUG_INSTRUCTIONS {
+            if self.execute_instruction() {
+                return;
+            }
+        }
+    }
+
+    /// Restarts the loaded ROM from power-on.
+    pub fn reset(&mut self) {
+        let rom = self.memory.borrow().rom().to_vec();
+        self.load_rom(rom);
+    }
+
+    /// Steps back to the latest rewind snapshot. Returns false when there is
+    /// no history left.
+    pub fn rewind_step(&mut self) -> bool {
+        match self.rewind.pop() {
+            // Snapshots come from `save_state`, so they always load
+            Some(state) => self.load_state(&state).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Takes a rewind snapshot every `frames` frames.
+    pub fn set_rewind_interval(&mut self, frames: u32) {
+        self.rewind.set_interval(frames);
+    }
+
+    /// Caps the memory used by rewind history; 0 turns rewinding off.
+    pub fn set_rewind_budget(&mut self, bytes: usize) {
+        self.rewind.set_budget(bytes);
+    }
+
+    /// A copy of the 160x144 frame buffer, one shade byte per pixel.
+    pub fn frame_buffer(&self) -> Vec<u8> {
+        self.gpu.borrow().frame_buffer().to_vec()
+    }
+
+    /// Address of the frame buffer, for frontends that read it in place.
+    pub fn frame_buffer_ptr(&self) -> *const u8 {
+        self.gpu.borrow().get_frame_buffer_ptr()
+    }
+
+    pub fn frame_buffer_len(&self) -> usize {
+        self.gpu.borrow().get_frame_buffer_len()
+    }
+
+    /// Picks the scanline renderer (the default) or the slower but more
+    /// accurate pixel FIFO. Survives `load_rom` and `reset`.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.gpu.borrow_mut().set_renderer(renderer);
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.gpu.borrow().renderer()
+    }
+
+    /// True if the loaded ROM runs in CGB mode, picked from the header's
+    /// CGB flag at 0x0143.
+    pub fn is_cgb(&self) -> bool {
+        self.gpu.borrow().is_cgb()
+    }
+
+    /// Renders an RGBA8888 copy of the frame in `palette` alongside the grey
+    /// one, or stops doing so with `None`. Survives `load_rom` and `reset`.
+    /// CGB games ignore it and always render RGBA in colour.
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        self.gpu.borrow_mut().set_palette(palette);
+    }
+
+    pub fn palette(&self) -> Option<Palette> {
+        self.gpu.borrow().palette()
+    }
+
+    /// Turns CGB colour correction on (the default) or off. Survives
+    /// `load_rom` and `reset`.
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.gpu.borrow_mut().set_color_correction(enabled);
+    }
+
+    /// A copy of the CGB frame as RGB555, red in the low bits.
+    pub fn rgb555_frame_buffer(&self) -> Vec<u16> {
+        self.gpu.borrow().rgb555_frame_buffer().to_vec()
+    }
+
+    /// Address of the RGBA frame buffer. Only valid in CGB mode or while a
+    /// palette is set, and it moves when the palette is set or cleared, or a ROM is loaded.
+    pub fn rgba_frame_buffer_ptr(&self) -> *const u8 {
+        self.gpu.borrow().rgba_frame_buffer().as_ptr()
+    }
+
+    /// Length of the RGBA frame buffer in bytes; 0 for a DMG game without a
+    /// palette.
+    pub fn rgba_frame_buffer_len(&self) -> usize {
+        self.gpu.borrow().rgba_frame_buffer().len()
+    }
+
+    /// Reads memory without triggering watchpoints.
+    pub fn read_byte(&self, address: u16) -> u8 {
+        self.memory.borrow().peek_byte(address)
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        self.memory.borrow_mut().write_byte(address, value);
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.memory.borrow_mut().set_button(button, pressed);
+    }
+
+    /// Plugs `device` into the serial port, replacing whatever was there.
+    /// Use `LinkCable` to connect another Game Boy.
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.memory.borrow().serial.borrow_mut().connect(device);
+    }
+
+    /// Unplugs the serial port, so transfers read 0xFF again.
+    pub fn disconnect_serial(&mut self) {
+        self.memory.borrow().serial.borrow_mut().disconnect();
+    }
+
+    pub(crate) fn serial_port(&self) -> Rc<RefCell<SerialPort>> {
+        Rc::clone(&self.memory.borrow().serial)
+    }
+
+    /// Returns and clears the bytes the game sent over the serial port since
+    /// the last call. Test ROMs report their results this way.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.memory.borrow_mut().take_serial_output()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    pub fn registers(&self) -> RegisterDump {
+        self.cpu.register_dump()
+    }
+
+    /// CPU clocks elapsed since power-on or the last ROM load.
+    pub fn cycle_count(&self) -> u64 {
+        self.cpu.cycles()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> Vec<u16> {
+        self.breakpoints.iter().copied().collect()
+    }
+
+    /// Stops execution when any address in `start..=end` is read and/or written.
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, on_read: bool, on_write: bool) {
+        let (start, end) = (start.min(end), start.max(end));
+        self.memory.borrow_mut().watchpoints.push(Watchpoint { start, end, on_read, on_write });
+    }
+
+    pub fn remove_watchpoint(&mut self, start: u16, end: u16) {
+        let (start, end) = (start.min(end), start.max(end));
+        self.memory
+            .borrow_mut()
+            .watchpoints
+            .retain(|watchpoint| watchpoint.start != start || watchpoint.end != end);
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.memory.borrow_mut().watchpoints.clear();
+    }
+
+    /// Executes exactly one instruction.
+    pub fn debug_step(&mut self) -> StopReason {
+        self.run_until(StopReason::Step, |_, _, _| true)
+    }
+
+    /// Like `debug_step`, but runs a CALL or RST until it returns.
+    pub fn debug_step_over(&mut self) -> StopReason {
+        let pc = self.cpu.pc();
+        let opcode = self.memory.borrow().peek_byte(pc);
+        match debugger::call_length(opcode) {
+            Some(length) => {
+                let return_address = pc.wrapping_add(length);
+                let sp = self.cpu.sp();
+                self.run_until(StopReason::Step, |emulator, _, _| {
+                    emulator.cpu.pc() == return_address && emulator.cpu.sp() >= sp
+                })
+            }
+            None => self.debug_step(),
+        }
+    }
+
+    /// Runs until the current subroutine returns to its caller.
+    pub fn debug_step_out(&mut self) -> StopReason {
+        let sp = self.cpu.sp();
+        self.run_until(StopReason::Step, |emulator, opcode, _| {
+            debugger::is_return(opcode) && emulator.cpu.sp() > sp
+        })
+    }
+
+    /// Runs until the next VBlank, stopping early at breakpoints and watchpoints.
+    pub fn debug_run_to_frame(&mut self) -> StopReason {
+        self.run_until(StopReason::FrameComplete, |_, _, frame_complete| frame_complete)
+    }
+
+    /// Disassembles `count` instructions starting at `address` without
+    /// executing anything.
+    pub fn disassemble(&self, address: u16, count: usize) -> Vec<DisassembledInstruction> {
+        let memory = self.memory.borrow();
+        disassembler::disassemble(|address| memory.peek_byte(address), address, count)
+    }
+
+    /// Starts logging every instruction in Game Boy Doctor format, keeping
+    /// the most recent `capacity` lines.
+    pub fn start_trace(&mut self, capacity: usize) {
+        self.tracer = Some(Tracer::ring(capacity));
+    }
+
+    /// Streams the instruction trace to a file instead of the in-memory ring.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn trace_to_file(&mut self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        self.tracer = Some(Tracer::file(path)?);
+        Ok(())
+    }
+
+    /// Returns and clears the buffered trace lines, oldest first.
+    pub fn take_trace(&mut self) -> Vec<String> {
+        self.tracer.as_mut().map_or_else(Vec::new, |tracer| tracer.drain())
+    }
+
+    /// Stops tracing, flushing a file sink and reporting any write error.
+    pub fn finish_trace(&mut self) -> std::io::Result<()> {
+        match self.tracer.take() {
+            Some(mut tracer) => tracer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Snapshots CPU, bus and PPU state as a versioned binary blob. The ROM
+    /// is referenced by checksum rather than copied.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new(STATE_MAGIC, STATE_VERSION);
+        self.cpu.save_state(&mut writer);
+        self.memory.borrow().save_state(&mut writer);
+        self.gpu.borrow().save_state(&mut writer);
+        writer.finish()
+    }
+
+    /// Restores a blob from `save_state`, leaving the emulator as it was if
+    /// the blob is corrupted, from another version or another ROM.
+    //
+    // State is copied into the existing components rather than replacing
+    // them, so the Rc links between CPU, bus and PPU stay intact no matter
+    // how often `load_rom` rebuilt them.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::open(data, STATE_MAGIC, STATE_VERSION, STATE_PAYLOAD_LEN)?;
+        let backup = self.save_state();
+
+        if let Err(error) = self.read_components(&mut reader) {
+            let mut reader = StateReader::open(&backup, STATE_MAGIC, STATE_VERSION, STATE_PAYLOAD_LEN)?;
+            self.read_components(&mut reader)?;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn read_components(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.cpu.load_state(reader)?;
+        self.memory.borrow_mut().load_state(reader)?;
+        self.gpu.borrow_mut().load_state(reader)
+    }
+
+    /// Runs one CPU instruction and the bookkeeping that hangs off frame
+    /// boundaries. Returns true if this instruction completed a frame.
+    pub(crate) fn execute_instruction(&mut self) -> bool {
+        if let Some(tracer) = &mut self.tracer {
+            let registers = self.cpu.register_dump();
+            // A halted or locked-up CPU isn't executing instructions
+            if !registers.halted && !registers.locked {
+                let memory = self.memory.borrow();
+                let pcmem = [0, 1, 2, 3].map(|offset| memory.peek_byte(registers.pc.wrapping_add(offset)));
+                tracer.record(trace::doctor_line(&registers, pcmem));
+            }
+        }
+
+        self.cpu.step();
+
+        let frame_complete = self.gpu.borrow_mut().take_frame_complete();
+        if frame_complete && self.rewind.tick() {
+            let state = self.save_state();
+            self.rewind.push(&state);
+        }
+        frame_complete
+    }
+
+    /// Executes instructions until `done` returns true or a breakpoint or
+    /// watchpoint intervenes. `done` sees the opcode just executed and
+    /// whether it completed a frame. A breakpoint at the starting PC is
+    /// ignored so that execution can resume from it.
+    fn run_until(&mut self, done_reason: StopReason, mut done: impl FnMut(&Self, u8, bool) -> bool) -> StopReason {
+        for executed in 0..MAX_DEBUG_INSTRUCTIONS {
+            let pc = self.cpu.pc();
+            if executed > 0 && self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+
+            let opcode = self.memory.borrow().peek_byte(pc);
+            self.memory.borrow().take_watch_hit();
+            let frame_complete = self.execute_instruction();
+
+            if let Some(hit) = self.memory.borrow().take_watch_hit() {
+                return StopReason::Watchpoint(hit);
+            }
+            if done(self, opcode, frame_complete) {
+                return done_reason;
+            }
+        }
+        StopReason::InstructionLimit
+    }
+}
+
+/// Builds the bus, PPU and CPU for `rom_data`, wires them together and
+/// sets up VRAM and the LCD.
+fn power_on(rom_data: Vec<u8>) -> (CPU, Rc<RefCell<GPU>>, Rc<RefCell<MemoryBus>>) {
+    // Step 1: Create MemoryBus without GPU reference
+    let memory = MemoryBus::new(rom_data);
+    let gpu = GPU::new(Rc::clone(&memory));
+
+    // Step 2: Set the GPU reference in MemoryBus
+    memory.borrow_mut().set_gpu(Rc::clone(&gpu));
+
+    // Step 3: Create the CPU with references to both MemoryBus and GPU
+    let cpu = CPU::new(Rc::clone(&memory), Rc::clone(&gpu));
+
+    gpu.borrow_mut().load_rom_to_vram(memory.borrow().rom());
+    gpu.borrow_mut().setup_lcd_control();
+    (cpu, gpu, memory)
+}
+
+impl EmulatorCore for Emulator {
+    fn system(&self) -> &'static str {
+        "gameboy"
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) -> Result<(), CoreError> {
+        Emulator::load_rom(self, rom.to_vec());
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        Emulator::reset(self);
+    }
+
+    fn run_frame(&mut self) -> Result<(), CoreError> {
+        Emulator::run_frame(self);
+        Ok(())
+    }
+
+    fn frame_buffer_info(&self) -> FrameBufferInfo {
+        let format = if self.rgba_frame_buffer_len() > 0 { PixelFormat::Rgba8888 } else { PixelFormat::Gray8 };
+        FrameBufferInfo { width: 160, height: 144, format }
+    }
+
+    fn frame_buffer(&self) -> Vec<u8> {
+        let gpu = self.gpu.borrow();
+        match gpu.rgba_frame_buffer() {
+            [] => gpu.frame_buffer().to_vec(),
+            rgba => rgba.to_vec(),
+        }
+    }
+
+    fn audio_sample_rate(&self) -> u32 {
+        48_000
+    }
+
+    /// There is no APU yet, so there is never any audio.
+    fn drain_audio(&mut self, _out: &mut Vec<f32>) {}
+
+    fn set_input(&mut self, input: Input, pressed: bool) {
+        let button = match input {
+            Input::Up => Button::Up,
+            Input::Down => Button::Down,
+            Input::Left => Button::Left,
+            Input::Right => Button::Right,
+            Input::A => Button::A,
+            Input::B => Button::B,
+            Input::Select => Button::Select,
+            Input::Start => Button::Start,
+            Input::Key(_) => return,
+        };
+        self.set_button(button, pressed);
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        Emulator::save_state(self)
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), CoreError> {
+        Ok(Emulator::load_state(self, data)?)
+    }
+}
@@ -0,0 +1,356 @@
+#![allow(clippy::upper_case_acronyms)]
+
+/// 8-bit operand: a register, or the byte at the address in HL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArithmeticTarget {
+    A, B, C, D, E, H, L,
+    HLI, // (HL)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegisterPair {
+    BC,
+    DE,
+    HL,
+    SP, // Only in 16-bit loads and arithmetic
+    AF, // Only in PUSH and POP
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    NZ,  // Not Zero
+    Z,   // Zero
+    NC,  // Not Carry
+    C,   // Carry
+}
+
+/// Address operand of `LD (rr),A` and `LD A,(rr)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indirect {
+    BC,
+    DE,
+    HLIncrement, // (HL+)
+    HLDecrement, // (HL-)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    ADD(ArithmeticTarget),        // Add to A
+    ADC(ArithmeticTarget),        // Add with carry
+    SUB(ArithmeticTarget),        // Subtract from A
+    SBC(ArithmeticTarget),        // Subtract with carry
+    AND(ArithmeticTarget),        // Logical AND with A
+    OR(ArithmeticTarget),         // Logical OR with A
+    XOR(ArithmeticTarget),        // Logical XOR with A
+    CP(ArithmeticTarget),         // Compare with A
+    ADDImmediate(u8),
+    ADCImmediate(u8),
+    SUBImmediate(u8),
+    SBCImmediate(u8),
+    ANDImmediate(u8),
+    ORImmediate(u8),
+    XORImmediate(u8),
+    CPImmediate(u8),
+    INC(ArithmeticTarget),        // Increment
+    DEC(ArithmeticTarget),        // Decrement
+    INC16(RegisterPair),          // Increment register pair
+    DEC16(RegisterPair),          // Decrement register pair
+    ADDHL(RegisterPair),          // Add register pair to HL
+    ADDSP(i8),                    // Add signed offset to SP
+    DAA,                          // Decimal adjust A
+    CPL,                          // Complement A
+    CCF,                          // Complement Carry Flag
+    SCF,                          // Set Carry Flag
+    RLCA,                         // Rotate A left (no carry)
+    RRCA,                         // Rotate A right (no carry)
+    RLA,                          // Rotate A left through carry
+    RRA,                          // Rotate A right through carry
+
+    // 0xCB-prefixed
+    RLC(ArithmeticTarget),        // Rotate left (no carry)
+    RRC(ArithmeticTarget),        // Rotate right (no carry)
+    RL(ArithmeticTarget),         // Rotate left through carry
+    RR(ArithmeticTarget),         // Rotate right through carry
+    SLA(ArithmeticTarget),        // Shift left arithmetic
+    SRA(ArithmeticTarget),        // Shift right arithmetic
+    SWAP(ArithmeticTarget),       // Swap nibbles
+    SRL(ArithmeticTarget),        // Shift right logical
+    BIT(u8, ArithmeticTarget),    // Test bit
+    RESET(u8, ArithmeticTarget),  // Reset bit
+    SET(u8, ArithmeticTarget),    // Set bit
+
+    LD(ArithmeticTarget, ArithmeticTarget), // Load from one operand to another
+    LDImmediate8(ArithmeticTarget, u8),
+    LDImmediate16(RegisterPair, u16),
+    LDIndirectFromA(Indirect),              // LD (rr), A
+    LDAFromIndirect(Indirect),              // LD A, (rr)
+    LDAddressFromA(u16),                    // LD (nn), A
+    LDAFromAddress(u16),                    // LD A, (nn)
+    LDIOOffsetFromA(u8),                    // LD (FF00+n), A
+    LDIOOffsetToA(u8),                      // LD A, (FF00+n)
+    LDIOCFromA,                             // LD (FF00+C), A
+    LDIOCToA,                               // LD A, (FF00+C)
+    LDFromSP(u16),                          // Load memory from stack pointer
+    LDSPFromHL,                             // Load stack pointer from HL
+    LDHLFromSP(i8),                         // Load HL from SP + signed offset
+    PUSH(RegisterPair),
+    POP(RegisterPair),
+
+    JP(u16),
+    JPConditional(Condition, u16),
+    JPHL,
+    JR(i8),
+    JRConditional(Condition, i8),
+    CALL(u16),
+    CALLConditional(Condition, u16),
+    RET,
+    RETConditional(Condition),
+    RETI,
+    RST(u8),
+
+    NOP,
+    HALT,
+    STOP,
+    DI,
+    EI,
+    /// One of the eleven unused opcodes, which lock up real hardware.
+    Illegal(u8),
+}
+
+/// Decodes the instruction at `address`, fetching bytes through `read`.
+/// Returns the instruction and its length in bytes. Decoding has no side
+/// effects, so the CPU and the disassembler share it.
+pub fn decode(read: impl Fn(u16) -> u8, address: u16) -> (Instruction, u16) {
+    use Instruction::*;
+
+    let opcode = read(address);
+    let d8 = || read(address.wrapping_add(1));
+    let d16 = || u16::from_le_bytes([d8(), read(address.wrapping_add(2))]);
+
+    let instruction = match opcode {
+        0x00 => NOP,
+        0x10 => STOP,
+        0x76 => HALT,
+        0xF3 => DI,
+        0xFB => EI,
+
+        // 16-bit loads and arithmetic, pair in bits 4-5
+        0x01 | 0x11 | 0x21 | 0x31 => LDImmediate16(register_pair(opcode >> 4, RegisterPair::SP), d16()),
+        0x03 | 0x13 | 0x23 | 0x33 => INC16(register_pair(opcode >> 4, RegisterPair::SP)),
+        0x0B | 0x1B | 0x2B | 0x3B => DEC16(register_pair(opcode >> 4, RegisterPair::SP)),
+        0x09 | 0x19 | 0x29 | 0x39 => ADDHL(register_pair(opcode >> 4, RegisterPair::SP)),
+        0xC1 | 0xD1 | 0xE1 | 0xF1 => POP(register_pair(opcode >> 4, RegisterPair::AF)),
+        0xC5 | 0xD5 | 0xE5 | 0xF5 => PUSH(register_pair(opcode >> 4, RegisterPair::AF)),
+
+        0x02 | 0x12 | 0x22 | 0x32 => LDIndirectFromA(indirect(opcode >> 4)),
+        0x0A | 0x1A | 0x2A | 0x3A => LDAFromIndirect(indirect(opcode >> 4)),
+
+        // 8-bit operand in bits 3-5
+        0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => INC(target(opcode >> 3)),
+        0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => DEC(target(opcode >> 3)),
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => LDImmediate8(target(opcode >> 3), d8()),
+
+        0x07 => RLCA,
+        0x0F => RRCA,
+        0x17 => RLA,
+        0x1F => RRA,
+        0x27 => DAA,
+        0x2F => CPL,
+        0x37 => SCF,
+        0x3F => CCF,
+
+        0x08 => LDFromSP(d16()),
+        0x18 => JR(d8() as i8),
+        0x20 | 0x28 | 0x30 | 0x38 => JRConditional(condition(opcode >> 3), d8() as i8),
+
+        // LD r, r' (0x76 is HALT, matched above)
+        0x40..=0x7F => LD(target(opcode >> 3), target(opcode)),
+        0x80..=0xBF => alu(opcode >> 3, target(opcode)),
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => alu_immediate(opcode >> 3, d8()),
+
+        0xC3 => JP(d16()),
+        0xC2 | 0xCA | 0xD2 | 0xDA => JPConditional(condition(opcode >> 3), d16()),
+        0xE9 => JPHL,
+        0xCD => CALL(d16()),
+        0xC4 | 0xCC | 0xD4 | 0xDC => CALLConditional(condition(opcode >> 3), d16()),
+        0xC9 => RET,
+        0xC0 | 0xC8 | 0xD0 | 0xD8 => RETConditional(condition(opcode >> 3)),
+        0xD9 => RETI,
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => RST(opcode & 0x38),
+
+        0xE0 => LDIOOffsetFromA(d8()),
+        0xF0 => LDIOOffsetToA(d8()),
+        0xE2 => LDIOCFromA,
+        0xF2 => LDIOCToA,
+        0xEA => LDAddressFromA(d16()),
+        0xFA => LDAFromAddress(d16()),
+        0xE8 => ADDSP(d8() as i8),
+        0xF8 => LDHLFromSP(d8() as i8),
+        0xF9 => LDSPFromHL,
+
+        0xCB => decode_prefixed(d8()),
+
+        _ => Illegal(opcode),
+    };
+    (instruction, instruction.length())
+}
+
+fn decode_prefixed(opcode: u8) -> Instruction {
+    let operand = target(opcode);
+    let bit = (opcode >> 3) & 7;
+    match opcode >> 6 {
+        0 => match bit {
+            0 => Instruction::RLC(operand),
+            1 => Instruction::RRC(operand),
+            2 => Instruction::RL(operand),
+            3 => Instruction::RR(operand),
+            4 => Instruction::SLA(operand),
+            5 => Instruction::SRA(operand),
+            6 => Instruction::SWAP(operand),
+            _ => Instruction::SRL(operand),
+        },
+        1 => Instruction::BIT(bit, operand),
+        2 => Instruction::RESET(bit, operand),
+        _ => Instruction::SET(bit, operand),
+    }
+}
+
+/// Operand encoded in the low three bits: B, C, D, E, H, L, (HL), A.
+fn target(bits: u8) -> ArithmeticTarget {
+    match bits & 7 {
+        0 => ArithmeticTarget::B,
+        1 => ArithmeticTarget::C,
+        2 => ArithmeticTarget::D,
+        3 => ArithmeticTarget::E,
+        4 => ArithmeticTarget::H,
+        5 => ArithmeticTarget::L,
+        6 => ArithmeticTarget::HLI,
+        _ => ArithmeticTarget::A,
+    }
+}
+
+/// Pair encoded in the low two bits; the fourth slot is SP or AF
+/// depending on the instruction.
+fn register_pair(bits: u8, fourth: RegisterPair) -> RegisterPair {
+    match bits & 3 {
+        0 => RegisterPair::BC,
+        1 => RegisterPair::DE,
+        2 => RegisterPair::HL,
+        _ => fourth,
+    }
+}
+
+fn indirect(bits: u8) -> Indirect {
+    match bits & 3 {
+        0 => Indirect::BC,
+        1 => Indirect::DE,
+        2 => Indirect::HLIncrement,
+        _ => Indirect::HLDecrement,
+    }
+}
+
+fn condition(bits: u8) -> Condition {
+    match bits & 3 {
+        0 => Condition::NZ,
+        1 => Condition::Z,
+        2 => Condition::NC,
+        _ => Condition::C,
+    }
+}
+
+fn alu(bits: u8, operand: ArithmeticTarget) -> Instruction {
+    match bits & 7 {
+        0 => Instruction::ADD(operand),
+        1 => Instruction::ADC(operand),
+        2 => Instruction::SUB(operand),
+        3 => Instruction::SBC(operand),
+        4 => Instruction::AND(operand),
+        5 => Instruction::XOR(operand),
+        6 => Instruction::OR(operand),
+        _ => Instruction::CP(operand),
+    }
+}
+
+fn alu_immediate(bits: u8, value: u8) -> Instruction {
+    match bits & 7 {
+        0 => Instruction::ADDImmediate(value),
+        1 => Instruction::ADCImmediate(value),
+        2 => Instruction::SUBImmediate(value),
+        3 => Instruction::SBCImmediate(value),
+        4 => Instruction::ANDImmediate(value),
+        5 => Instruction::XORImmediate(value),
+        6 => Instruction::ORImmediate(value),
+        _ => Instruction::CPImmediate(value),
+    }
+}
+
+impl Instruction {
+    /// Encoded size in bytes, including the 0xCB prefix.
+    pub fn length(&self) -> u16 {
+        use Instruction::*;
+        match self {
+            ADDImmediate(_) | ADCImmediate(_) | SUBImmediate(_) | SBCImmediate(_)
+            | ANDImmediate(_) | ORImmediate(_) | XORImmediate(_) | CPImmediate(_)
+            | LDImmediate8(..) | LDIOOffsetFromA(_) | LDIOOffsetToA(_)
+            | ADDSP(_) | LDHLFromSP(_) | JR(_) | JRConditional(..) | STOP => 2,
+
+            RLC(_) | RRC(_) | RL(_) | RR(_) | SLA(_) | SRA(_) | SWAP(_) | SRL(_)
+            | BIT(..) | RESET(..) | SET(..) => 2,
+
+            LDImmediate16(..) | LDAddressFromA(_) | LDAFromAddress(_) | LDFromSP(_)
+            | JP(_) | JPConditional(..) | CALL(_) | CALLConditional(..) => 3,
+
+            _ => 1,
+        }
+    }
+
+    /// Machine clock cycles (4.19 MHz) the instruction takes. Conditional
+    /// jumps, calls and returns take longer when `branch_taken`.
+    pub fn cycles(&self, branch_taken: bool) -> u32 {
+        use ArithmeticTarget::HLI;
+        use Instruction::*;
+
+        let branch = |not_taken, taken| if branch_taken { taken } else { not_taken };
+        match *self {
+            ADD(HLI) | ADC(HLI) | SUB(HLI) | SBC(HLI)
+            | AND(HLI) | OR(HLI) | XOR(HLI) | CP(HLI) => 8,
+            ADD(_) | ADC(_) | SUB(_) | SBC(_) | AND(_) | OR(_) | XOR(_) | CP(_) => 4,
+            ADDImmediate(_) | ADCImmediate(_) | SUBImmediate(_) | SBCImmediate(_)
+            | ANDImmediate(_) | ORImmediate(_) | XORImmediate(_) | CPImmediate(_) => 8,
+
+            INC(HLI) | DEC(HLI) => 12,
+            INC(_) | DEC(_) => 4,
+            INC16(_) | DEC16(_) | ADDHL(_) => 8,
+            ADDSP(_) => 16,
+            DAA | CPL | CCF | SCF | RLCA | RRCA | RLA | RRA => 4,
+
+            BIT(_, HLI) => 12,
+            RLC(HLI) | RRC(HLI) | RL(HLI) | RR(HLI) | SLA(HLI) | SRA(HLI) | SWAP(HLI) | SRL(HLI)
+            | RESET(_, HLI) | SET(_, HLI) => 16,
+            RLC(_) | RRC(_) | RL(_) | RR(_) | SLA(_) | SRA(_) | SWAP(_) | SRL(_)
+            | BIT(..) | RESET(..) | SET(..) => 8,
+
+            LD(HLI, _) | LD(_, HLI) => 8,
+            LD(..) => 4,
+            LDImmediate8(HLI, _) => 12,
+            LDImmediate8(..) => 8,
+            LDImmediate16(..) => 12,
+            LDIndirectFromA(_) | LDAFromIndirect(_) | LDIOCFromA | LDIOCToA | LDSPFromHL => 8,
+            LDIOOffsetFromA(_) | LDIOOffsetToA(_) | LDHLFromSP(_) | POP(_) => 12,
+            LDAddressFromA(_) | LDAFromAddress(_) | PUSH(_) => 16,
+            LDFromSP(_) => 20,
+
+            JP(_) => 16,
+            JPConditional(..) => branch(12, 16),
+            JPHL => 4,
+            JR(_) => 12,
+            JRConditional(..) => branch(8, 12),
+            CALL(_) => 24,
+            CALLConditional(..) => branch(12, 24),
+            RET | RETI | RST(_) => 16,
+            RETConditional(_) => branch(8, 20),
+
+            NOP | HALT | STOP | DI | EI | Illegal(_) => 4,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+/// A button on the Game Boy joypad.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// Every button, in P1 bit order.
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    /// Bit in the pressed-buttons mask: directions in the low nibble,
+    /// actions in the high one, each in P1 order.
+    pub(crate) fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Computes the P1 register (0xFF00) from the selection bits last written
+/// and the pressed-buttons mask. Pressed buttons read as 0, and a cleared
+/// bit 4 or 5 selects the directions or the actions respectively.
+pub(crate) fn read_p1(select: u8, pressed: u8) -> u8 {
+    let mut low = 0;
+    if select & 0x10 == 0 {
+        low |= pressed & 0x0F;
+    }
+    if select & 0x20 == 0 {
+        low |= pressed >> 4;
+    }
+    0xC0 | (select & 0x30) | (!low & 0x0F)
+}
@@ -0,0 +1,76 @@
+//! Two Game Boys joined by a link cable.
+
+use std::rc::Rc;
+
+use crate::debugger::MAX_DEBUG_INSTRUCTIONS;
+use crate::serial::Peer;
+use crate::Emulator;
+
+/// Two emulators with their serial ports wired together, run in lockstep
+/// so that a byte sent by one arrives while the other is still waiting
+/// for it.
+///
+/// Whichever side starts a transfer on its internal clock drives it: when
+/// the byte is done the two SB registers swap and both sides get the
+/// serial interrupt, provided the other side had a transfer waiting on the
+/// external clock. Otherwise the sender reads 0xFF, as with no cable.
+pub struct LinkCable {
+    left: Emulator,
+    right: Emulator,
+}
+
+impl LinkCable {
+    pub fn new(mut left: Emulator, mut right: Emulator) -> Self {
+        Self::connect(&mut left, &mut right);
+        LinkCable { left, right }
+    }
+
+    /// Wires two emulators together without taking them over, for
+    /// frontends that run each one themselves. The closer together they
+    /// are stepped, the less a game waiting on its partner has to retry.
+    pub fn connect(left: &mut Emulator, right: &mut Emulator) {
+        let (left_port, right_port) = (left.serial_port(), right.serial_port());
+        left.connect_serial(Box::new(Peer(Rc::downgrade(&right_port))));
+        right.connect_serial(Box::new(Peer(Rc::downgrade(&left_port))));
+    }
+
+    /// Runs both Game Boys until each has reached its next VBlank,
+    /// alternating an instruction at a time.
+    pub fn run_frame(&mut self) {
+        let (mut left_done, mut right_done) = (false, false);
+        for _ in 0..MAX_DEBUG_INSTRUCTIONS {
+            if !left_done {
+                left_done = self.left.execute_instruction();
+            }
+            if !right_done {
+                right_done = self.right.execute_instruction();
+            }
+            if left_done && right_done {
+                return;
+            }
+        }
+    }
+
+    pub fn left(&self) -> &Emulator {
+        &self.left
+    }
+
+    pub fn left_mut(&mut self) -> &mut Emulator {
+        &mut self.left
+    }
+
+    pub fn right(&self) -> &Emulator {
+        &self.right
+    }
+
+    pub fn right_mut(&mut self) -> &mut Emulator {
+        &mut self.right
+    }
+
+    /// Unplugs the cable and hands both emulators back.
+    pub fn disconnect(mut self) -> (Emulator, Emulator) {
+        self.left.disconnect_serial();
+        self.right.disconnect_serial();
+        (self.left, self.right)
+    }
+}
@@ -0,0 +1,115 @@
+//! Memory bank controllers: the cartridge hardware that maps more ROM and
+//! RAM into the address space than fits in it at once.
+//!
+//! Plain 32 KiB cartridges and MBC1 are supported. Other controllers are
+//! run as plain cartridges, so only their first two ROM banks are visible.
+
+use emu_common::log_warn;
+use emu_common::state::{StateError, StateReader, StateWriter};
+
+/// The most cartridge RAM an MBC1 can address, four 8 KiB banks.
+pub const MAX_RAM_LEN: usize = 0x8000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// ROM at 0x0000-0x7FFF as is, and 8 KiB of RAM always enabled.
+    Plain,
+    Mbc1,
+}
+
+#[derive(Clone)]
+pub struct Mbc {
+    kind: Kind,
+    rom_banks: usize,  // 16 KiB banks in the ROM
+    ram_len: usize,    // Bytes of cartridge RAM
+    ram_enabled: bool, // RAMG: 0x0A in the low nibble
+    rom_bank: u8,      // BANK1: 5 bits, 0 selects 1
+    upper_bits: u8,    // BANK2: 2 bits, ROM bank bits 5-6 or the RAM bank
+    advanced: bool,    // MODE: BANK2 also applies to 0x0000 and RAM
+}
+
+impl Mbc {
+    /// Picks the controller and RAM size from the cartridge header.
+    pub fn new(rom: &[u8]) -> Self {
+        let cartridge_type = rom.get(0x0147).copied().unwrap_or(0);
+        let kind = match cartridge_type {
+            0x01..=0x03 => Kind::Mbc1,
+            0x00 | 0x08 | 0x09 => Kind::Plain,
+            _ => {
+                log_warn!("Unsupported cartridge type {:#04X}, running it without banking", cartridge_type);
+                Kind::Plain
+            }
+        };
+        let ram_len = match (kind, rom.get(0x0149).copied().unwrap_or(0)) {
+            (Kind::Plain, _) => 0x2000,
+            (_, 0x01) => 0x800,
+            (_, 0x02) => 0x2000,
+            (_, 0x03) => 0x8000,
+            _ => 0,
+        };
+        Mbc {
+            kind,
+            rom_banks: (rom.len() / 0x4000).max(1),
+            ram_len,
+            ram_enabled: kind == Kind::Plain,
+            rom_bank: 1,
+            upper_bits: 0,
+            advanced: false,
+        }
+    }
+
+    /// Bytes written by `save_state`.
+    pub const STATE_LEN: usize = 4;
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_bool(self.ram_enabled);
+        writer.put_u8(self.rom_bank);
+        writer.put_u8(self.upper_bits);
+        writer.put_bool(self.advanced);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.ram_enabled = reader.bool("RAM enable")? || self.kind == Kind::Plain;
+        self.rom_bank = (reader.u8() & 0x1F).max(1);
+        self.upper_bits = reader.u8() & 0x03;
+        self.advanced = reader.bool("banking mode")?;
+        Ok(())
+    }
+
+    /// Handles a write to 0x0000-0x7FFF, where the MBC registers live.
+    pub fn write(&mut self, address: u16, value: u8) {
+        if self.kind == Kind::Plain {
+            return;
+        }
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x1F).max(1),
+            0x4000..=0x5FFF => self.upper_bits = value & 0x03,
+            _ => self.advanced = value & 0x01 != 0,
+        }
+    }
+
+    /// Maps 0x0000-0x7FFF to an offset into the ROM. Banks past the end of
+    /// the ROM wrap, as the unused bank lines are not connected.
+    pub fn rom_offset(&self, address: u16) -> usize {
+        if self.kind == Kind::Plain {
+            return address as usize;
+        }
+        let bank = match address {
+            0x0000..=0x3FFF if self.advanced => (self.upper_bits as usize) << 5,
+            0x0000..=0x3FFF => 0,
+            _ => (self.upper_bits as usize) << 5 | self.rom_bank as usize,
+        };
+        (bank % self.rom_banks) * 0x4000 + (address as usize & 0x3FFF)
+    }
+
+    /// Maps 0xA000-0xBFFF to an offset into cartridge RAM, or `None` when
+    /// it is disabled or absent and the bus reads open.
+    pub fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram_len == 0 {
+            return None;
+        }
+        let bank = if self.advanced { self.upper_bits as usize } else { 0 };
+        Some((bank * 0x2000 + (address as usize - 0xA000)) % self.ram_len)
+    }
+}
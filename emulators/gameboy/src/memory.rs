@@ -1,77 +1,339 @@
-use crate::console_log;
+use crate::debugger::{WatchHit, Watchpoint};
+use crate::joypad::{self, Button};
+use crate::mbc::{self, Mbc};
 use crate::ppu::GPU;
+use crate::serial::SerialPort;
+use crate::timer::Timer;
+use emu_common::state::{crc32, StateError, StateReader, StateWriter};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 
 
 #[derive(Clone)]
 pub struct MemoryBus {
     rom: Vec<u8>,                 // Cartridge ROM
-    vram: [u8; 0x2000],           // Video RAM
-    eram: [u8; 0x2000],           // External RAM
-    wram: [u8; 0x2000],           // Work RAM
-    oam: [u8; 0xA0],              // Object Attribute Memory
+    cgb: bool,                    // The cartridge header asks for CGB mode
+    mbc: Mbc,                     // Cartridge banking
+    eram: [u8; mbc::MAX_RAM_LEN], // External RAM, as much as the MBC can address
+    wram: [u8; 0x8000],           // Work RAM, eight banks of 0x1000 (1-7 are CGB only)
+    wram_bank: u8,                // WRAM bank at 0xD000 (SVBK)
+    speed_switch_armed: bool,     // KEY1 bit 0: STOP switches speed
+    double_speed: bool,           // KEY1 bit 7: CPU runs at 2x
+    timer: Timer,                 // DIV, TIMA, TMA and TAC
+    hdma_source: u16,             // Next VRAM DMA source address (HDMA1/2)
+    hdma_dest: u16,               // Next VRAM DMA destination, offset into VRAM (HDMA3/4)
+    hdma_length: u8,              // Blocks left minus one, 0x7F when done (HDMA5)
+    hdma_active: bool,            // An HBlank DMA is in progress
+    dma_stall: u32,               // CPU clocks the last DMA blocks held the CPU for
     io_registers: [u8; 0x80],     // I/O Registers
     hram: [u8; 0x7F],             // High RAM (HRAM)
     pub interrupt_enable: u8,         // Interrupt Enable Register
     pub interrupt_flag: u8,           // Interrupt Flag Register (0xFF0F)
     pub gpu: Option<Rc<RefCell<GPU>>>,                  // Add a reference to your GPU
+    pub watchpoints: Vec<Watchpoint>,                   // Debugger watchpoints
+    buttons: u8,                                        // Pressed joypad buttons, see `Button::mask`
+    pub serial: Rc<RefCell<SerialPort>>,                // SB, SC and the link cable
+    watch_hit: Cell<Option<WatchHit>>,                  // First watchpoint hit since last take
 }
 
 impl MemoryBus {
     pub fn new(rom: Vec<u8>) -> Rc<RefCell<Self>> {
+        // Bit 7 of the CGB flag marks games that use CGB features; 0xC0
+        // means CGB only, 0x80 means they also run on a DMG
+        let cgb = rom.get(0x0143).is_some_and(|flag| flag & 0x80 != 0);
+        let mbc = Mbc::new(&rom);
         Rc::new(RefCell::new(Self  {
             rom,
-            vram: [0; 0x2000],
-            eram: [0; 0x2000],
-            wram: [0; 0x2000],
-            oam: [0; 0xA0],
+            cgb,
+            mbc,
+            eram: [0; mbc::MAX_RAM_LEN],
+            wram: [0; 0x8000],
+            wram_bank: 1,
+            speed_switch_armed: false,
+            double_speed: false,
+            timer: Timer::default(),
+            hdma_source: 0,
+            hdma_dest: 0,
+            hdma_length: 0x7F,
+            hdma_active: false,
+            dma_stall: 0,
             io_registers: [0; 0x80],
             hram: [0; 0x7F],
             interrupt_enable: 0,
             interrupt_flag: 0,
-            gpu: None
+            gpu: None,
+            watchpoints: Vec::new(),
+            buttons: 0,
+            serial: SerialPort::new(),
+            watch_hit: Cell::new(None),
         }))
     }
     pub fn set_gpu(&mut self, gpu: Rc<RefCell<GPU>>) {
         self.gpu = Some(gpu);
     }
 
+    /// Bytes written by `save_state`.
+    pub const STATE_LEN: usize =
+        4 + mbc::MAX_RAM_LEN + 0x8000 + 0x80 + 0x7F + 2 + 3 + 6 + SerialPort::STATE_LEN + Mbc::STATE_LEN + Timer::STATE_LEN;
+
+    /// Writes all RAM regions and IO registers. The ROM itself is not stored,
+    /// only its checksum, so a state can't be loaded into a different game.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_u32(crc32(&self.rom));
+        writer.put_bytes(&self.eram);
+        writer.put_bytes(&self.wram);
+        writer.put_bytes(&self.io_registers);
+        writer.put_bytes(&self.hram);
+        writer.put_u8(self.interrupt_enable);
+        writer.put_u8(self.interrupt_flag);
+        writer.put_u8(self.wram_bank);
+        writer.put_bool(self.speed_switch_armed);
+        writer.put_bool(self.double_speed);
+        writer.put_u16(self.hdma_source);
+        writer.put_u16(self.hdma_dest);
+        writer.put_u8(self.hdma_length);
+        writer.put_bool(self.hdma_active);
+        self.serial.borrow().save_state(writer);
+        self.mbc.save_state(writer);
+        self.timer.save_state(writer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        if reader.u32() != crc32(&self.rom) {
+            return Err(StateError::InvalidField("ROM checksum"));
+        }
+        reader.bytes(&mut self.eram);
+        reader.bytes(&mut self.wram);
+        reader.bytes(&mut self.io_registers);
+        reader.bytes(&mut self.hram);
+        self.interrupt_enable = reader.u8();
+        self.interrupt_flag = reader.u8();
+        self.wram_bank = reader.u8().clamp(1, 7);
+        self.speed_switch_armed = reader.bool("KEY1 switch")?;
+        self.double_speed = reader.bool("KEY1 speed")?;
+        self.hdma_source = reader.u16();
+        self.hdma_dest = reader.u16() & 0x1FF0;
+        self.hdma_length = reader.u8() & 0x7F;
+        self.hdma_active = reader.bool("HDMA flag")?;
+        self.serial.borrow_mut().load_state(reader);
+        self.mbc.load_state(reader)?;
+        self.timer.load_state(reader);
+        Ok(())
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// True if the cartridge header selected CGB mode.
+    pub fn is_cgb(&self) -> bool {
+        self.cgb
+    }
+
+    /// True while the CPU runs in CGB double-speed mode.
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Called on STOP: performs the speed switch if one was armed through
+    /// KEY1. Returns whether the speed changed.
+    pub fn switch_speed(&mut self) -> bool {
+        if !std::mem::take(&mut self.speed_switch_armed) {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        true
+    }
+
+    /// Handles a write to HDMA5 (0xFF55). Bit 7 clear starts a general
+    /// purpose DMA, copied at once, or cancels a running HBlank DMA; bit 7
+    /// set starts an HBlank DMA, one 16-byte block per HBlank.
+    fn write_vram_dma(&mut self, value: u8) {
+        if value & 0x80 == 0 {
+            if self.hdma_active {
+                self.hdma_active = false;
+                return;
+            }
+            self.hdma_length = value & 0x7F;
+            while self.transfer_vram_dma_block() {}
+        } else {
+            self.hdma_length = value & 0x7F;
+            self.hdma_active = true;
+            // With the LCD off there are no HBlanks, so the first block goes
+            // straight away
+            if self.gpu().lcd_control & 0x80 == 0 {
+                self.hblank_dma();
+            }
+        }
+    }
+
+    /// Copies the next block of an HBlank DMA. The PPU signals each HBlank
+    /// and the CPU, which owns both, forwards it here.
+    pub fn hblank_dma(&mut self) {
+        if self.hdma_active {
+            self.hdma_active = self.transfer_vram_dma_block();
+        }
+    }
+
+    /// Copies 16 bytes to VRAM and charges the CPU for it. Returns false
+    /// once the last block is done.
+    fn transfer_vram_dma_block(&mut self) -> bool {
+        for _ in 0..16 {
+            let value = self.peek_byte(self.hdma_source);
+            self.gpu().write_vram(0x8000 | self.hdma_dest, value);
+            self.hdma_source = self.hdma_source.wrapping_add(1);
+            self.hdma_dest = (self.hdma_dest + 1) & 0x1FFF;
+        }
+        // 8 M-cycles per block, which take twice the clocks at double speed
+        self.dma_stall += if self.double_speed { 64 } else { 32 };
+        self.hdma_length = self.hdma_length.wrapping_sub(1) & 0x7F;
+        self.hdma_length != 0x7F
+    }
+
+    /// Copies 160 bytes from `page` * 0x100 to OAM. The transfer happens at
+    /// once rather than over 160 M-cycles, and the CPU isn't locked out of
+    /// the bus while it runs.
+    fn oam_dma(&mut self, page: u8) {
+        let source = (page as u16) << 8;
+        for offset in 0..0xA0 {
+            let value = self.peek_byte(source + offset);
+            self.gpu().write_oam(0xFE00 + offset, value);
+        }
+    }
+
+    /// Returns and clears the CPU clocks spent on VRAM DMA since the last
+    /// call, during which the CPU is halted.
+    pub fn take_dma_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.dma_stall)
+    }
+
+    /// Maps 0xC000-0xFDFF, echo RAM included, to an offset into `wram`.
+    /// 0xC000-0xCFFF is always bank 0; 0xD000-0xDFFF is the SVBK bank.
+    fn wram_index(&self, address: u16) -> usize {
+        let offset = (address as usize - 0xC000) & 0x1FFF;
+        if offset < 0x1000 {
+            offset
+        } else {
+            self.wram_bank as usize * 0x1000 + offset - 0x1000
+        }
+    }
+
+    fn gpu(&self) -> std::cell::RefMut<'_, GPU> {
+        self.gpu.as_ref().expect("PPU not attached to the bus").borrow_mut()
+    }
+
+    /// Presses or releases a joypad button, requesting the joypad interrupt
+    /// on a press.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed && self.buttons & button.mask() == 0 {
+            self.interrupt_flag |= 0x10;
+        }
+        if pressed {
+            self.buttons |= button.mask();
+        } else {
+            self.buttons &= !button.mask();
+        }
+    }
+
+    /// Returns and clears the bytes sent over the serial port since the last
+    /// call.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.serial.borrow_mut().take_output()
+    }
+
+    /// Runs the serial port for `cycles` CPU clocks and requests the serial
+    /// interrupt if a transfer finished, on this side's clock or a
+    /// partner's.
+    pub fn step_serial(&mut self, cycles: u32) {
+        let mut serial = self.serial.borrow_mut();
+        serial.step(cycles);
+        if serial.take_interrupt() {
+            self.interrupt_flag |= 0x08;
+        }
+    }
+
+    /// Runs the timer for `cycles` CPU clocks and requests the timer
+    /// interrupt if TIMA overflowed.
+    pub fn step_timer(&mut self, cycles: u32) {
+        self.timer.step(cycles);
+        if self.timer.take_interrupt() {
+            self.interrupt_flag |= 0x04;
+        }
+    }
+
+    /// Returns and clears the first watchpoint hit since the last call.
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    fn check_watchpoints(&self, address: u16, value: u8, write: bool) {
+        if self.watch_hit.get().is_none()
+            && self.watchpoints.iter().any(|watchpoint| watchpoint.matches(address, write))
+        {
+            self.watch_hit.set(Some(WatchHit { address, value, write }));
+        }
+    }
+
     pub fn read_byte(&self, address: u16) -> u8 {
-        match address {
-            // ROM Bank 0 (0x0000 - 0x3FFF)
-            0x0000..=0x3FFF => self.rom[address as usize],
+        let value = self.peek_byte(address);
+        self.check_watchpoints(address, value, false);
+        value
+    }
 
-            // ROM Bank 1 (Switchable) (0x4000 - 0x7FFF)
-            0x4000..=0x7FFF => self.rom[address as usize], // Add bank switching logic if needed
+    /// Reads a byte without triggering watchpoints, for debugger views.
+    pub fn peek_byte(&self, address: u16) -> u8 {
+        match address {
+            // ROM Bank 0 (0x0000 - 0x3FFF) and the switchable bank
+            // (0x4000 - 0x7FFF); a ROM smaller than 32 KiB reads open bus
+            // past its end
+            0x0000..=0x7FFF => self.rom.get(self.mbc.rom_offset(address)).copied().unwrap_or(0xFF),
 
             // Video RAM (0x8000 - 0x9FFF)
-            0x8000..=0x9FFF => self.vram[(address - 0x8000) as usize],
+            0x8000..=0x9FFF => self.gpu().read_vram(address),
 
             // External RAM (0xA000 - 0xBFFF)
-            0xA000..=0xBFFF => self.eram[(address - 0xA000) as usize],
-
-            // Work RAM (0xC000 - 0xCFFF)
-            0xC000..=0xCFFF => self.wram[(address - 0xC000) as usize],
+            0xA000..=0xBFFF => self.mbc.ram_offset(address).map_or(0xFF, |offset| self.eram[offset]),
 
-            // Work RAM (0xD000 - 0xDFFF)
-            0xD000..=0xDFFF => self.wram[(address - 0xC000) as usize],
-
-            // Echo RAM (mirror of 0xC000 - 0xDDFF) (0xE000 - 0xFDFF)
-            0xE000..=0xFDFF => self.wram[(address - 0xE000) as usize],
+            // Work RAM (0xC000 - 0xDFFF), and Echo RAM mirroring it
+            // (0xE000 - 0xFDFF)
+            0xC000..=0xFDFF => self.wram[self.wram_index(address)],
 
             // Object Attribute Memory (OAM) (0xFE00 - 0xFE9F)
-            0xFE00..=0xFE9F => self.oam[(address - 0xFE00) as usize],
+            0xFE00..=0xFE9F => self.gpu().read_oam(address),
 
             // Unused (0xFEA0 - 0xFEFF)
             0xFEA0..=0xFEFF => 0xFF,
 
             // I/O Registers (0xFF00 - 0xFF7F)
             0xFF00..=0xFF7F => {
-                if address == 0xFF0F {
+                if address == 0xFF00 {
+                    joypad::read_p1(self.io_registers[0], self.buttons)
+                } else if address == 0xFF01 {
+                    self.serial.borrow().read_data()
+                } else if address == 0xFF02 {
+                    self.serial.borrow().read_control(self.cgb)
+                } else if (0xFF04..=0xFF07).contains(&address) {
+                    self.timer.read(address)
+                } else if address == 0xFF0F {
                     self.interrupt_flag
-                } else if address == 0xFF40 {
-                    self.gpu.as_ref().unwrap().borrow_mut().lcd_control
+                } else if is_ppu_register(address) {
+                    self.gpu().read_register(address)
+                } else if address == 0xFF4D {
+                    if self.cgb {
+                        0x7E | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8
+                    } else {
+                        0xFF
+                    }
+                } else if address == 0xFF70 {
+                    if self.cgb { 0xF8 | self.wram_bank } else { 0xFF }
+                } else if address == 0xFF55 {
+                    match (self.cgb, self.hdma_active) {
+                        (false, _) => 0xFF,
+                        (true, true) => self.hdma_length,
+                        (true, false) => 0x80 | self.hdma_length,
+                    }
+                } else if (0xFF51..=0xFF54).contains(&address) {
+                    0xFF // Write-only
                 }
                 else {
                     self.io_registers[(address - 0xFF00) as usize]
@@ -83,41 +345,66 @@ impl MemoryBus {
 
             // Interrupt Enable Register (0xFFFF)
             0xFFFF => self.interrupt_enable,
-
-            _ => 0xFF,
         }
     }
 
     pub fn write_byte(&mut self, address: u16, value: u8) {
+        self.check_watchpoints(address, value, true);
         match address {
-            // ROM areas should be read-only
-            0x0000..=0x7FFF => {
-                // Optionally handle MBC here for bank switching
-            }
+            // ROM is read-only; writes go to the MBC's registers
+            0x0000..=0x7FFF => self.mbc.write(address, value),
 
             // Video RAM (0x8000 - 0x9FFF)
-            0x8000..=0x9FFF => self.vram[(address - 0x8000) as usize] = value,
+            0x8000..=0x9FFF => self.gpu().write_vram(address, value),
 
             // External RAM (0xA000 - 0xBFFF)
-            0xA000..=0xBFFF => self.eram[(address - 0xA000) as usize] = value,
-
-            // Work RAM (0xC000 - 0xDFFF)
-            0xC000..=0xDFFF => self.wram[(address - 0xC000) as usize] = value,
+            0xA000..=0xBFFF => {
+                if let Some(offset) = self.mbc.ram_offset(address) {
+                    self.eram[offset] = value;
+                }
+            }
 
-            // Echo RAM (mirror of 0xC000 - 0xDDFF)
-            0xE000..=0xFDFF => self.wram[(address - 0xE000) as usize] = value,
+            // Work RAM (0xC000 - 0xDFFF), and Echo RAM mirroring it
+            // (0xE000 - 0xFDFF)
+            0xC000..=0xFDFF => self.wram[self.wram_index(address)] = value,
 
             // Object Attribute Memory (OAM) (0xFE00 - 0xFE9F)
-            0xFE00..=0xFE9F => self.oam[(address - 0xFE00) as usize] = value,
+            0xFE00..=0xFE9F => self.gpu().write_oam(address, value),
 
             // I/O Registers (0xFF00 - 0xFF7F)
             0xFF00..=0xFF7F => {
-                if address == 0xFF0F {
+                if address == 0xFF01 {
+                    self.serial.borrow_mut().write_data(value);
+                } else if address == 0xFF02 {
+                    self.serial.borrow_mut().write_control(value, self.cgb);
+                } else if (0xFF04..=0xFF07).contains(&address) {
+                    self.timer.write(address, value);
+                } else if address == 0xFF0F {
                     self.interrupt_flag = value;
-                } else if address == 0xFF40 {
-                    // Update the LCD Control register
-                    console_log!("Writing to LCD Control (0xFF40): {:#04X}", value);
-                    self.gpu.as_mut().unwrap().borrow_mut().lcd_control = value;
+                } else if is_ppu_register(address) {
+                    self.gpu().write_register(address, value);
+                } else if address == 0xFF46 {
+                    self.io_registers[0x46] = value;
+                    self.oam_dma(value);
+                } else if address == 0xFF4D {
+                    if self.cgb {
+                        self.speed_switch_armed = value & 0x01 != 0;
+                    }
+                } else if address == 0xFF70 {
+                    // Selecting bank 0 selects bank 1
+                    if self.cgb {
+                        self.wram_bank = (value & 0x07).max(1);
+                    }
+                } else if (0xFF51..=0xFF55).contains(&address) {
+                    if self.cgb {
+                        match address {
+                            0xFF51 => self.hdma_source = (value as u16) << 8 | (self.hdma_source & 0x00F0),
+                            0xFF52 => self.hdma_source = (self.hdma_source & 0xFF00) | (value & 0xF0) as u16,
+                            0xFF53 => self.hdma_dest = ((value & 0x1F) as u16) << 8 | (self.hdma_dest & 0x00F0),
+                            0xFF54 => self.hdma_dest = (self.hdma_dest & 0x1F00) | (value & 0xF0) as u16,
+                            _ => self.write_vram_dma(value),
+                        }
+                    }
                 }
                 else {
                     self.io_registers[(address - 0xFF00) as usize] = value;
@@ -134,3 +421,9 @@ impl MemoryBus {
         }
     }
 }
+
+/// IO registers that live in the PPU: LCDC through WX except DMA (0xFF46),
+/// VBK, and the CGB palette registers.
+fn is_ppu_register(address: u16) -> bool {
+    matches!(address, 0xFF40..=0xFF45 | 0xFF47..=0xFF4B | 0xFF4F | 0xFF68..=0xFF6B)
+}
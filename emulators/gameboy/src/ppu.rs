@@ -1,11 +1,18 @@
+#![allow(clippy::upper_case_acronyms)]
+
+mod fifo;
+
 use crate::memory::MemoryBus;
-use crate::console_log;
+use fifo::Fifo;
+use emu_common::{log_debug, log_trace, log_warn};
+use emu_common::palette::Palette;
+use emu_common::state::{StateError, StateReader, StateWriter};
 use std::rc::Rc;
 use std::cell::RefCell;
 
 #[derive(Clone)]
 pub struct GPU {
-    vram: [u8; 0x2000],         // Video RAM
+    vram: [u8; 0x4000],         // Video RAM, two banks of 0x2000 (bank 1 is CGB only)
     oam: [u8; 0xA0],            // Object Attribute Memory (Sprites)
     pub lcd_control: u8,            // LCD Control (LCDC)
     lcd_status: u8,             // LCD Status (STAT)
@@ -18,24 +25,75 @@ pub struct GPU {
     background_palette: u8,     // Background Palette (BGP)
     sprite_palette_0: u8,       // Object Palette 0 (OBP0)
     sprite_palette_1: u8,       // Object Palette 1 (OBP1)
+    window_line: u8,            // Window rows drawn so far this frame
+    window_triggered: bool,     // LY has matched WY this frame
+    stat_line: bool,            // STAT interrupt sources, ORed, as of the last step
+    cgb: bool,                  // Running a CGB game in colour mode
+    vram_bank: u8,              // VRAM bank at 0x8000 (VBK)
+    bg_palette_index: u8,       // BG palette RAM address and auto-increment (BCPS)
+    obj_palette_index: u8,      // OBJ palette RAM address and auto-increment (OCPS)
+    bg_palette_ram: [u8; 64],   // 8 BG palettes of 4 RGB555 colours
+    obj_palette_ram: [u8; 64],  // 8 OBJ palettes of 4 RGB555 colours
     frame_buffer: [[u8; 160]; 144], // Frame buffer to store pixel data
+    color_frame: Vec<u16>,      // RGB555 pixels in CGB mode, unused otherwise
     mode_clock: u32,            // Clock for tracking mode timing
     mode: GPUMode,              // Current GPU mode (OAM, VRAM, HBlank, VBlank)
+    frame_complete: bool,       // Set on entering VBlank, cleared by take_frame_complete
+    hblank_started: bool,       // Set on entering HBlank of a visible line, cleared by take_hblank_started
+    palette: Option<Palette>,   // Colours for the RGBA output, if enabled
+    color_correction: bool,     // Mimic the CGB screen instead of showing raw RGB555
+    rgba: Vec<u8>,              // Frame buffer as RGBA8888, empty unless a palette is set or in CGB mode
+    renderer: Renderer,         // How lines are drawn
+    fifo: Fifo,                 // Pixel FIFO renderer state, rebuilt rather than saved
     bus: Rc<RefCell<MemoryBus>>
 }
 
+/// Grey level written to the frame buffer for each shade, lightest first.
+const GREY_LEVELS: [u8; 4] = [0xFF, 0xC0, 0x80, 0x00];
+
+// Discriminants match the STAT mode bits.
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum GPUMode {
-    HBlank,
-    VBlank,
-    OAM,
-    VRAM,
+    HBlank = 0,
+    VBlank = 1,
+    OAM = 2,
+    VRAM = 3,
+}
+
+/// How the PPU turns VRAM into pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Renderer {
+    /// Draws each line in one go at the end of a fixed-length mode 3. Fast,
+    /// but register writes during mode 3 only show from the next line.
+    #[default]
+    Scanline,
+    /// Runs the background and sprite fetchers a dot at a time, so writes
+    /// during mode 3 land mid-line and mode 3 stretches with fine scroll,
+    /// sprites and the window as it does on hardware.
+    PixelFifo,
+}
+
+/// A background or window pixel before palette lookup.
+#[derive(Clone, Copy, Default)]
+struct BackgroundPixel {
+    color_id: u8,
+    attributes: u8, // CGB tile map attributes, 0 on DMG
+}
+
+/// An opaque sprite pixel before palette lookup.
+#[derive(Clone, Copy)]
+struct SpritePixel {
+    color_id: u8,
+    attributes: u8, // OAM attributes
+    oam_index: u8,  // Decides priority between sprites on CGB
 }
 
 impl GPU {
+    /// Creates the PPU, in colour mode if the bus holds a CGB game.
     pub fn new(bus: Rc<RefCell<MemoryBus>>) -> Rc<RefCell<Self>>  {
-        Rc::new(RefCell::new(Self {
-            vram: [0; 0x2000],
+        let cgb = bus.borrow().is_cgb();
+        let mut gpu = Self {
+            vram: [0; 0x4000],
             oam: [0; 0xA0],
             lcd_control: 0x80,
             lcd_status: 0,
@@ -48,11 +106,213 @@ impl GPU {
             background_palette: 0,
             sprite_palette_0: 0,
             sprite_palette_1: 0,
+            window_line: 0,
+            window_triggered: false,
+            stat_line: false,
+            cgb,
+            vram_bank: 0,
+            bg_palette_index: 0,
+            obj_palette_index: 0,
+            // The boot ROM leaves BG palettes white
+            bg_palette_ram: [0xFF; 64],
+            obj_palette_ram: [0; 64],
             frame_buffer: [[0; 160]; 144],
+            color_frame: vec![0; 160 * 144],
             mode_clock: 0,
             mode: GPUMode::OAM,
+            frame_complete: false,
+            hblank_started: false,
+            palette: None,
+            color_correction: true,
+            rgba: Vec::new(),
+            renderer: Renderer::default(),
+            fifo: Fifo::default(),
             bus
-        }))
+        };
+        gpu.render_rgba();
+        Rc::new(RefCell::new(gpu))
+    }
+
+    /// Bytes written by `save_state`.
+    pub const STATE_LEN: usize = 0x4000 + 0xA0 + 11 + 4 + 2 + 64 * 2 + 160 * 144 + 160 * 144 * 2 + 4 + 1;
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_bytes(&self.vram);
+        writer.put_bytes(&self.oam);
+        writer.put_u8(self.lcd_control);
+        writer.put_u8(self.lcd_status);
+        writer.put_u8(self.scroll_x);
+        writer.put_u8(self.scroll_y);
+        writer.put_u8(self.window_x);
+        writer.put_u8(self.window_y);
+        writer.put_u8(self.current_scanline);
+        writer.put_u8(self.ly_compare);
+        writer.put_u8(self.background_palette);
+        writer.put_u8(self.sprite_palette_0);
+        writer.put_u8(self.sprite_palette_1);
+        writer.put_u8(self.window_line);
+        writer.put_bool(self.window_triggered);
+        writer.put_bool(self.stat_line);
+        writer.put_u8(self.vram_bank);
+        writer.put_u8(self.bg_palette_index);
+        writer.put_u8(self.obj_palette_index);
+        writer.put_bytes(&self.bg_palette_ram);
+        writer.put_bytes(&self.obj_palette_ram);
+        for row in &self.frame_buffer {
+            writer.put_bytes(row);
+        }
+        for &color in &self.color_frame {
+            writer.put_u16(color);
+        }
+        writer.put_u32(self.mode_clock);
+        writer.put_u8(self.mode as u8);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.bytes(&mut self.vram);
+        reader.bytes(&mut self.oam);
+        self.lcd_control = reader.u8();
+        self.lcd_status = reader.u8();
+        self.scroll_x = reader.u8();
+        self.scroll_y = reader.u8();
+        self.window_x = reader.u8();
+        self.window_y = reader.u8();
+        self.current_scanline = reader.u8();
+        self.ly_compare = reader.u8();
+        self.background_palette = reader.u8();
+        self.sprite_palette_0 = reader.u8();
+        self.sprite_palette_1 = reader.u8();
+        self.window_line = reader.u8();
+        self.window_triggered = reader.bool("WY trigger")?;
+        self.stat_line = reader.bool("STAT line")?;
+        self.vram_bank = reader.u8() & 1;
+        self.bg_palette_index = reader.u8();
+        self.obj_palette_index = reader.u8();
+        reader.bytes(&mut self.bg_palette_ram);
+        reader.bytes(&mut self.obj_palette_ram);
+        for row in self.frame_buffer.iter_mut() {
+            reader.bytes(row);
+        }
+        for color in self.color_frame.iter_mut() {
+            *color = reader.u16();
+        }
+        self.mode_clock = reader.u32();
+        self.mode = match reader.u8() {
+            0 => GPUMode::HBlank,
+            1 => GPUMode::VBlank,
+            2 => GPUMode::OAM,
+            3 => GPUMode::VRAM,
+            _ => return Err(StateError::InvalidField("PPU mode")),
+        };
+        self.render_rgba();
+        self.resync_fifo();
+        Ok(())
+    }
+
+    /// Returns true once per frame, after the PPU enters VBlank.
+    pub fn take_frame_complete(&mut self) -> bool {
+        std::mem::replace(&mut self.frame_complete, false)
+    }
+
+    /// Returns true once after each visible line's HBlank begins, when an
+    /// HBlank DMA block is due.
+    pub fn take_hblank_started(&mut self) -> bool {
+        std::mem::replace(&mut self.hblank_started, false)
+    }
+
+    /// True when rendering a CGB game in colour.
+    pub fn is_cgb(&self) -> bool {
+        self.cgb
+    }
+
+    /// Reads VRAM (0x8000-0x9FFF) through the selected bank.
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.vram[self.vram_bank as usize * 0x2000 + (address as usize & 0x1FFF)]
+    }
+
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        self.vram[self.vram_bank as usize * 0x2000 + (address as usize & 0x1FFF)] = value;
+    }
+
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.oam[(address - 0xFE00) as usize]
+    }
+
+    pub fn write_oam(&mut self, address: u16, value: u8) {
+        self.oam[(address - 0xFE00) as usize] = value;
+    }
+
+    /// Reads one of the PPU's IO registers: 0xFF40-0xFF4B except DMA, plus
+    /// the CGB VBK (0xFF4F) and palette registers (0xFF68-0xFF6B).
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            0xFF40 => self.lcd_control,
+            0xFF41 => {
+                let coincidence = if self.current_scanline == self.ly_compare { 0x04 } else { 0 };
+                0x80 | (self.lcd_status & 0x78) | coincidence | self.mode as u8
+            }
+            0xFF42 => self.scroll_y,
+            0xFF43 => self.scroll_x,
+            0xFF44 => self.current_scanline,
+            0xFF45 => self.ly_compare,
+            0xFF47 => self.background_palette,
+            0xFF48 => self.sprite_palette_0,
+            0xFF49 => self.sprite_palette_1,
+            0xFF4A => self.window_y,
+            0xFF4B => self.window_x,
+            0xFF4F if self.cgb => 0xFE | self.vram_bank,
+            0xFF68 if self.cgb => self.bg_palette_index | 0x40,
+            0xFF69 if self.cgb => self.bg_palette_ram[(self.bg_palette_index & 0x3F) as usize],
+            0xFF6A if self.cgb => self.obj_palette_index | 0x40,
+            0xFF6B if self.cgb => self.obj_palette_ram[(self.obj_palette_index & 0x3F) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF40 => {
+                log_debug!("Writing to LCD Control (0xFF40): {:#04X}", value);
+                self.lcd_control = value;
+            }
+            // The mode and coincidence bits are read-only
+            0xFF41 => self.lcd_status = value & 0x78,
+            0xFF42 => self.scroll_y = value,
+            0xFF43 => self.scroll_x = value,
+            0xFF44 => {} // LY is read-only
+            0xFF45 => self.ly_compare = value,
+            0xFF47 => self.background_palette = value,
+            0xFF48 => self.sprite_palette_0 = value,
+            0xFF49 => self.sprite_palette_1 = value,
+            0xFF4A => self.window_y = value,
+            0xFF4B => self.window_x = value,
+            0xFF4F if self.cgb => self.vram_bank = value & 1,
+            0xFF68 if self.cgb => self.bg_palette_index = value & 0xBF,
+            0xFF69 if self.cgb => {
+                self.bg_palette_ram[(self.bg_palette_index & 0x3F) as usize] = value;
+                self.bg_palette_index = Self::advance_palette_index(self.bg_palette_index);
+            }
+            0xFF6A if self.cgb => self.obj_palette_index = value & 0xBF,
+            0xFF6B if self.cgb => {
+                self.obj_palette_ram[(self.obj_palette_index & 0x3F) as usize] = value;
+                self.obj_palette_index = Self::advance_palette_index(self.obj_palette_index);
+            }
+            _ => {}
+        }
+    }
+
+    /// Steps BCPS/OCPS after a data write if bit 7 asks for auto-increment.
+    fn advance_palette_index(index: u8) -> u8 {
+        if index & 0x80 != 0 {
+            0x80 | ((index + 1) & 0x3F)
+        } else {
+            index
+        }
+    }
+
+    /// The frame buffer as rows of 160 pixels, top row first.
+    pub fn frame_buffer(&self) -> &[u8] {
+        self.frame_buffer.as_flattened()
     }
 
     pub fn get_frame_buffer_ptr(&self) -> *const u8 {
@@ -64,8 +324,80 @@ impl GPU {
         self.frame_buffer.len() * self.frame_buffer[0].len()
     }
 
+    /// Enables RGBA output in the given colours, or turns it off with `None`.
+    /// The current frame is redrawn straight away so the change shows even
+    /// while paused. CGB games always render RGBA in their own colours.
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        self.palette = palette;
+        self.render_rgba();
+    }
+
+    pub fn palette(&self) -> Option<Palette> {
+        self.palette
+    }
+
+    /// Chooses between colours adjusted to look like the CGB's screen
+    /// (the default) and the raw RGB555 values scaled up.
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.color_correction = enabled;
+        self.render_rgba();
+    }
+
+    pub fn color_correction(&self) -> bool {
+        self.color_correction
+    }
+
+    /// The frame buffer as RGBA8888, or an empty slice on a DMG game without
+    /// a palette.
+    pub fn rgba_frame_buffer(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    /// The CGB frame as RGB555, red in the low bits. All black outside CGB
+    /// mode.
+    pub fn rgb555_frame_buffer(&self) -> &[u16] {
+        &self.color_frame
+    }
+
+    /// Rebuilds the whole RGBA buffer from the grey or RGB555 frame buffer.
+    fn render_rgba(&mut self) {
+        if self.cgb {
+            self.rgba.resize(160 * 144 * 4, 0);
+            for (pixel, &color) in self.rgba.chunks_exact_mut(4).zip(&self.color_frame) {
+                pixel.copy_from_slice(&rgb555_to_rgba(color, self.color_correction));
+            }
+            return;
+        }
+        let Some(palette) = self.palette else {
+            self.rgba = Vec::new();
+            return;
+        };
+        self.rgba.resize(160 * 144 * 4, 0);
+        for (pixel, &grey) in self.rgba.chunks_exact_mut(4).zip(self.frame_buffer.as_flattened()) {
+            let shade = GREY_LEVELS.iter().position(|&level| level == grey).unwrap_or(0);
+            pixel.copy_from_slice(&palette.color(shade as u8));
+        }
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, shade: u8) {
+        self.frame_buffer[y][x] = GREY_LEVELS[shade as usize];
+        if let Some(palette) = self.palette {
+            let offset = (y * 160 + x) * 4;
+            self.rgba[offset..offset + 4].copy_from_slice(&palette.color(shade));
+        }
+    }
+
+    /// Writes a CGB pixel, keeping a grey version in the DMG frame buffer.
+    fn put_color(&mut self, x: usize, y: usize, color: u16) {
+        let rgba = rgb555_to_rgba(color, false);
+        self.frame_buffer[y][x] = ((rgba[0] as u32 * 77 + rgba[1] as u32 * 150 + rgba[2] as u32 * 29) >> 8) as u8;
+        self.color_frame[y * 160 + x] = color;
+        let offset = (y * 160 + x) * 4;
+        self.rgba[offset..offset + 4].copy_from_slice(&rgb555_to_rgba(color, self.color_correction));
+    }
+
     pub fn load_rom_to_vram(&mut self, rom_data: &[u8]) {
-        console_log!("Starting to load ROM data into VRAM...");
+        log_debug!("Starting to load ROM data into VRAM...");
 
         // Step 1: Load tile data into VRAM (0x8000 - 0x9800)
         let tile_data_start = 0x8000;
@@ -73,7 +405,7 @@ impl GPU {
         let vram_tile_offset = 0;
 
         if rom_data.len() > tile_data_end {
-            console_log!("Loading tile data into VRAM...");
+            log_debug!("Loading tile data into VRAM...");
             for i in 0..(tile_data_end - tile_data_start) {
                 let rom_index = tile_data_start + i;
                 if rom_index < rom_data.len() {
@@ -81,7 +413,7 @@ impl GPU {
                 }
             }
         } else {
-            console_log!("Error: ROM data too small for tile data");
+            log_warn!("ROM data too small for tile data");
         }
 
         // Step 2: Load tile map into VRAM (0x9800 - 0x9C00)
@@ -90,7 +422,7 @@ impl GPU {
         let vram_map_offset = 0x1800;
 
         if rom_data.len() > tile_map_end {
-            console_log!("Loading tile map into VRAM...");
+            log_debug!("Loading tile map into VRAM...");
             for i in 0..(tile_map_end - tile_map_start) {
                 let rom_index = tile_map_start + i;
                 if rom_index < rom_data.len() {
@@ -98,103 +430,168 @@ impl GPU {
                 }
             }
         } else {
-            console_log!("Error: ROM data too small for tile map");
+            log_warn!("ROM data too small for tile map");
         }
 
         // Verify that the tile map contains non-zero values
-        console_log!("Checking tile map after loading...");
+        log_debug!("Checking tile map after loading...");
         for i in 0x1800..0x1B00 {
             if self.vram[i] != 0 {
-                console_log!("Non-zero tile number found at VRAM[{}]: {}", i, self.vram[i]);
+                log_debug!("Non-zero tile number found at VRAM[{}]: {}", i, self.vram[i]);
                 break;
             }
         }
-        console_log!("Finished loading ROM data into VRAM");
+        log_debug!("Finished loading ROM data into VRAM");
     }
     
     pub fn setup_lcd_control(&mut self) {
         self.lcd_control = 0x91; // Turn on LCD, background display, and use correct tile data
     }
-    
 
-    fn render_sprites(&mut self) {
-        
+    /// Picks the sprites on the current line, highest priority first: up to
+    /// ten in OAM order, then on DMG sorted by X so the leftmost wins.
+    fn sprites_on_line(&self, sprite_height: i16) -> Vec<usize> {
+        let line = self.current_scanline as i16;
+        let mut sprites: Vec<usize> = (0..40)
+            .filter(|&i| {
+                let y_pos = self.oam[i * 4] as i16 - 16;
+                (y_pos..y_pos + sprite_height).contains(&line)
+            })
+            .take(10)
+            .collect();
+        if !self.cgb {
+            // Stable, so equal X falls back to OAM order
+            sprites.sort_by_key(|&i| self.oam[i * 4 + 1]);
+        }
+        sprites
+    }
+
+    fn sprite_height(&self) -> i16 {
+        if self.lcd_control & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    /// Colour IDs of sprite `i`'s row on the current line, left to right.
+    fn sprite_row(&self, i: usize, sprite_height: i16) -> [u8; 8] {
+        let index = i * 4;
+        let y_pos = self.oam[index] as i16 - 16;
+        let mut tile_index = self.oam[index + 2];
+        let attributes = self.oam[index + 3];
+        if sprite_height == 16 {
+            tile_index &= 0xFE;
+        }
+
+        // Masked in case LCDC switched to 8x8 sprites since the OAM scan
+        let line = (self.current_scanline as i16 - y_pos) & (sprite_height - 1);
+        let line = if attributes & 0x40 != 0 { sprite_height - 1 - line } else { line };
+
+        let bank = if self.cgb && attributes & 0x08 != 0 { 0x2000 } else { 0 };
+        let tile_address = bank + tile_index as usize * 16 + line as usize * 2;
+        let byte1 = self.vram[tile_address];
+        let byte2 = self.vram[tile_address + 1];
+
+        let mut row = [0; 8];
+        for (x, color_id) in row.iter_mut().enumerate() {
+            let color_bit = if attributes & 0x20 != 0 { x } else { 7 - x };
+            *color_id = ((byte1 >> color_bit) & 1) | (((byte2 >> color_bit) & 1) << 1);
+        }
+        row
+    }
+
+    /// Works out which sprite pixel, if any, lands on each column of the
+    /// current line.
+    fn render_sprites(&self) -> [Option<SpritePixel>; 160] {
+        // The highest priority sprite with an opaque pixel owns it, even if
+        // the background then hides that pixel
+        let mut owner = [None; 160];
+
         if self.lcd_control & 0x02 == 0 {
-            console_log!("Sprites are disabled");
-            return;
+            log_trace!("Sprites are disabled");
+            return owner;
         }
-    
-        let sprite_height = if self.lcd_control & 0x04 != 0 { 16 } else { 8 };
-        let mut sprites_rendered = 0;
-    
-        for i in (0..40).rev() {
-            if sprites_rendered >= 10 {
-                break;
-            }
-    
-            let index = i * 4;
-            if index + 3 >= self.oam.len() {
-                console_log!("OAM out of bounds access at index: {}", index);
-                continue;
-            }
-    
-            let y_pos = self.oam[index] as i16 - 16;
-            let x_pos = self.oam[index + 1] as i16 - 8;
-            let tile_index = self.oam[index + 2];
-            let attributes = self.oam[index + 3];
-    
-    
-            if self.current_scanline < y_pos as u8 || self.current_scanline >= (y_pos + sprite_height) as u8 {
-                continue;
+
+        let sprite_height = self.sprite_height();
+        for i in self.sprites_on_line(sprite_height) {
+            let x_pos = self.oam[i * 4 + 1] as i16 - 8;
+            let attributes = self.oam[i * 4 + 3];
+            for (x, color_id) in self.sprite_row(i, sprite_height).into_iter().enumerate() {
+                let pixel_x = x_pos + x as i16;
+                if !(0..160).contains(&pixel_x) || owner[pixel_x as usize].is_some() || color_id == 0 {
+                    continue;
+                }
+                owner[pixel_x as usize] = Some(SpritePixel { color_id, attributes, oam_index: i as u8 });
             }
-    
-            let line = if attributes & 0x40 != 0 {
-                sprite_height - 1 - (self.current_scanline as i16 - y_pos)
+        }
+        owner
+    }
+
+    /// Mixes the background and sprite pixels for column `x` of the current
+    /// line and draws the result, reading the palettes as they are now.
+    fn draw_pixel(&mut self, x: usize, background: BackgroundPixel, sprite: Option<SpritePixel>) {
+        let y = self.current_scanline as usize;
+        // On DMG, LCDC bit 0 blanks the background and window to white; on
+        // CGB it only takes away their priority over sprites
+        let background_enabled = self.lcd_control & 0x01 != 0;
+        let background = if self.cgb || background_enabled { background } else { BackgroundPixel::default() };
+
+        let sprite = sprite.filter(|sprite| {
+            let behind_background = if self.cgb {
+                background_enabled
+                    && background.color_id != 0
+                    && (sprite.attributes & 0x80 != 0 || background.attributes & 0x80 != 0)
             } else {
-                self.current_scanline as i16 - y_pos
+                sprite.attributes & 0x80 != 0 && background.color_id != 0
             };
-    
-            let tile_address = 0x8000 + (tile_index as u16 * 16) + (line as u16 * 2);
-            if tile_address as usize >= self.vram.len() {
-                console_log!("Sprite tile address out of bounds: {}", tile_address);
-                continue;
+            !behind_background
+        });
+
+        match (self.cgb, sprite) {
+            (true, Some(sprite)) => {
+                let color = Self::palette_color(&self.obj_palette_ram, sprite.attributes & 0x07, sprite.color_id);
+                self.put_color(x, y, color);
             }
-    
-            let byte1 = self.vram[(tile_address - 0x8000) as usize];
-            let byte2 = self.vram[(tile_address - 0x8000 + 1) as usize];
-    
-            for x in 0..8 {
-                let color_bit = if attributes & 0x20 != 0 { x } else { 7 - x };
-                let color_id = ((byte1 >> color_bit) & 1) | (((byte2 >> color_bit) & 1) << 1);
-    
-                if color_id == 0 {
-                    continue;
-                }
-    
-                let palette = if attributes & 0x10 != 0 { self.sprite_palette_1 } else { self.sprite_palette_0 };
-                let color = self.get_color(color_id, palette);
-    
-                let pixel_x = x_pos + x;
-                if pixel_x < 0 || pixel_x >= 160 {
-                    continue;
-                }
-    
-                if attributes & 0x80 != 0 && self.frame_buffer[self.current_scanline as usize][pixel_x as usize] != 0xFF {
-                    continue;
-                }
-    
-                self.frame_buffer[self.current_scanline as usize][pixel_x as usize] = color;
+            (true, None) => {
+                let color = Self::palette_color(&self.bg_palette_ram, background.attributes & 0x07, background.color_id);
+                self.put_color(x, y, color);
+            }
+            (false, Some(sprite)) => {
+                let palette = if sprite.attributes & 0x10 != 0 { self.sprite_palette_1 } else { self.sprite_palette_0 };
+                self.put_pixel(x, y, self.get_shade(sprite.color_id, palette));
             }
-            sprites_rendered += 1;
+            (false, None) if !background_enabled => self.put_pixel(x, y, 0),
+            (false, None) => self.put_pixel(x, y, self.get_shade(background.color_id, self.background_palette)),
         }
     }
-    
 
+    /// Picks how lines are drawn. Switching mid-frame restarts the current
+    /// line's mode 3 if it was in progress.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+        self.resync_fifo();
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
 
     pub fn step(&mut self, cycles: u32) {
-    
+        match self.renderer {
+            Renderer::Scanline => self.step_scanline(cycles),
+            Renderer::PixelFifo => {
+                for _ in 0..cycles {
+                    self.tick_dot();
+                }
+            }
+        }
+        self.update_stat_interrupt();
+    }
+
+    fn step_scanline(&mut self, cycles: u32) {
+        if self.mode == GPUMode::OAM && self.mode_clock == 0 {
+            self.latch_window_y();
+        }
+
         self.mode_clock += cycles;
-    
+
         match self.mode {
             GPUMode::OAM => {
                 if self.mode_clock >= 80 {
@@ -205,129 +602,186 @@ impl GPU {
             GPUMode::VRAM => {
                 if self.mode_clock >= 172 {
                     self.mode_clock = 0;
-                    console_log!("Entering render_scanline");
+                    log_trace!("Entering render_scanline");
 
                     self.render_scanline();
-                    console_log!("Entering render_sprites");
-
-                    self.render_sprites();
-                    self.mode = GPUMode::HBlank;
+                    self.enter_hblank();
                 }
             }
             GPUMode::HBlank => {
                 if self.mode_clock >= 204 {
                     self.mode_clock = 0;
-                    self.current_scanline += 1;
-    
-                    if self.current_scanline == 144 {
-                        console_log!("VBlank started");
-
-                        self.mode = GPUMode::VBlank;
-                        self.request_vblank_interrupt();
-                    } else {
-                        self.mode = GPUMode::OAM;
-                    }
+                    self.next_line();
                 }
             }
             GPUMode::VBlank => {
                 if self.mode_clock >= 456 {
                     self.mode_clock = 0;
-                    self.current_scanline += 1;
-    
-                    if self.current_scanline > 153 {
-                        self.mode = GPUMode::OAM;
-                        self.current_scanline = 0;
-                    }
+                    self.next_line();
                 }
             }
         }
     }
-    
+
+    /// Checks WY at the start of a line. Once it has matched LY the window
+    /// stays enabled for the rest of the frame, whatever WY does next.
+    fn latch_window_y(&mut self) {
+        if self.current_scanline == self.window_y {
+            self.window_triggered = true;
+        }
+    }
+
+    fn enter_hblank(&mut self) {
+        self.mode = GPUMode::HBlank;
+        self.hblank_started = self.lcd_control & 0x80 != 0;
+    }
+
+    /// Moves LY on at the end of a line, entering VBlank after the last
+    /// visible line and starting a new frame after line 153.
+    fn next_line(&mut self) {
+        self.current_scanline += 1;
+
+        if self.current_scanline == 144 {
+            log_trace!("VBlank started");
+
+            self.mode = GPUMode::VBlank;
+            self.request_vblank_interrupt();
+            self.frame_complete = true;
+        } else if self.current_scanline > 153 {
+            self.mode = GPUMode::OAM;
+            self.current_scanline = 0;
+            self.window_line = 0;
+            self.window_triggered = false;
+        } else if self.mode != GPUMode::VBlank {
+            self.mode = GPUMode::OAM;
+        }
+    }
+
+    /// Requests the STAT interrupt on a rising edge of the STAT line, the
+    /// OR of the sources enabled in STAT bits 3-6.
+    fn update_stat_interrupt(&mut self) {
+        let line = self.lcd_control & 0x80 != 0
+            && ((self.lcd_status & 0x40 != 0 && self.current_scanline == self.ly_compare)
+                || (self.lcd_status & 0x20 != 0 && self.mode == GPUMode::OAM)
+                || (self.lcd_status & 0x10 != 0 && self.mode == GPUMode::VBlank)
+                || (self.lcd_status & 0x08 != 0 && self.mode == GPUMode::HBlank));
+        if line && !self.stat_line {
+            self.bus.borrow_mut().interrupt_flag |= 0x02;
+        }
+        self.stat_line = line;
+    }
+
     fn render_scanline(&mut self) {
-    
+
         if self.lcd_control & 0x80 == 0 {
-            console_log!("LCD is disabled, skipping scanline rendering");
+            log_trace!("LCD is disabled, skipping scanline rendering");
             return;
         }
-    
-        let tile_data = if self.lcd_control & 0x10 != 0 { 0x8000 } else { 0x8800 };
-        let tile_map = if self.lcd_control & 0x08 != 0 { 0x9C00 } else { 0x9800 };
-    
+
+        let background = self.render_background();
+        log_trace!("Entering render_sprites");
+        let sprites = self.render_sprites();
         for x in 0..160 {
-            let pixel = self.get_background_pixel(x, self.current_scanline, tile_data, tile_map);
-    
-            // Check for out-of-bounds frame buffer access
-            if self.current_scanline as usize >= self.frame_buffer.len() || x as usize >= self.frame_buffer[0].len() {
-                console_log!("Out of bounds frame buffer access at scanline: {}, x: {}", self.current_scanline, x);
-                continue;
-            }
-    
-            self.frame_buffer[self.current_scanline as usize][x as usize] = pixel;
-    
-            if pixel != 0 {
-                console_log!("Non-zero pixel written at ({}, {}): {}", x, self.current_scanline, pixel);
-            }
+            self.draw_pixel(x, background[x], sprites[x]);
         }
     }
-        
-    fn get_background_pixel(&self, x: u8, y: u8, tile_data: u16, tile_map: u16) -> u8 {
-        let map_offset = ((y as u16 / 8) * 32) + (x as u16 / 8);
-        let vram_index = (tile_map - 0x8000 + map_offset) as usize;
-    
-        // Log the tile number
-        console_log!("Fetching tile number from tile map at vram_index = {}", vram_index);
-        if vram_index >= self.vram.len() {
-            console_log!("Error: VRAM index out of bounds: {}", vram_index);
-            return 0;
+
+    /// Fetches the background and window pixels for the current line.
+    fn render_background(&mut self) -> [BackgroundPixel; 160] {
+        let mut line = [BackgroundPixel::default(); 160];
+        let y = self.current_scanline;
+
+        // A DMG with LCDC bit 0 clear shows neither, and the window doesn't
+        // count the line
+        if !self.cgb && self.lcd_control & 0x01 == 0 {
+            return line;
         }
-    
-        let tile_number = self.vram[vram_index];
-        console_log!("Tile number fetched: {}", tile_number);
-    
-        if tile_number == 0 {
-            console_log!("Tile number is zero - potential issue with tile map initialization");
+
+        let window_visible = self.lcd_control & 0x20 != 0 && self.window_triggered && self.window_x <= 166;
+        let mut window_drawn = false;
+
+        for x in 0..160u8 {
+            let in_window = window_visible && x as u16 + 7 >= self.window_x as u16;
+            let (map, map_x, map_y) = if in_window {
+                window_drawn = true;
+                let map = if self.lcd_control & 0x40 != 0 { 0x1C00 } else { 0x1800 };
+                (map, x + 7 - self.window_x, self.window_line)
+            } else {
+                let map = if self.lcd_control & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+                (map, x.wrapping_add(self.scroll_x), y.wrapping_add(self.scroll_y))
+            };
+
+            let map_index = map + (map_y as usize / 8) * 32 + map_x as usize / 8;
+            line[x as usize] = self.background_tile_row(map_index, map_y % 8)[map_x as usize % 8];
         }
-    
-        let tile_address = if tile_data == 0x8000 {
-            tile_data + (tile_number as u16 * 16)
+
+        if window_drawn {
+            self.window_line += 1;
+        }
+        line
+    }
+
+    /// Decodes row `row` of the tile at tile map entry `map_index` (a VRAM
+    /// offset), applying the entry's CGB attributes. Pixels are left to
+    /// right.
+    fn background_tile_row(&self, map_index: usize, row: u8) -> [BackgroundPixel; 8] {
+        let tile_number = self.vram[map_index];
+        let attributes = if self.cgb { self.vram[0x2000 + map_index] } else { 0 };
+        log_trace!("Tile number {} at map index {:#06X}", tile_number, map_index);
+
+        let tile_address = if self.lcd_control & 0x10 != 0 {
+            tile_number as usize * 16
         } else {
-            let signed_tile_number = tile_number as i8;
-            let base_address: u16 = 0x9000;
-            let tile_offset = (signed_tile_number as i16 * 16) as u16;
-            base_address.wrapping_add(tile_offset)
+            (0x1000 + tile_number as i8 as isize * 16) as usize
         };
-    
-        console_log!("Tile address calculated: {:X}", tile_address);
-        
-        // Log the fetched tile data
-        let byte1_index = (tile_address + 1 - 0x8000) as usize;
-        let byte2_index = (tile_address + 2 - 0x8000) as usize;
-        if byte1_index < self.vram.len() && byte2_index < self.vram.len() {
-            console_log!("Tile data byte1: {}, byte2: {}", self.vram[byte1_index], self.vram[byte2_index]);
-        }
-    
-        return 255; // Temporarily returning white for testing
-    }
-                        
-    fn get_color(&self, color_id: u8, palette: u8) -> u8 {
-        match (palette >> (color_id * 2)) & 0b11 {
-            0 => 0xFF, 
-            1 => 0xC0, 
-            2 => 0x80, 
-            3 => 0x00, 
-            _ => 0xFF,
+
+        let row = if attributes & 0x40 != 0 { 7 - row as usize } else { row as usize };
+        let bank = if attributes & 0x08 != 0 { 0x2000 } else { 0 };
+        let byte1 = self.vram[bank + tile_address + row * 2];
+        let byte2 = self.vram[bank + tile_address + row * 2 + 1];
+
+        let mut pixels = [BackgroundPixel::default(); 8];
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            let bit = if attributes & 0x20 != 0 { x } else { 7 - x };
+            let color_id = ((byte1 >> bit) & 1) | (((byte2 >> bit) & 1) << 1);
+            *pixel = BackgroundPixel { color_id, attributes };
         }
+        pixels
+    }
+
+    /// Maps a colour ID through a DMG palette register to a shade, 0
+    /// (white) to 3 (black).
+    fn get_shade(&self, color_id: u8, palette: u8) -> u8 {
+        (palette >> (color_id * 2)) & 0b11
     }
 
+    /// Reads colour `color_id` of CGB palette `palette` from palette RAM.
+    fn palette_color(ram: &[u8; 64], palette: u8, color_id: u8) -> u16 {
+        let index = palette as usize * 8 + color_id as usize * 2;
+        u16::from_le_bytes([ram[index], ram[index + 1]]) & 0x7FFF
+    }
+
+    /// Sets the VBlank bit in IF. IE only decides whether it's serviced.
     fn request_vblank_interrupt(&mut self) {
-        if self.bus.borrow_mut().interrupt_enable & 0x01 != 0 {
-            self.bus.borrow_mut().interrupt_flag |= 0x01;
-        }
-        }
+        self.bus.borrow_mut().interrupt_flag |= 0x01;
+    }
+}
 
-    fn request_lcd_interrupt(&mut self) {
-        if self.bus.borrow_mut().interrupt_enable & 0x01 != 0 {
-            self.bus.borrow_mut().interrupt_flag |= 0x01;
-        }
-        }
+/// Expands an RGB555 colour to RGBA8888. With `correct` set the channels
+/// are mixed and the range compressed the way the CGB's LCD does, which
+/// tames the oversaturated colours games were designed around.
+fn rgb555_to_rgba(color: u16, correct: bool) -> [u8; 4] {
+    let r = (color & 0x1F) as u32;
+    let g = ((color >> 5) & 0x1F) as u32;
+    let b = ((color >> 10) & 0x1F) as u32;
+    if correct {
+        [
+            ((r * 26 + g * 4 + b * 2).min(960) >> 2) as u8,
+            ((g * 24 + b * 8).min(960) >> 2) as u8,
+            ((r * 6 + g * 4 + b * 22).min(960) >> 2) as u8,
+            0xFF,
+        ]
+    } else {
+        [(r << 3 | r >> 2) as u8, (g << 3 | g >> 2) as u8, (b << 3 | b >> 2) as u8, 0xFF]
+    }
 }
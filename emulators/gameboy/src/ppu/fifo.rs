@@ -0,0 +1,228 @@
+//! The pixel FIFO renderer: mode 3 run a dot at a time, with a background
+//! fetcher feeding a pixel FIFO and sprites fetched into a second FIFO as
+//! the output reaches them.
+//!
+//! Timing follows the hardware closely enough for mid-line effects, not to
+//! the exact dot: mode 3 takes 172 dots plus the SCX fine scroll, the
+//! window restart and a stall for each sprite fetch.
+
+use std::collections::VecDeque;
+
+use super::{BackgroundPixel, GPUMode, Renderer, SpritePixel, GPU};
+
+/// Dots at the start of mode 3 before the first tile fetch, which with
+/// that fetch makes up the 12-dot minimum overhead.
+const STARTUP_DOTS: u8 = 6;
+
+/// Dots a sprite fetch holds up the output for, once the background
+/// fetcher has finished its tile.
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Default)]
+pub(super) struct Fifo {
+    line_dot: u16,                         // Dots since the line started
+    background: VecDeque<BackgroundPixel>, // Up to 8 pixels waiting to be shifted out
+    sprites: VecDeque<Option<SpritePixel>>, // Slot 0 lines up with the next pixel drawn
+    line_sprites: Vec<usize>,              // Sprites on this line not fetched yet, in priority order
+    sprite_fetch: Option<(usize, u8)>,     // Sprite being fetched and dots spent on it
+    fetcher_dots: u8,                      // Dots into the current tile fetch, 6 when the row is ready
+    fetcher_x: u8,                         // Tile column of the next fetch
+    map_index: usize,                      // VRAM offset of the tile map entry being fetched
+    tile_row: u8,                          // Row within that tile
+    window: bool,                          // Fetching window tiles instead of background
+    x: u8,                                 // Next column to draw (LX)
+    discard: u8,                           // Pixels still to drop for fine scroll
+    startup: u8,                           // Dots left before the first fetch
+}
+
+impl GPU {
+    /// Rebuilds the FIFO state, which isn't saved, from the mode and mode
+    /// clock. A line caught in mode 3 starts mode 3 again.
+    pub(super) fn resync_fifo(&mut self) {
+        self.fifo = Fifo {
+            line_dot: match self.mode {
+                GPUMode::OAM | GPUMode::VBlank => self.mode_clock as u16,
+                GPUMode::VRAM => 80,
+                GPUMode::HBlank => 252 + self.mode_clock as u16,
+            },
+            ..Fifo::default()
+        };
+        if self.renderer == Renderer::PixelFifo && self.mode == GPUMode::VRAM {
+            self.mode_clock = 0;
+            self.start_mode3();
+        }
+    }
+
+    fn start_mode3(&mut self) {
+        let line_sprites = self.sprites_on_line(self.sprite_height());
+        self.fifo = Fifo {
+            line_dot: self.fifo.line_dot,
+            line_sprites,
+            discard: self.scroll_x & 7,
+            startup: STARTUP_DOTS,
+            ..Fifo::default()
+        };
+    }
+
+    /// Advances the PPU by one dot.
+    pub(super) fn tick_dot(&mut self) {
+        self.mode_clock += 1;
+        self.fifo.line_dot += 1;
+
+        match self.mode {
+            GPUMode::OAM => {
+                if self.mode_clock == 1 {
+                    self.latch_window_y();
+                }
+                if self.mode_clock >= 80 {
+                    self.mode_clock = 0;
+                    self.mode = GPUMode::VRAM;
+                    self.start_mode3();
+                }
+            }
+            GPUMode::VRAM => {
+                let done = if self.lcd_control & 0x80 == 0 {
+                    self.mode_clock >= 172
+                } else {
+                    self.tick_mode3();
+                    self.fifo.x == 160
+                };
+                if done {
+                    if self.fifo.window {
+                        self.window_line += 1;
+                    }
+                    self.mode_clock = 0;
+                    self.enter_hblank();
+                }
+            }
+            GPUMode::HBlank | GPUMode::VBlank => {
+                if self.fifo.line_dot >= 456 {
+                    self.mode_clock = 0;
+                    self.fifo.line_dot = 0;
+                    self.next_line();
+                }
+            }
+        }
+        self.update_stat_interrupt();
+    }
+
+    /// One dot of mode 3: start the window or a sprite fetch if due, run
+    /// the fetchers, and shift out a pixel unless a sprite fetch stalls it.
+    fn tick_mode3(&mut self) {
+        if self.fifo.startup > 0 {
+            self.fifo.startup -= 1;
+            return;
+        }
+
+        let window_enabled = self.lcd_control & 0x20 != 0 && (self.cgb || self.lcd_control & 0x01 != 0);
+        if !self.fifo.window
+            && window_enabled
+            && self.window_triggered
+            && self.window_x <= 166
+            && self.fifo.discard == 0
+            && self.fifo.x as u16 + 7 >= self.window_x as u16
+        {
+            // The window restarts the fetcher and drops whatever background
+            // was queued; with WX below 7 its first columns are off screen
+            self.fifo.window = true;
+            self.fifo.background.clear();
+            self.fifo.fetcher_x = 0;
+            self.fifo.fetcher_dots = 0;
+            if self.fifo.x == 0 {
+                self.fifo.discard = 7u8.saturating_sub(self.window_x);
+            }
+        }
+
+        if self.fifo.sprite_fetch.is_none() && self.lcd_control & 0x02 != 0 {
+            let x = self.fifo.x as i16;
+            let due = self.fifo.line_sprites.iter().position(|&i| self.oam[i * 4 + 1] as i16 - 8 <= x);
+            if let Some(position) = due {
+                let sprite = self.fifo.line_sprites.remove(position);
+                self.fifo.sprite_fetch = Some((sprite, 0));
+            }
+        }
+
+        if let Some((sprite, dots)) = self.fifo.sprite_fetch {
+            // The background fetcher gets to finish its tile first
+            if self.fifo.fetcher_dots < 6 {
+                self.tick_fetcher();
+            } else if dots + 1 < SPRITE_FETCH_DOTS {
+                self.fifo.sprite_fetch = Some((sprite, dots + 1));
+            } else {
+                self.merge_sprite(sprite);
+                self.fifo.sprite_fetch = None;
+            }
+            return;
+        }
+
+        self.tick_fetcher();
+
+        let Some(background) = self.fifo.background.pop_front() else {
+            return;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        let sprite = self.fifo.sprites.pop_front().flatten();
+        self.draw_pixel(self.fifo.x as usize, background, sprite);
+        self.fifo.x += 1;
+    }
+
+    /// One dot of the background fetcher: two dots each for the tile
+    /// number and its two bitplanes, then a push once the FIFO is empty.
+    fn tick_fetcher(&mut self) {
+        if self.fifo.fetcher_dots < 6 {
+            self.fifo.fetcher_dots += 1;
+            if self.fifo.fetcher_dots == 2 {
+                // The tile is picked with the scroll registers as they are now
+                let (map, column, y) = if self.fifo.window {
+                    let map = if self.lcd_control & 0x40 != 0 { 0x1C00 } else { 0x1800 };
+                    (map, self.fifo.fetcher_x, self.window_line)
+                } else {
+                    let map = if self.lcd_control & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+                    let column = (self.scroll_x / 8).wrapping_add(self.fifo.fetcher_x);
+                    (map, column, self.current_scanline.wrapping_add(self.scroll_y))
+                };
+                self.fifo.map_index = map + (y as usize / 8) * 32 + (column & 31) as usize;
+                self.fifo.tile_row = y % 8;
+            }
+            return;
+        }
+
+        if self.fifo.background.is_empty() {
+            let row = self.background_tile_row(self.fifo.map_index, self.fifo.tile_row);
+            self.fifo.background.extend(row);
+            self.fifo.fetcher_x = self.fifo.fetcher_x.wrapping_add(1);
+            self.fifo.fetcher_dots = 0;
+        }
+    }
+
+    /// Mixes a fetched sprite row into the sprite FIFO. On DMG, pixels
+    /// already there win, as they came from sprites further left or earlier
+    /// in OAM; on CGB the lower OAM index wins.
+    fn merge_sprite(&mut self, sprite: usize) {
+        let row = self.sprite_row(sprite, self.sprite_height());
+        let left = self.oam[sprite * 4 + 1] as i16 - 8;
+        let attributes = self.oam[sprite * 4 + 3];
+
+        for (offset, color_id) in row.into_iter().enumerate() {
+            let slot = left + offset as i16 - self.fifo.x as i16;
+            if slot < 0 || color_id == 0 {
+                continue;
+            }
+            let slot = slot as usize;
+            if self.fifo.sprites.len() <= slot {
+                self.fifo.sprites.resize(slot + 1, None);
+            }
+            let pixel = SpritePixel { color_id, attributes, oam_index: sprite as u8 };
+            let replace = match self.fifo.sprites[slot] {
+                None => true,
+                Some(existing) => self.cgb && pixel.oam_index < existing.oam_index,
+            };
+            if replace {
+                self.fifo.sprites[slot] = Some(pixel);
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! The Game Boy Printer, a serial device that takes image data in packets
+//! and prints it on thermal paper.
+//!
+//! Every packet is `88 33`, a command, a compression flag, a 16-bit
+//! little-endian length, the data, a 16-bit checksum of everything from
+//! the command on, and two bytes the printer answers with 0x81 and its
+//! status.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use emu_common::log_debug;
+use emu_common::palette::Palette;
+
+use crate::serial::SerialDevice;
+
+const INITIALIZE: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+/// Status bits sent back at the end of each packet.
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const IMAGE_DATA_FULL: u8 = 0x04;
+const UNPROCESSED_DATA: u8 = 0x08;
+
+/// The printer's buffer holds nine data packets, 160x144 pixels.
+const BUFFER_LEN: usize = 0x1680;
+const TILES_PER_ROW: usize = 20;
+
+/// Status replies during which the printer reports it's still printing.
+const PRINT_BUSY_REPLIES: u8 = 2;
+
+/// Blank rows fed per unit of the print command's margins, a rough match
+/// for the paper the real printer feeds.
+const MARGIN_ROWS: usize = 8;
+
+/// A finished print, 160 pixels wide, in RGBA8888.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrintedImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// Where in a packet the next byte falls.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Stage {
+    #[default]
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+#[derive(Default)]
+struct State {
+    stage: Stage,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,   // Packet data as received, still compressed
+    checksum: u16,   // Running sum from the command byte
+    received: u16,   // Checksum sent by the Game Boy
+    status: u8,
+    busy_replies: u8,
+    buffer: Vec<u8>, // Decompressed tile data waiting to be printed
+    prints: Vec<PrintedImage>,
+}
+
+/// A Game Boy Printer to plug into `Emulator::connect_serial`. Clones share
+/// one printer, so keep a clone to collect what it prints.
+#[derive(Clone, Default)]
+pub struct Printer {
+    state: Rc<RefCell<State>>,
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns and clears the images printed since the last call, oldest
+    /// first.
+    pub fn take_prints(&self) -> Vec<PrintedImage> {
+        std::mem::take(&mut self.state.borrow_mut().prints)
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.state.borrow_mut().receive(byte)
+    }
+}
+
+impl State {
+    /// Takes the next byte of a packet and returns the printer's reply,
+    /// which is 0 apart from the last two bytes.
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.stage = match self.stage {
+            // Anything but the magic bytes is noise between packets
+            Stage::Magic1 if byte == 0x88 => Stage::Magic2,
+            Stage::Magic1 => Stage::Magic1,
+            Stage::Magic2 if byte == 0x33 => Stage::Command,
+            Stage::Magic2 => Stage::Magic1,
+            Stage::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                Stage::Compression
+            }
+            Stage::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Stage::LengthLow
+            }
+            Stage::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                Stage::LengthHigh
+            }
+            Stage::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                if self.length == 0 { Stage::ChecksumLow } else { Stage::Data }
+            }
+            Stage::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length as usize { Stage::ChecksumLow } else { Stage::Data }
+            }
+            Stage::ChecksumLow => {
+                self.received = byte as u16;
+                Stage::ChecksumHigh
+            }
+            Stage::ChecksumHigh => {
+                self.received |= (byte as u16) << 8;
+                if self.received == self.checksum {
+                    self.status &= !CHECKSUM_ERROR;
+                    self.run_command();
+                } else {
+                    self.status |= CHECKSUM_ERROR;
+                }
+                Stage::Alive
+            }
+            Stage::Alive => {
+                reply = 0x81;
+                Stage::Status
+            }
+            Stage::Status => {
+                reply = self.status;
+                if self.busy_replies > 0 {
+                    self.busy_replies -= 1;
+                    if self.busy_replies == 0 {
+                        self.status &= !PRINTING;
+                    }
+                }
+                Stage::Magic1
+            }
+        };
+        reply
+    }
+
+    fn run_command(&mut self) {
+        match self.command {
+            INITIALIZE => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_replies = 0;
+            }
+            DATA => {
+                // An empty packet marks the end of the image
+                if self.data.is_empty() {
+                    self.status |= IMAGE_DATA_FULL;
+                    return;
+                }
+                let data = if self.compressed { decompress(&self.data) } else { std::mem::take(&mut self.data) };
+                let room = BUFFER_LEN - self.buffer.len();
+                self.buffer.extend_from_slice(&data[..data.len().min(room)]);
+                self.status |= UNPROCESSED_DATA;
+                if self.buffer.len() == BUFFER_LEN {
+                    self.status |= IMAGE_DATA_FULL;
+                }
+            }
+            PRINT if self.data.len() == 4 => {
+                // Games that leave the palette at 0 expect the usual one
+                let (margins, palette) = (self.data[1], self.data[2]);
+                let palette = if palette == 0 { 0xE4 } else { palette };
+                self.print(margins, palette);
+                self.buffer.clear();
+                self.status = (self.status & !(UNPROCESSED_DATA | IMAGE_DATA_FULL)) | PRINTING;
+                self.busy_replies = PRINT_BUSY_REPLIES;
+            }
+            // Status inquiries only want the reply
+            STATUS => {}
+            _ => log_debug!("Ignoring printer command {:#04X}", self.command),
+        }
+    }
+
+    /// Turns the buffered tiles into an image, mapping colour IDs to
+    /// shades through `palette` as BGP does, with `margins`' high and low
+    /// nibbles of blank paper fed before and after.
+    fn print(&mut self, margins: u8, palette: u8) {
+        let tile_rows = self.buffer.len() / (TILES_PER_ROW * 16);
+        let (before, after) = ((margins >> 4) as usize * MARGIN_ROWS, (margins & 0x0F) as usize * MARGIN_ROWS);
+        let height = before + tile_rows * 8 + after;
+        let paper = Palette::GREY.color(0);
+        let mut rgba: Vec<u8> = paper.repeat(160 * height);
+
+        for tile_row in 0..tile_rows {
+            for tile in 0..TILES_PER_ROW {
+                let address = (tile_row * TILES_PER_ROW + tile) * 16;
+                for row in 0..8 {
+                    let (low, high) = (self.buffer[address + row * 2], self.buffer[address + row * 2 + 1]);
+                    for x in 0..8 {
+                        let bit = 7 - x;
+                        let color_id = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                        let shade = (palette >> (color_id * 2)) & 0b11;
+                        let pixel = (before + tile_row * 8 + row) * 160 + tile * 8 + x;
+                        rgba[pixel * 4..pixel * 4 + 4].copy_from_slice(&Palette::GREY.color(shade));
+                    }
+                }
+            }
+        }
+        self.prints.push(PrintedImage { width: 160, height: height as u32, rgba });
+    }
+}
+
+/// Expands the printer's run-length encoding: a byte with bit 7 clear is
+/// followed by that many plus one literal bytes, one with bit 7 set by a
+/// single byte repeated its low bits plus two times.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut bytes = data.iter().copied();
+    while let Some(control) = bytes.next() {
+        if control & 0x80 != 0 {
+            let Some(value) = bytes.next() else { break };
+            output.extend(std::iter::repeat_n(value, (control & 0x7F) as usize + 2));
+        } else {
+            output.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+    output
+}
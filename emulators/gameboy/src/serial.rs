@@ -0,0 +1,153 @@
+//! The serial port: SB (0xFF01) and SC (0xFF02), and whatever is plugged
+//! into the other end of the link cable.
+//!
+//! Transfers are modelled a byte at a time. The side running on the
+//! internal clock shifts for 8 bit times and then swaps its byte with the
+//! connected device in one go; a side waiting on the external clock only
+//! completes when a partner clocks a byte into it.
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use emu_common::state::{StateReader, StateWriter};
+
+/// CPU clocks per byte at 8192 Hz, and at 262144 Hz with the CGB fast
+/// clock (SC bit 1). Double speed doubles the serial clock too, so these
+/// count CPU clocks at either speed.
+const CLOCKS_PER_BYTE: u32 = 4096;
+const FAST_CLOCKS_PER_BYTE: u32 = 128;
+
+/// Something on the other end of the link cable, clocked by the Game Boy.
+pub trait SerialDevice {
+    /// Called when the Game Boy finishes clocking out `byte` on its
+    /// internal clock. Returns the byte the device shifted back.
+    fn exchange(&mut self, byte: u8) -> u8;
+}
+
+#[derive(Default)]
+pub struct SerialPort {
+    data: u8,                               // SB
+    control: u8,                            // SC bits 7, 1 and 0
+    clocks: u32,                            // Clocks left in an internally clocked transfer
+    interrupt: bool,                        // A transfer finished since the bus last looked
+    output: Vec<u8>,                        // Every byte sent, for test harnesses
+    device: Option<Box<dyn SerialDevice>>,  // The other end of the cable, if anything
+}
+
+impl SerialPort {
+    pub fn new() -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self::default()))
+    }
+
+    /// Bytes written by `save_state`.
+    pub const STATE_LEN: usize = 2 + 4;
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_u8(self.data);
+        writer.put_u8(self.control);
+        writer.put_u32(self.clocks);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) {
+        self.data = reader.u8();
+        self.control = reader.u8() & 0x83;
+        self.clocks = reader.u32().min(CLOCKS_PER_BYTE);
+    }
+
+    /// Puts the registers back to power-on values, keeping the device.
+    pub fn reset(&mut self) {
+        *self = SerialPort { device: self.device.take(), ..SerialPort::default() };
+    }
+
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = Some(device);
+    }
+
+    pub fn disconnect(&mut self) {
+        self.device = None;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        self.data = value;
+    }
+
+    /// Unused bits read as 1, and bit 1 only exists on CGB.
+    pub fn read_control(&self, cgb: bool) -> u8 {
+        self.control | if cgb { 0x7C } else { 0x7E }
+    }
+
+    /// Setting bit 7 starts a transfer. On the internal clock it runs for
+    /// a byte's worth of clocks; on the external clock it waits for a
+    /// partner.
+    pub fn write_control(&mut self, value: u8, cgb: bool) {
+        self.control = value & if cgb { 0x83 } else { 0x81 };
+        if self.control & 0x81 == 0x81 {
+            self.output.push(self.data);
+            self.clocks = if self.control & 0x02 != 0 { FAST_CLOCKS_PER_BYTE } else { CLOCKS_PER_BYTE };
+        }
+    }
+
+    /// Runs an internally clocked transfer for `cycles` CPU clocks,
+    /// swapping bytes with the device when it completes. With nothing
+    /// connected 0xFF shifts in.
+    pub fn step(&mut self, cycles: u32) {
+        if self.control & 0x81 != 0x81 {
+            return;
+        }
+        self.clocks = self.clocks.saturating_sub(cycles);
+        if self.clocks == 0 {
+            let byte = self.data;
+            let received = self.device.as_mut().map_or(0xFF, |device| device.exchange(byte));
+            self.finish(received);
+        }
+    }
+
+    /// Completes a transfer this side was clocked into by a partner.
+    /// Returns the byte that was in SB, or `None`, with nothing shifted,
+    /// if no transfer was waiting on the external clock.
+    fn clock_in(&mut self, byte: u8) -> Option<u8> {
+        if self.control & 0x81 != 0x80 {
+            return None;
+        }
+        let sent = self.data;
+        self.output.push(sent);
+        self.finish(byte);
+        Some(sent)
+    }
+
+    fn finish(&mut self, received: u8) {
+        self.data = received;
+        self.control &= 0x7F;
+        self.interrupt = true;
+    }
+
+    /// Returns and clears whether a transfer finished, which requests the
+    /// serial interrupt.
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt)
+    }
+
+    /// Returns and clears the bytes sent since the last call.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+}
+
+/// Another Game Boy's serial port, as seen down the link cable. Held
+/// weakly so that two linked ports don't keep each other alive.
+pub(crate) struct Peer(pub(crate) Weak<RefCell<SerialPort>>);
+
+impl SerialDevice for Peer {
+    /// A partner that isn't waiting on the external clock doesn't shift,
+    /// so the line stays high.
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.0
+            .upgrade()
+            .and_then(|port| port.borrow_mut().clock_in(byte))
+            .unwrap_or(0xFF)
+    }
+}
@@ -0,0 +1,111 @@
+//! The timer: DIV (0xFF04), TIMA (0xFF05), TMA (0xFF06) and TAC (0xFF07).
+//!
+//! DIV is the top byte of a 16-bit counter that runs at the CPU clock.
+//! TIMA counts falling edges of one counter bit, chosen by TAC and gated
+//! by its enable bit, so resetting DIV or changing TAC can tick TIMA just
+//! as it does on a DMG.
+
+use emu_common::state::{StateReader, StateWriter};
+
+/// The counter as the DMG boot ROM leaves it at 0x100.
+const BOOT_COUNTER: u16 = 0xABCC;
+
+#[derive(Clone)]
+pub struct Timer {
+    counter: u16,    // DIV is the high byte
+    tima: u8,
+    tma: u8,
+    tac: u8,         // Bits 2-0
+    interrupt: bool, // TIMA overflowed since the bus last looked
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Timer { counter: BOOT_COUNTER, tima: 0, tma: 0, tac: 0, interrupt: false }
+    }
+}
+
+impl Timer {
+    /// Bytes written by `save_state`.
+    pub const STATE_LEN: usize = 2 + 3;
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.put_u16(self.counter);
+        writer.put_u8(self.tima);
+        writer.put_u8(self.tma);
+        writer.put_u8(self.tac);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) {
+        self.counter = reader.u16();
+        self.tima = reader.u8();
+        self.tma = reader.u8();
+        self.tac = reader.u8() & 0x07;
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            _ => 0xF8 | self.tac,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xFF04 => self.set_counter(0),
+            0xFF05 => self.tima = value,
+            0xFF06 => self.tma = value,
+            _ => {
+                let before = self.signal();
+                self.tac = value & 0x07;
+                if before && !self.signal() {
+                    self.increment_tima();
+                }
+            }
+        }
+    }
+
+    /// Runs the counter for `cycles` CPU clocks, a machine cycle at a time
+    /// so that no edge of the fastest TIMA rate is missed.
+    pub fn step(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            self.set_counter(self.counter.wrapping_add(4));
+        }
+    }
+
+    /// Returns and clears whether TIMA overflowed, which requests the
+    /// timer interrupt.
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.interrupt)
+    }
+
+    /// The input TIMA counts falling edges of.
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9, // 4096 Hz
+            1 => 3, // 262144 Hz
+            2 => 5, // 65536 Hz
+            _ => 7, // 16384 Hz
+        };
+        self.tac & 0x04 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn set_counter(&mut self, counter: u16) {
+        let before = self.signal();
+        self.counter = counter;
+        if before && !self.signal() {
+            self.increment_tima();
+        }
+    }
+
+    /// On overflow TIMA reloads from TMA. The hardware takes another
+    /// machine cycle to do so, reading 0 in between; that delay isn't
+    /// emulated.
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = if overflow { self.tma } else { tima };
+        self.interrupt |= overflow;
+    }
+}
@@ -0,0 +1,25 @@
+use crate::debugger::RegisterDump;
+pub use emu_common::trace::Tracer;
+
+/// Formats CPU state before an instruction the way Game Boy Doctor expects:
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`,
+/// where PCMEM holds the four bytes at PC.
+pub fn doctor_line(registers: &RegisterDump, pcmem: [u8; 4]) -> String {
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        registers.af >> 8,
+        registers.af & 0xFF,
+        registers.bc >> 8,
+        registers.bc & 0xFF,
+        registers.de >> 8,
+        registers.de & 0xFF,
+        registers.hl >> 8,
+        registers.hl & 0xFF,
+        registers.sp,
+        registers.pc,
+        pcmem[0],
+        pcmem[1],
+        pcmem[2],
+        pcmem[3],
+    )
+}
@@ -0,0 +1,321 @@
+//! The JavaScript API, a thin layer over `crate::Emulator` that turns Rust
+//! results into JS values.
+
+use wasm_bindgen::prelude::*;
+pub use wasm_bindgen::memory;
+
+use crate::debugger::StopReason;
+use emu_common::logging;
+use emu_common::palette::Palette;
+use crate::{Button, LinkCable, Printer, Renderer};
+
+#[wasm_bindgen]
+pub struct Emulator {
+    emulator: crate::Emulator,
+    printer: Option<Printer>,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom_data: Vec<u8>) -> Self {
+        Emulator { emulator: crate::Emulator::new(rom_data), printer: None }
+    }
+
+    pub fn load_rom(&mut self, rom_data: Vec<u8>) {
+        self.emulator.load_rom(rom_data);
+    }
+
+    pub fn step(&mut self) {
+        self.emulator.step();
+    }
+
+    /// Runs until the next VBlank, ignoring breakpoints and watchpoints.
+    pub fn run_frame(&mut self) {
+        self.emulator.run_frame();
+    }
+
+    /// Restarts the loaded ROM from power-on.
+    pub fn reset(&mut self) {
+        self.emulator.reset();
+    }
+
+    /// Steps back to the latest rewind snapshot. Returns false when there is
+    /// no history left.
+    pub fn rewind_step(&mut self) -> bool {
+        self.emulator.rewind_step()
+    }
+
+    /// Takes a rewind snapshot every `frames` frames.
+    pub fn set_rewind_interval(&mut self, frames: u32) {
+        self.emulator.set_rewind_interval(frames);
+    }
+
+    /// Caps the memory used by rewind history; 0 turns rewinding off.
+    pub fn set_rewind_budget(&mut self, bytes: usize) {
+        self.emulator.set_rewind_budget(bytes);
+    }
+
+    pub fn get_frame_buffer(&self) -> *const u8 {
+        self.emulator.frame_buffer_ptr()
+    }
+
+    pub fn get_frame_buffer_length(&self) -> usize {
+        self.emulator.frame_buffer_len()
+    }
+
+    /// Turns on RGBA output in a preset palette: `dmg`, `pocket` or `grey`.
+    pub fn set_palette(&mut self, name: &str) -> Result<(), JsValue> {
+        let palette = Palette::named(name).ok_or_else(|| JsValue::from_str(&format!("unknown palette `{}`", name)))?;
+        self.emulator.set_palette(Some(palette));
+        Ok(())
+    }
+
+    /// Turns on RGBA output in four `0xRRGGBB` colours, lightest first.
+    pub fn set_custom_palette(&mut self, colors: Vec<u32>) -> Result<(), JsValue> {
+        let colors: [u32; 4] =
+            colors.try_into().map_err(|_| JsValue::from_str("a palette needs exactly four colours"))?;
+        self.emulator.set_palette(Some(Palette::custom(colors)));
+        Ok(())
+    }
+
+    /// Goes back to grey output only.
+    pub fn clear_palette(&mut self) {
+        self.emulator.set_palette(None);
+    }
+
+    /// Switches to the pixel FIFO renderer, which shows mid-line register
+    /// changes, or back to the faster scanline renderer.
+    pub fn set_pixel_fifo(&mut self, enabled: bool) {
+        let renderer = if enabled { Renderer::PixelFifo } else { Renderer::Scanline };
+        self.emulator.set_renderer(renderer);
+    }
+
+    /// True if the ROM runs in CGB mode, in which case the RGBA buffer is
+    /// always available and palettes don't apply.
+    pub fn is_cgb(&self) -> bool {
+        self.emulator.is_cgb()
+    }
+
+    /// Turns CGB colour correction on (the default) or off.
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.emulator.set_color_correction(enabled);
+    }
+
+    /// Address of the RGBA frame buffer, for wrapping in an `ImageData`. Ask
+    /// again after changing the palette or loading a ROM, as it moves.
+    pub fn get_rgba_buffer(&self) -> *const u8 {
+        self.emulator.rgba_frame_buffer_ptr()
+    }
+
+    /// Length of the RGBA frame buffer in bytes; 0 for a DMG game without a
+    /// palette.
+    pub fn get_rgba_buffer_length(&self) -> usize {
+        self.emulator.rgba_frame_buffer_len()
+    }
+
+    /// Reads memory without triggering watchpoints.
+    pub fn read_byte(&self, address: u16) -> u8 {
+        self.emulator.read_byte(address)
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        self.emulator.write_byte(address, value);
+    }
+
+    /// Presses or releases a button: 0-7 are right, left, up, down, A, B,
+    /// select and start. Other values are ignored.
+    pub fn set_button(&mut self, button: u8, pressed: bool) {
+        if let Some(&button) = Button::ALL.get(button as usize) {
+            self.emulator.set_button(button, pressed);
+        }
+    }
+
+    /// Returns and clears the bytes sent over the serial port since the
+    /// last call.
+    pub fn take_serial_output(&mut self) -> Vec<u8> {
+        self.emulator.take_serial_output()
+    }
+
+    /// Connects this Game Boy's link port to `other`'s. Both keep running
+    /// on their own; step them in turn so neither gets far ahead.
+    pub fn link_with(&mut self, other: &mut Emulator) {
+        LinkCable::connect(&mut self.emulator, &mut other.emulator);
+        self.printer = None;
+        other.printer = None;
+    }
+
+    /// Unplugs the link cable or printer from this side.
+    pub fn unlink(&mut self) {
+        self.emulator.disconnect_serial();
+        self.printer = None;
+    }
+
+    /// Plugs a Game Boy Printer into the link port, replacing any cable.
+    pub fn connect_printer(&mut self) {
+        let printer = Printer::new();
+        self.emulator.connect_serial(Box::new(printer.clone()));
+        self.printer = Some(printer);
+    }
+
+    /// Returns and clears the printer's finished prints, oldest first, as
+    /// an array of RGBA8888 `Uint8Array`s 160 pixels wide and
+    /// `length / 640` tall. Empty if no printer is connected.
+    pub fn take_prints(&mut self) -> JsValue {
+        let array = js_sys::Array::new();
+        for print in self.printer.iter().flat_map(Printer::take_prints) {
+            array.push(&js_sys::Uint8Array::from(&print.rgba[..]));
+        }
+        array.into()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.emulator.add_breakpoint(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.emulator.remove_breakpoint(address);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.emulator.clear_breakpoints();
+    }
+
+    pub fn get_breakpoints(&self) -> Vec<u16> {
+        self.emulator.breakpoints()
+    }
+
+    /// Stops execution when any address in `start..=end` is read and/or written.
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, on_read: bool, on_write: bool) {
+        self.emulator.add_watchpoint(start, end, on_read, on_write);
+    }
+
+    pub fn remove_watchpoint(&mut self, start: u16, end: u16) {
+        self.emulator.remove_watchpoint(start, end);
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.emulator.clear_watchpoints();
+    }
+
+    /// Executes exactly one instruction. Returns a stop object
+    /// `{ reason, pc, address?, value?, access? }`.
+    pub fn step_instruction(&mut self) -> JsValue {
+        let reason = self.emulator.debug_step();
+        stop_to_js(reason, self.emulator.pc())
+    }
+
+    /// Like `step_instruction`, but runs a CALL or RST until it returns.
+    pub fn step_over(&mut self) -> JsValue {
+        let reason = self.emulator.debug_step_over();
+        stop_to_js(reason, self.emulator.pc())
+    }
+
+    /// Runs until the current subroutine returns to its caller.
+    pub fn step_out(&mut self) -> JsValue {
+        let reason = self.emulator.debug_step_out();
+        stop_to_js(reason, self.emulator.pc())
+    }
+
+    /// Runs until the next VBlank, stopping early at breakpoints and watchpoints.
+    pub fn run_to_frame(&mut self) -> JsValue {
+        let reason = self.emulator.debug_run_to_frame();
+        stop_to_js(reason, self.emulator.pc())
+    }
+
+    /// Returns `{ af, bc, de, hl, sp, pc, ime, halted, locked }`.
+    pub fn get_registers(&self) -> JsValue {
+        let registers = self.emulator.registers();
+        let object = js_sys::Object::new();
+        set_property(&object, "af", registers.af.into());
+        set_property(&object, "bc", registers.bc.into());
+        set_property(&object, "de", registers.de.into());
+        set_property(&object, "hl", registers.hl.into());
+        set_property(&object, "sp", registers.sp.into());
+        set_property(&object, "pc", registers.pc.into());
+        set_property(&object, "ime", registers.ime.into());
+        set_property(&object, "halted", registers.halted.into());
+        set_property(&object, "locked", registers.locked.into());
+        object.into()
+    }
+
+    /// Disassembles `count` instructions starting at `address` without
+    /// executing anything. Returns an array of
+    /// `{ address, bytes, text, cycles, branch_cycles? }`.
+    pub fn disassemble(&self, address: u16, count: usize) -> JsValue {
+        let array = js_sys::Array::new();
+        for line in self.emulator.disassemble(address, count) {
+            let object = js_sys::Object::new();
+            set_property(&object, "address", line.address.into());
+            set_property(&object, "bytes", js_sys::Uint8Array::from(&line.bytes[..]).into());
+            set_property(&object, "text", line.text.into());
+            set_property(&object, "cycles", line.cycles.into());
+            if let Some(cycles) = line.branch_cycles {
+                set_property(&object, "branch_cycles", cycles.into());
+            }
+            array.push(&object);
+        }
+        array.into()
+    }
+
+    /// Starts logging every instruction in Game Boy Doctor format, keeping
+    /// the most recent `capacity` lines.
+    pub fn start_trace(&mut self, capacity: usize) {
+        self.emulator.start_trace(capacity);
+    }
+
+    pub fn stop_trace(&mut self) {
+        // The in-memory ring has nothing to flush
+        let _ = self.emulator.finish_trace();
+    }
+
+    /// Returns and clears the buffered trace, one instruction per line.
+    pub fn take_trace(&mut self) -> String {
+        self.emulator.take_trace().join("\n")
+    }
+
+    /// Snapshots CPU, bus and PPU state as a versioned binary blob. The ROM
+    /// is referenced by checksum rather than copied.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.emulator.save_state()
+    }
+
+    /// Restores a blob from `save_state`. Throws, leaving the emulator as it
+    /// was, if the blob is corrupted, from another version or another ROM.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.emulator
+            .load_state(data)
+            .map_err(|error| JsValue::from_str(&error.to_string()))
+    }
+}
+
+fn set_property(object: &js_sys::Object, key: &str, value: JsValue) {
+    let _ = js_sys::Reflect::set(object, &key.into(), &value);
+}
+
+fn stop_to_js(reason: StopReason, pc: u16) -> JsValue {
+    let object = js_sys::Object::new();
+    let name = match reason {
+        StopReason::Step => "step",
+        StopReason::Breakpoint(_) => "breakpoint",
+        StopReason::Watchpoint(_) => "watchpoint",
+        StopReason::FrameComplete => "frame",
+        StopReason::InstructionLimit => "limit",
+    };
+    set_property(&object, "reason", name.into());
+    set_property(&object, "pc", pc.into());
+    if let StopReason::Watchpoint(hit) = reason {
+        set_property(&object, "address", hit.address.into());
+        set_property(&object, "value", hit.value.into());
+        set_property(&object, "access", if hit.write { "write" } else { "read" }.into());
+    }
+    object.into()
+}
+
+/// Sets the runtime log level: 0 silences logging, 1-5 select error, warn,
+/// info, debug or trace. Levels compiled out with the `max_level_*` cargo
+/// features stay silent regardless.
+#[wasm_bindgen]
+pub fn set_log_level(level: u8) {
+    logging::set_level(logging::Level::from_u8(level));
+}
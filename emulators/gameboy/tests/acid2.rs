@@ -0,0 +1,126 @@
+//! Runs Matt Currie's dmg-acid2 and cgb-acid2 and compares the final frame
+//! pixel for pixel with the reference screenshots, under both renderers.
+//!
+//! Like the other test ROMs these are ignored by default and read from
+//! `$GB_TEST_ROMS/acid2` (or `tests/roms/acid2`): `dmg-acid2.gb` with
+//! `dmg-acid2.png`, and `cgb-acid2.gbc` with `cgb-acid2.png`, from the
+//! releases at https://github.com/mattcurrie/dmg-acid2 and
+//! https://github.com/mattcurrie/cgb-acid2 (the screenshots are in each
+//! repository's `img/reference-*.png`). Run them with
+//! `cargo test --test acid2 -- --ignored`; a missing file fails the test.
+//!
+//! On a mismatch the frame and a diff image, with wrong pixels in red over
+//! a faded reference, are written next to the test binary's temporary
+//! files.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use emu_common::palette::Palette;
+use gameboy::{Emulator, Renderer};
+
+/// Both tests draw their face within a few frames and then spin.
+const FRAMES: u32 = 30;
+
+/// The shades the dmg-acid2 reference was captured with.
+const DMG_REFERENCE: Palette = Palette::custom([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]);
+
+#[test]
+#[ignore = "needs dmg-acid2 in $GB_TEST_ROMS/acid2; see the module docs"]
+fn dmg_acid2() {
+    // A palette so the DMG frame comes out as RGBA like the CGB one
+    run_acid2("dmg-acid2.gb", "dmg-acid2.png", |emulator| emulator.set_palette(Some(DMG_REFERENCE)));
+}
+
+#[test]
+#[ignore = "needs cgb-acid2 in $GB_TEST_ROMS/acid2; see the module docs"]
+fn cgb_acid2() {
+    // The reference scales RGB555 linearly to RGB888
+    run_acid2("cgb-acid2.gbc", "cgb-acid2.png", |emulator| emulator.set_color_correction(false));
+}
+
+fn run_acid2(rom_name: &str, reference_name: &str, configure: fn(&mut Emulator)) {
+    let root = std::env::var_os("GB_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms"));
+    let directory = root.join("acid2");
+    let rom_path = directory.join(rom_name);
+    let rom = std::fs::read(&rom_path)
+        .unwrap_or_else(|error| panic!("{}: {}; see the module docs for where to get it", rom_path.display(), error));
+    let reference = read_png(&directory.join(reference_name));
+
+    let mut failures = Vec::new();
+    for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+        let mut emulator = Emulator::new(rom.clone());
+        emulator.set_renderer(renderer);
+        configure(&mut emulator);
+        for _ in 0..FRAMES {
+            emulator.run_frame();
+        }
+
+        let frame = rgba_frame(&emulator);
+        let wrong = frame.chunks(4).zip(reference.chunks(4)).filter(|(actual, expected)| actual != expected).count();
+        if wrong > 0 {
+            let stem = format!("{}-{:?}", rom_name.split('.').next().unwrap_or(rom_name), renderer);
+            let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("acid2");
+            std::fs::create_dir_all(&output).unwrap();
+            let diff = output.join(format!("{}-diff.png", stem));
+            write_png(&output.join(format!("{}.png", stem)), &frame);
+            write_png(&diff, &diff_image(&frame, &reference));
+            failures.push(format!("{:?}: {} pixels differ, see {}", renderer, wrong, diff.display()));
+        }
+    }
+    assert!(failures.is_empty(), "{}:\n{}", rom_name, failures.join("\n"));
+}
+
+fn rgba_frame(emulator: &Emulator) -> Vec<u8> {
+    let length = emulator.rgba_frame_buffer_len();
+    assert_eq!(length, 160 * 144 * 4, "no RGBA frame buffer");
+    // SAFETY: the buffer is `length` bytes and nothing touches the
+    // emulator while the slice is alive
+    unsafe { std::slice::from_raw_parts(emulator.rgba_frame_buffer_ptr(), length) }.to_vec()
+}
+
+/// Reads a 160x144 screenshot as RGBA, whatever colour type it was saved in.
+fn read_png(path: &Path) -> Vec<u8> {
+    let file = File::open(path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    assert_eq!((info.width, info.height), (160, 144), "{} isn't a 160x144 screenshot", path.display());
+    pixels.truncate(info.buffer_size());
+
+    match info.color_type {
+        png::ColorType::Rgba => pixels,
+        png::ColorType::Rgb => pixels.chunks(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks(2).flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]]).collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&g| [g, g, g, 0xFF]).collect(),
+        png::ColorType::Indexed => unreachable!("EXPAND turns indexed images into RGB"),
+    }
+}
+
+/// Wrong pixels in red, the rest of the reference faded towards white.
+fn diff_image(frame: &[u8], reference: &[u8]) -> Vec<u8> {
+    frame
+        .chunks(4)
+        .zip(reference.chunks(4))
+        .flat_map(|(actual, expected)| {
+            if actual == expected {
+                let fade = |channel: u8| 0xC0 + channel / 4;
+                [fade(expected[0]), fade(expected[1]), fade(expected[2]), 0xFF]
+            } else {
+                [0xFF, 0x00, 0x00, 0xFF]
+            }
+        })
+        .collect()
+}
+
+fn write_png(path: &Path, pixels: &[u8]) {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path).unwrap()), 160, 144);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(pixels).unwrap();
+}
@@ -0,0 +1,65 @@
+//! ROM building shared by the integration tests.
+
+// Each test binary only uses some of these
+#![allow(dead_code)]
+
+use gameboy::Emulator;
+
+/// Builds a test cartridge: 32 KiB of zeros, which run as NOPs, with code
+/// and header bytes placed where a test needs them.
+pub struct RomBuilder {
+    rom: Vec<u8>,
+}
+
+impl Default for RomBuilder {
+    fn default() -> Self {
+        RomBuilder { rom: vec![0; 0x8000] }
+    }
+}
+
+impl RomBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grows or truncates the ROM to `len` bytes.
+    pub fn size(mut self, len: usize) -> Self {
+        self.rom.resize(len, 0);
+        self
+    }
+
+    /// Puts `program` at the entry point, $0100.
+    pub fn program(self, program: &[u8]) -> Self {
+        self.at(0x100, program)
+    }
+
+    /// Puts `bytes` at offset `address` of the ROM.
+    pub fn at(mut self, address: usize, bytes: &[u8]) -> Self {
+        self.rom[address..address + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    /// Sets the header's CGB flag: 0x80 for CGB enhanced, 0xC0 for CGB only.
+    pub fn cgb(self, flag: u8) -> Self {
+        self.at(0x143, &[flag])
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        self.rom
+    }
+
+    /// Powers on a Game Boy with this cartridge inserted.
+    pub fn boot(self) -> Emulator {
+        Emulator::new(self.rom)
+    }
+}
+
+/// Powers on a Game Boy running `program` from the entry point.
+pub fn boot(program: &[u8]) -> Emulator {
+    RomBuilder::new().program(program).boot()
+}
+
+/// A ROM whose entry point loads $42 into A and spins.
+pub fn spin_rom() -> Vec<u8> {
+    RomBuilder::new().program(&[0x3E, 0x42, 0x18, 0xFE]).build()
+}
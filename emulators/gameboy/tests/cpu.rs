@@ -0,0 +1,195 @@
+//! SM83 instruction behaviour: results, flags and cycle counts, checked by
+//! running short programs from 0x100.
+
+mod common;
+
+use common::{boot, RomBuilder};
+use gameboy::Emulator;
+
+/// `LD BC,af; PUSH BC; POP AF`, which sets A and F directly.
+fn set_af(af: u16) -> Vec<u8> {
+    vec![0x01, af as u8, (af >> 8) as u8, 0xC5, 0xF1]
+}
+
+/// Runs `program` until PC leaves it.
+fn run(program: &[u8]) -> Emulator {
+    let mut emulator = boot(program);
+    while (emulator.pc() as usize) < 0x100 + program.len() {
+        emulator.step();
+    }
+    emulator
+}
+
+#[test]
+fn arithmetic_sets_flags() {
+    // Starting AF, instruction, resulting AF (F is Z N H C in bits 7-4)
+    let cases: &[(u16, &[u8], u16)] = &[
+        (0x3A00, &[0xC6, 0xC6], 0x00B0), // ADD A,$C6: zero, half carry, carry
+        (0x0F00, &[0xC6, 0x01], 0x1020), // ADD A,$01: half carry only
+        (0xE110, &[0xCE, 0x0F], 0xF120), // ADC A,$0F with carry in
+        (0x1000, &[0xD6, 0x01], 0x0F60), // SUB $01: half borrow
+        (0x3E00, &[0xD6, 0x3E], 0x00C0), // SUB $3E: zero
+        (0x3B10, &[0xDE, 0x2A], 0x1040), // SBC A,$2A with carry in
+        (0x3B10, &[0xDE, 0x4F], 0xEB70), // SBC A,$4F: borrow and half borrow
+        (0x3C00, &[0xFE, 0x40], 0x3C50), // CP $40 leaves A alone
+        (0x5A00, &[0xE6, 0x38], 0x1820), // AND $38 sets H
+        (0x5A70, &[0xAF], 0x0080),       // XOR A
+        (0x0070, &[0xF6, 0x00], 0x0080), // OR $00
+        (0xFF10, &[0x3C], 0x00B0),       // INC A keeps C
+        (0x0100, &[0x3D], 0x00C0),       // DEC A to zero
+        (0x1000, &[0x3D], 0x0F60),       // DEC A borrows from bit 4
+        (0x4500, &[0xC6, 0x38, 0x27], 0x8300), // ADD then DAA
+        (0x4500, &[0xD6, 0x38, 0x27], 0x0740), // SUB then DAA
+        (0x9900, &[0xC6, 0x01, 0x27], 0x0090), // DAA carries out to zero
+        (0x3500, &[0x2F], 0xCA60),       // CPL
+        (0x0080, &[0x37], 0x0090),       // SCF
+        (0x0090, &[0x3F], 0x0080),       // CCF
+        (0x8580, &[0x07], 0x0B10),       // RLCA clears Z
+        (0x8100, &[0x1F], 0x4010),       // RRA
+    ];
+    for &(af, op, expected) in cases {
+        let program = [set_af(af), op.to_vec()].concat();
+        assert_eq!(run(&program).registers().af, expected, "AF={:04X}, {:02X?}", af, op);
+    }
+}
+
+#[test]
+fn cb_prefixed_ops_set_flags() {
+    let cases: &[(u16, &[u8], u16)] = &[
+        (0x8000, &[0xCB, 0x17], 0x0090), // RL A
+        (0x0110, &[0xCB, 0x1F], 0x8010), // RR A rotates carry in, bit 0 out
+        (0x8A00, &[0xCB, 0x2F], 0xC500), // SRA A keeps bit 7
+        (0x0100, &[0xCB, 0x3F], 0x0090), // SRL A
+        (0x8000, &[0xCB, 0x27], 0x0090), // SLA A
+        (0xF010, &[0xCB, 0x37], 0x0F00), // SWAP A clears C
+        (0x8000, &[0xCB, 0x07], 0x0110), // RLC A
+        (0x8000, &[0xCB, 0x7F], 0x8020), // BIT 7,A set
+        (0x7F10, &[0xCB, 0x7F], 0x7FB0), // BIT 7,A clear keeps C
+        (0x0000, &[0xCB, 0xC7], 0x0100), // SET 0,A
+        (0xFF00, &[0xCB, 0xBF], 0x7F00), // RES 7,A
+    ];
+    for &(af, op, expected) in cases {
+        let program = [set_af(af), op.to_vec()].concat();
+        assert_eq!(run(&program).registers().af, expected, "AF={:04X}, {:02X?}", af, op);
+    }
+
+    // H is 0x01 after boot, so BIT 7,H sets Z
+    let emulator = run(&[set_af(0x0010), vec![0xCB, 0x7C]].concat());
+    assert_eq!(emulator.registers().af, 0x00B0);
+}
+
+#[test]
+fn cb_prefixed_ops_work_on_memory() {
+    let emulator = run(&[
+        0x21, 0x00, 0xC0, // LD HL,$C000
+        0x36, 0x85, // LD (HL),$85
+        0xCB, 0x06, // RLC (HL)
+        0xCB, 0xDE, // SET 3,(HL)
+        0xCB, 0x86, // RES 0,(HL)
+    ]);
+    assert_eq!(emulator.read_byte(0xC000), 0x0A);
+    assert_eq!(emulator.registers().af & 0xF0, 0x10);
+}
+
+#[test]
+fn sixteen_bit_arithmetic_sets_flags() {
+    // ADD HL,BC carries out of bit 11 and leaves Z alone
+    let emulator = run(&[set_af(0x0080), vec![0x21, 0xFF, 0x0F, 0x01, 0x01, 0x00, 0x09]].concat());
+    assert_eq!(emulator.registers().hl, 0x1000);
+    assert_eq!(emulator.registers().af & 0xFF, 0xA0);
+
+    // ADD SP,e8 and LD HL,SP+e8 take H and C from the low byte and clear Z
+    let emulator = run(&[set_af(0x00C0), vec![0x31, 0xFF, 0x00, 0xE8, 0x01]].concat());
+    assert_eq!(emulator.registers().sp, 0x0100);
+    assert_eq!(emulator.registers().af & 0xFF, 0x30);
+
+    let emulator = run(&[set_af(0x00F0), vec![0x31, 0x00, 0x00, 0xF8, 0xFF]].concat());
+    assert_eq!(emulator.registers().hl, 0xFFFF);
+    assert_eq!(emulator.registers().af & 0xFF, 0x00);
+
+    let emulator = run(&[0x31, 0x01, 0x00, 0xF8, 0xFF]);
+    assert_eq!(emulator.registers().hl, 0x0000);
+    assert_eq!(emulator.registers().af & 0xFF, 0x30);
+}
+
+#[test]
+fn pop_af_masks_the_low_nibble() {
+    let emulator = run(&set_af(0x12FF));
+    assert_eq!(emulator.registers().af, 0x12F0);
+}
+
+#[test]
+fn instructions_take_their_documented_cycles() {
+    // Setup, the instruction to time, and its cycle count
+    let with_hl = [0x21, 0x00, 0xC0];
+    let cases: &[(&[u8], &[u8], u64)] = &[
+        (&[], &[0x00], 4),                    // NOP
+        (&[], &[0x41], 4),                    // LD B,C
+        (&with_hl, &[0x7E], 8),               // LD A,(HL)
+        (&with_hl, &[0x36, 0x12], 12),        // LD (HL),n
+        (&[], &[0x01, 0x34, 0x12], 12),       // LD BC,nn
+        (&[], &[0xF0, 0x44], 12),             // LD A,(FF00+n)
+        (&[], &[0xEA, 0x00, 0xC0], 16),       // LD (nn),A
+        (&[], &[0x08, 0x00, 0xC0], 20),       // LD (nn),SP
+        (&[], &[0xC5], 16),                   // PUSH BC
+        (&[], &[0xC1], 12),                   // POP BC
+        (&[], &[0xE8, 0x01], 16),             // ADD SP,e8
+        (&with_hl, &[0x34], 12),              // INC (HL)
+        (&[], &[0x09], 8),                    // ADD HL,BC
+        (&[], &[0x18, 0x00], 12),             // JR
+        (&[0xAF], &[0x20, 0x00], 8),          // JR NZ not taken (XOR A set Z)
+        (&[0xC6, 0x01], &[0x20, 0x00], 12),   // JR NZ taken
+        (&[0xAF], &[0xCA, 0x50, 0x01], 16),   // JP Z taken
+        (&[0xC6, 0x01], &[0xCA, 0x50, 0x01], 12), // JP Z not taken
+        (&[], &[0xCD, 0x50, 0x01], 24),       // CALL
+        (&[0xAF], &[0xC4, 0x50, 0x01], 12),   // CALL NZ not taken
+        (&[], &[0xC9], 16),                   // RET
+        (&[0xAF], &[0xC8], 20),               // RET Z taken
+        (&[0xC6, 0x01], &[0xC8], 8),          // RET Z not taken
+        (&[], &[0xFF], 16),                   // RST $38
+        (&[], &[0xCB, 0x7C], 8),              // BIT 7,H
+        (&with_hl, &[0xCB, 0x46], 12),        // BIT 0,(HL)
+        (&with_hl, &[0xCB, 0xC6], 16),        // SET 0,(HL)
+        (&with_hl, &[0xCB, 0x86], 16),        // RES 0,(HL)
+        (&with_hl, &[0xCB, 0x06], 16),        // RLC (HL)
+        (&[], &[0xCB, 0x37], 8),              // SWAP A
+    ];
+    for &(setup, op, expected) in cases {
+        let mut emulator = boot(&[setup, op].concat());
+        while (emulator.pc() as usize) < 0x100 + setup.len() {
+            emulator.step();
+        }
+        let before = emulator.cycle_count();
+        emulator.step();
+        assert_eq!(emulator.cycle_count() - before, expected, "{:02X?} after {:02X?}", op, setup);
+    }
+}
+
+#[test]
+fn illegal_opcodes_lock_up_the_cpu() {
+    let program = [0x3C, 0xD3, 0x3C]; // INC A, illegal, INC A
+    let mut emulator = boot(&program);
+    let a = emulator.registers().af >> 8;
+    for _ in 0..100 {
+        emulator.step();
+    }
+    let registers = emulator.registers();
+    assert!(registers.locked);
+    assert_eq!(registers.pc, 0x101);
+    assert_eq!(registers.af >> 8, a + 1, "only the first INC A ran");
+
+    // Time carries on, and interrupts don't wake it
+    emulator.write_byte(0xFFFF, 0x01);
+    emulator.write_byte(0xFF0F, 0x01);
+    let before = emulator.cycle_count();
+    emulator.debug_run_to_frame();
+    assert!(emulator.cycle_count() > before);
+    assert_eq!(emulator.pc(), 0x101);
+
+    // The lock-up survives a save state, and only reloading the ROM clears it
+    let state = emulator.save_state();
+    emulator.load_rom(RomBuilder::new().program(&program).build());
+    assert!(!emulator.registers().locked);
+    emulator.load_state(&state).unwrap();
+    assert!(emulator.registers().locked);
+}
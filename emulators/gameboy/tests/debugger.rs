@@ -0,0 +1,84 @@
+//! Breakpoints, watchpoints and the stepping commands, on a small program
+//! that calls a subroutine and stores its result.
+
+mod common;
+
+use common::RomBuilder;
+use gameboy::debugger::{StopReason, WatchHit};
+use gameboy::Emulator;
+
+fn calling_program() -> Emulator {
+    RomBuilder::new()
+        .program(&[
+            0x31, 0xFE, 0xFF, // LD SP,$FFFE
+            0xCD, 0x10, 0x01, // CALL $0110
+            0xEA, 0x00, 0xC0, // LD ($C000),A
+            0x18, 0xFE, // JR $0109
+        ])
+        .at(0x110, &[
+            0x3E, 0x42, // LD A,$42
+            0x04, // INC B
+            0xC9, // RET
+        ])
+        .boot()
+}
+
+#[test]
+fn step_over_runs_the_call_to_completion() {
+    let mut emulator = calling_program();
+    assert_eq!(emulator.debug_step_over(), StopReason::Step);
+    assert_eq!(emulator.pc(), 0x103);
+
+    assert_eq!(emulator.debug_step_over(), StopReason::Step);
+    assert_eq!(emulator.pc(), 0x106);
+    assert_eq!(emulator.registers().af >> 8, 0x42);
+    assert_eq!(emulator.registers().sp, 0xFFFE);
+}
+
+#[test]
+fn step_out_returns_to_the_caller() {
+    let mut emulator = calling_program();
+    emulator.debug_step();
+    emulator.debug_step();
+    assert_eq!(emulator.pc(), 0x110);
+
+    assert_eq!(emulator.debug_step_out(), StopReason::Step);
+    assert_eq!(emulator.pc(), 0x106);
+}
+
+#[test]
+fn write_watchpoint_stops_after_the_store() {
+    let mut emulator = calling_program();
+    // Reads of the same address don't count
+    emulator.add_watchpoint(0xC000, 0xC000, true, false);
+    emulator.add_watchpoint(0xBFF0, 0xC00F, false, true);
+
+    let hit = WatchHit { address: 0xC000, value: 0x42, write: true };
+    assert_eq!(emulator.debug_run_to_frame(), StopReason::Watchpoint(hit));
+    assert_eq!(emulator.pc(), 0x109);
+
+    emulator.clear_watchpoints();
+    assert_eq!(emulator.debug_run_to_frame(), StopReason::FrameComplete);
+}
+
+#[test]
+fn breakpoint_stops_before_the_instruction_and_resumes_past_it() {
+    let mut emulator = calling_program();
+    emulator.add_breakpoint(0x110);
+    assert_eq!(emulator.debug_run_to_frame(), StopReason::Breakpoint(0x110));
+    assert_eq!(emulator.registers().af >> 8, 0x01, "LD A,$42 ran early");
+
+    emulator.remove_breakpoint(0x110);
+    emulator.add_breakpoint(0x109);
+    assert_eq!(emulator.debug_run_to_frame(), StopReason::Breakpoint(0x109));
+    assert_eq!(emulator.breakpoints(), [0x109]);
+}
+
+#[test]
+fn fetches_and_interrupt_checks_dont_trip_read_watchpoints() {
+    let mut emulator = common::boot(&[0xFB, 0x18, 0xFE]); // EI; JR $0101
+    emulator.add_watchpoint(0x0100, 0x0102, true, false);
+    emulator.add_watchpoint(0xFF0F, 0xFF0F, true, false);
+    emulator.add_watchpoint(0xFFFF, 0xFFFF, true, false);
+    assert_eq!(emulator.debug_run_to_frame(), StopReason::FrameComplete);
+}
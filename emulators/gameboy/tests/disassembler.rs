@@ -0,0 +1,105 @@
+//! Disassembly text, lengths and cycle counts, without running anything.
+
+mod common;
+
+use common::RomBuilder;
+use gameboy::disassembler::disassemble;
+
+/// Disassembles the one instruction at the start of `bytes`.
+fn single(bytes: &[u8]) -> gameboy::disassembler::DisassembledInstruction {
+    let read = |address: u16| bytes.get(address as usize).copied().unwrap_or(0);
+    disassemble(read, 0, 1).remove(0)
+}
+
+#[test]
+fn formats_instructions() {
+    // Encoding, text, cycles, cycles when a branch is taken
+    let cases: &[(&[u8], &str, u32, Option<u32>)] = &[
+        (&[0x00], "NOP", 4, None),
+        (&[0x3E, 0x42], "LD A,$42", 8, None),
+        (&[0x01, 0x34, 0x12], "LD BC,$1234", 12, None),
+        (&[0xF0, 0x44], "LD A,(FF00+$44)", 12, None),
+        (&[0xE0, 0x40], "LD (FF00+$40),A", 12, None),
+        (&[0xF2], "LD A,(FF00+C)", 8, None),
+        (&[0x2A], "LD A,(HL+)", 8, None),
+        (&[0x36, 0x12], "LD (HL),$12", 12, None),
+        (&[0x08, 0x00, 0xC0], "LD ($C000),SP", 20, None),
+        (&[0xF8, 0xFE], "LD HL,SP-2", 12, None),
+        (&[0xE8, 0x05], "ADD SP,5", 16, None),
+        (&[0x20, 0x03], "JR NZ,$+5", 8, Some(12)),
+        (&[0x18, 0xFE], "JR $+0", 12, None),
+        (&[0x38, 0xFB], "JR C,$-3", 8, Some(12)),
+        (&[0xC2, 0x50, 0x01], "JP NZ,$0150", 12, Some(16)),
+        (&[0xCD, 0x00, 0x40], "CALL $4000", 24, None),
+        (&[0xDC, 0x00, 0x40], "CALL C,$4000", 12, Some(24)),
+        (&[0xC8], "RET Z", 8, Some(20)),
+        (&[0xFF], "RST $38", 16, None),
+        (&[0xD3], "DB $D3", 4, None),
+    ];
+    for &(bytes, text, cycles, branch_cycles) in cases {
+        let instruction = single(bytes);
+        assert_eq!(instruction.text, text, "{:02X?}", bytes);
+        assert_eq!(instruction.bytes, bytes, "{}", text);
+        assert_eq!(instruction.cycles, cycles, "{}", text);
+        assert_eq!(instruction.branch_cycles, branch_cycles, "{}", text);
+    }
+}
+
+#[test]
+fn formats_cb_prefixed_instructions() {
+    let cases: &[(u8, &str, u32)] = &[
+        (0x7C, "BIT 7,H", 8),
+        (0x46, "BIT 0,(HL)", 12),
+        (0x11, "RL C", 8),
+        (0x06, "RLC (HL)", 16),
+        (0x2F, "SRA A", 8),
+        (0x37, "SWAP A", 8),
+        (0x3E, "SRL (HL)", 16),
+        (0x87, "RES 0,A", 8),
+        (0xFE, "SET 7,(HL)", 16),
+    ];
+    for &(opcode, text, cycles) in cases {
+        let instruction = single(&[0xCB, opcode]);
+        assert_eq!(instruction.text, text);
+        assert_eq!(instruction.bytes, [0xCB, opcode]);
+        assert_eq!(instruction.cycles, cycles, "{}", text);
+    }
+}
+
+#[test]
+fn walks_consecutive_instructions() {
+    let program = [0x3E, 0x42, 0xCB, 0x7C, 0xC3, 0x00, 0x01, 0x00];
+    let read = |address: u16| program.get(address as usize).copied().unwrap_or(0);
+    let listing = disassemble(read, 0, 4);
+    let addresses: Vec<u16> = listing.iter().map(|instruction| instruction.address).collect();
+    assert_eq!(addresses, [0, 2, 4, 7]);
+    assert_eq!(listing[2].text, "JP $0100");
+}
+
+#[test]
+fn immediates_wrap_past_the_end_of_the_address_space() {
+    let read = |address: u16| match address {
+        0xFFFE => 0xC3,
+        0xFFFF => 0x34,
+        0x0000 => 0x12,
+        _ => 0x00,
+    };
+    let listing = disassemble(read, 0xFFFE, 2);
+    assert_eq!(listing[0].text, "JP $1234");
+    assert_eq!(listing[0].bytes, [0xC3, 0x34, 0x12]);
+    assert_eq!(listing[1].address, 0x0001);
+}
+
+#[test]
+fn immediates_read_past_the_end_of_the_rom() {
+    // A 16 KiB ROM: the operand of LD BC,nn at its last byte comes from
+    // the unmapped second bank, which reads as open bus
+    let emulator = RomBuilder::new().size(0x4000).at(0x3FFF, &[0x01]).boot();
+    let instruction = &emulator.disassemble(0x3FFF, 1)[0];
+    assert_eq!(instruction.text, "LD BC,$FFFF");
+    assert_eq!(instruction.bytes, [0x01, 0xFF, 0xFF]);
+
+    // Across the end of a full ROM the operand comes from VRAM
+    let emulator = RomBuilder::new().at(0x7FFF, &[0x01]).boot();
+    assert_eq!(emulator.disassemble(0x7FFF, 1)[0].text, "LD BC,$0000");
+}
@@ -0,0 +1,91 @@
+//! OAM DMA, and how long a VRAM DMA holds the CPU.
+
+mod common;
+
+use common::RomBuilder;
+use gameboy::Emulator;
+
+/// Powers on with `program` at $0150, past the header, and steps over the
+/// jump there.
+fn start(cgb: bool, program: &[u8]) -> Emulator {
+    let mut emulator = RomBuilder::new()
+        .cgb(if cgb { 0xC0 } else { 0x00 })
+        .program(&[0xC3, 0x50, 0x01]) // JP $0150
+        .at(0x150, program)
+        .boot();
+    emulator.step();
+    emulator
+}
+
+/// Steps once and returns the CPU clocks it took.
+fn clocks(emulator: &mut Emulator) -> u64 {
+    let before = emulator.cycle_count();
+    emulator.step();
+    emulator.cycle_count() - before
+}
+
+fn oam(emulator: &Emulator) -> Vec<u8> {
+    (0xFE00..0xFEA0).map(|address| emulator.read_byte(address)).collect()
+}
+
+#[test]
+fn oam_dma_copies_160_bytes() {
+    // From ROM: the page holding the program
+    let mut emulator = start(false, &[0x18, 0xFE]);
+    emulator.write_byte(0xFF46, 0x01);
+    let rom: Vec<u8> = (0x100..0x1A0).map(|address| emulator.read_byte(address)).collect();
+    assert_eq!(oam(&emulator), rom);
+
+    // From WRAM, with the bytes either side of the block left out
+    for offset in 0..0x100 {
+        emulator.write_byte(0xC200 + offset, offset as u8 ^ 0xA5);
+    }
+    emulator.write_byte(0xFF46, 0xC2);
+    assert_eq!(emulator.read_byte(0xFF46), 0xC2);
+    assert_eq!(oam(&emulator), (0..0xA0).map(|offset| offset as u8 ^ 0xA5).collect::<Vec<_>>());
+    assert!((0xFEA0..=0xFEFF).all(|address| emulator.read_byte(address) == 0xFF));
+}
+
+#[test]
+fn oam_dma_does_not_stall_the_cpu() {
+    // LD A,$C0; LDH ($46),A
+    let mut emulator = start(false, &[0x3E, 0xC0, 0xE0, 0x46, 0x18, 0xFE]);
+    emulator.step();
+    assert_eq!(clocks(&mut emulator), 12);
+}
+
+#[test]
+fn general_vram_dma_stalls_the_cpu_per_block() {
+    for blocks in [1, 4, 0x80] {
+        // LD A,blocks-1; LDH ($55),A
+        let mut emulator = start(true, &[0x3E, blocks as u8 - 1, 0xE0, 0x55, 0x18, 0xFE]);
+        for (register, value) in [(0xFF51, 0xC0), (0xFF52, 0x00), (0xFF53, 0x00), (0xFF54, 0x00)] {
+            emulator.write_byte(register, value);
+        }
+        emulator.step();
+        assert_eq!(clocks(&mut emulator), 12 + 32 * blocks, "{} blocks", blocks);
+        assert_eq!(emulator.read_byte(0xFF55), 0xFF);
+    }
+}
+
+#[test]
+fn the_ppu_keeps_running_through_a_vram_dma() {
+    let mut emulator = start(true, &[0x3E, 0x7F, 0xE0, 0x55, 0x18, 0xFE]);
+    emulator.step();
+    let line = emulator.read_byte(0xFF44);
+    // 128 blocks hold the CPU for 4096 clocks, which with the LDH itself
+    // is just over 9 lines of 456
+    emulator.step();
+    assert_eq!(emulator.read_byte(0xFF44), line + 9);
+}
+
+#[test]
+fn vram_dma_blocks_take_twice_the_clocks_at_double_speed() {
+    // LD A,$01; LDH ($4D),A; STOP; LD A,$01; LDH ($55),A
+    let mut emulator = start(true, &[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x3E, 0x01, 0xE0, 0x55, 0x18, 0xFE]);
+    for _ in 0..4 {
+        emulator.step();
+    }
+    assert_eq!(emulator.read_byte(0xFF4D) & 0x80, 0x80, "not in double speed");
+    assert_eq!(clocks(&mut emulator), 12 + 2 * 64);
+}
@@ -0,0 +1,39 @@
+//! Two emulators swapping bytes over a `LinkCable`.
+
+mod common;
+
+use common::boot;
+use gameboy::{Emulator, LinkCable};
+
+/// Powers on a Game Boy that puts `byte` in SB, starts a transfer with
+/// `control`, waits for it to finish and copies what came back into B.
+fn transfer(byte: u8, control: u8) -> Emulator {
+    boot(&[
+        0x3E, byte, 0xE0, 0x01, // SB = byte
+        0x3E, control, 0xE0, 0x02, // SC = control
+        0xF0, 0x02, 0xE6, 0x80, 0x20, 0xFA, // wait for SC bit 7 to clear
+        0xF0, 0x01, 0x47, // B = SB
+        0x18, 0xFE,
+    ])
+}
+
+#[test]
+fn swaps_bytes_between_linked_game_boys() {
+    let master = transfer(0x42, 0x81);
+    let slave = transfer(0x99, 0x80);
+    let mut cable = LinkCable::new(master, slave);
+    cable.run_frame();
+
+    for (emulator, received) in [(cable.left(), 0x99), (cable.right(), 0x42)] {
+        assert_eq!(emulator.registers().bc >> 8, received);
+        assert_ne!(emulator.read_byte(0xFF0F) & 0x08, 0, "serial interrupt not requested");
+    }
+    assert_eq!(cable.left_mut().take_serial_output(), [0x42]);
+    assert_eq!(cable.right_mut().take_serial_output(), [0x99]);
+
+    // Unplugged, the master reads the line idling high
+    let (mut master, _) = cable.disconnect();
+    master.reset();
+    master.run_frame();
+    assert_eq!(master.registers().bc >> 8, 0xFF);
+}
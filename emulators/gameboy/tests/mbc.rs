@@ -0,0 +1,93 @@
+//! MBC1 ROM and RAM banking.
+
+mod common;
+
+use common::RomBuilder;
+use gameboy::Emulator;
+
+/// An MBC1 cartridge with RAM: `banks` 16 KiB banks, each starting with
+/// its own number, and 32 KiB of RAM.
+fn mbc1(banks: usize) -> Emulator {
+    let mut rom = RomBuilder::new().size(banks * 0x4000);
+    for bank in 0..banks {
+        rom = rom.at(bank * 0x4000, &[bank as u8]);
+    }
+    // Cartridge type MBC1+RAM+BATTERY, the ROM size, and 32 KiB of RAM
+    rom.program(&[0x18, 0xFE]).at(0x147, &[0x03, banks.trailing_zeros() as u8 - 1, 0x03]).boot()
+}
+
+#[test]
+fn switches_rom_banks() {
+    let mut emulator = mbc1(64);
+    assert_eq!(emulator.read_byte(0x4000), 1);
+
+    emulator.write_byte(0x2000, 5);
+    assert_eq!(emulator.read_byte(0x4000), 5);
+    // Bank 0 can't be selected at 0x4000
+    emulator.write_byte(0x2000, 0);
+    assert_eq!(emulator.read_byte(0x4000), 1);
+    // Only five bits count
+    emulator.write_byte(0x3FFF, 0xE3);
+    assert_eq!(emulator.read_byte(0x4000), 3);
+
+    // BANK2 supplies bits 5 and 6
+    emulator.write_byte(0x4000, 1);
+    assert_eq!(emulator.read_byte(0x4000), 0x23);
+    // The zero check only looks at BANK1
+    emulator.write_byte(0x2000, 0x20);
+    assert_eq!(emulator.read_byte(0x4000), 0x21);
+    assert_eq!(emulator.read_byte(0x0000), 0);
+
+    // In mode 1 BANK2 also moves 0x0000-0x3FFF
+    emulator.write_byte(0x6000, 1);
+    assert_eq!(emulator.read_byte(0x0000), 0x20);
+    emulator.write_byte(0x6000, 0);
+    assert_eq!(emulator.read_byte(0x0000), 0);
+}
+
+#[test]
+fn wraps_banks_past_the_end_of_the_rom() {
+    let mut emulator = mbc1(8);
+    emulator.write_byte(0x2000, 9);
+    assert_eq!(emulator.read_byte(0x4000), 1);
+    emulator.write_byte(0x4000, 1);
+    assert_eq!(emulator.read_byte(0x4000), 1);
+}
+
+#[test]
+fn switches_ram_banks_once_enabled() {
+    let mut emulator = mbc1(4);
+    emulator.write_byte(0xA000, 0x12);
+    assert_eq!(emulator.read_byte(0xA000), 0xFF);
+
+    emulator.write_byte(0x0000, 0x0A);
+    emulator.write_byte(0xA000, 0x12);
+    assert_eq!(emulator.read_byte(0xA000), 0x12);
+
+    // RAM banks only switch in mode 1
+    emulator.write_byte(0x4000, 2);
+    assert_eq!(emulator.read_byte(0xA000), 0x12);
+    emulator.write_byte(0x6000, 1);
+    assert_eq!(emulator.read_byte(0xA000), 0x00);
+    emulator.write_byte(0xBFFF, 0x34);
+    emulator.write_byte(0x4000, 0);
+    assert_eq!(emulator.read_byte(0xA000), 0x12);
+
+    let state = emulator.save_state();
+    emulator.write_byte(0x0000, 0x00);
+    assert_eq!(emulator.read_byte(0xA000), 0xFF);
+    emulator.load_state(&state).unwrap();
+    emulator.write_byte(0x4000, 2);
+    assert_eq!(emulator.read_byte(0xBFFF), 0x34);
+}
+
+#[test]
+fn plain_cartridges_ignore_bank_writes() {
+    let mut emulator = RomBuilder::new().at(0x4000, &[0x44]).boot();
+    emulator.write_byte(0x2000, 2);
+    assert_eq!(emulator.read_byte(0x4000), 0x44);
+
+    // Their RAM needs no enabling
+    emulator.write_byte(0xA000, 0x56);
+    assert_eq!(emulator.read_byte(0xA000), 0x56);
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use common::{spin_rom, RomBuilder};
+use emu_common::{EmulatorCore, Input, PixelFormat};
+use gameboy::Emulator;
+
+#[test]
+fn runs_natively() {
+    let mut emulator = Emulator::new(spin_rom());
+    assert_eq!(emulator.disassemble(0x100, 1)[0].text, "LD A,$42");
+
+    emulator.step();
+    assert_eq!(emulator.registers().af >> 8, 0x42);
+    assert_eq!(emulator.pc(), 0x102);
+
+    let state = emulator.save_state();
+    emulator.step();
+    emulator.load_state(&state).unwrap();
+    assert_eq!(emulator.pc(), 0x102);
+    assert_eq!(emulator.frame_buffer().len(), 160 * 144);
+}
+
+#[test]
+fn new_powers_on_like_load_rom() {
+    let fresh = Emulator::new(spin_rom());
+    let mut reloaded = Emulator::new(spin_rom());
+    reloaded.run_frame();
+    reloaded.load_rom(spin_rom());
+    assert_eq!(fresh.read_byte(0xFF40), 0x91);
+    assert_eq!(fresh.save_state(), reloaded.save_state());
+}
+
+#[test]
+fn drives_through_emulator_core() {
+    let mut core: Box<dyn EmulatorCore> = Box::new(Emulator::new(spin_rom()));
+    core.load_rom(&spin_rom()).unwrap();
+    core.set_input(Input::Start, true);
+    core.run_frame().unwrap();
+    assert_eq!(core.frame_buffer().len(), core.frame_buffer_info().len());
+
+    let state = core.save_state();
+    core.reset();
+    core.load_state(&state).unwrap();
+}
+
+#[test]
+fn runs_cgb_games_in_colour() {
+    let mut emulator = RomBuilder::new()
+        .cgb(0x80)
+        .program(&[
+            0x3E, 0x80, 0xE0, 0x68, // BCPS = auto-increment from 0
+            0x3E, 0x1F, 0xE0, 0x69, // BG palette 0, colour 0 = red
+            0xAF, 0xE0, 0x69,
+            0x3E, 0x02, 0xE0, 0x70, // SVBK = 2
+            0x3E, 0x42, 0xEA, 0x00, 0xD0, // ($D000) = $42
+            0x00, 0x18, 0xFE,
+        ])
+        .boot();
+    assert!(emulator.is_cgb());
+    assert_eq!(emulator.registers().af >> 8, 0x11);
+
+    emulator.set_color_correction(false);
+    emulator.run_frame();
+    assert_eq!(emulator.frame_buffer_info().format, PixelFormat::Rgba8888);
+    assert_eq!(EmulatorCore::frame_buffer(&emulator)[..4], [0xFF, 0x00, 0x00, 0xFF]);
+    assert_eq!(emulator.rgb555_frame_buffer()[0], 0x001F);
+
+    assert_eq!(emulator.read_byte(0xD000), 0x42);
+    emulator.write_byte(0xFF70, 1);
+    assert_eq!(emulator.read_byte(0xD000), 0x00);
+}
+
+#[test]
+fn copies_to_vram_with_dma() {
+    let mut rom = spin_rom();
+    rom[0x143] = 0xC0;
+    let mut emulator = Emulator::new(rom);
+    for offset in 0..0x40 {
+        emulator.write_byte(0xC000 + offset, offset as u8 + 1);
+    }
+    let vram = |emulator: &Emulator, start: u16| {
+        (start..start + 0x20).map(|address| emulator.read_byte(address)).collect::<Vec<_>>()
+    };
+    let expected: Vec<u8> = (1..=0x20).collect();
+
+    // General purpose: two blocks from $C000 to $8000, done at once
+    for (register, value) in [(0xFF51, 0xC0), (0xFF52, 0x00), (0xFF53, 0x00), (0xFF54, 0x00), (0xFF55, 0x01)] {
+        emulator.write_byte(register, value);
+    }
+    assert_eq!(vram(&emulator, 0x8000), expected);
+    assert_eq!(emulator.read_byte(0xFF55), 0xFF);
+
+    // HBlank: two blocks to $9000, one per line
+    for (register, value) in [(0xFF51, 0xC0), (0xFF52, 0x00), (0xFF53, 0x10), (0xFF54, 0x00), (0xFF55, 0x81)] {
+        emulator.write_byte(register, value);
+    }
+    assert_eq!(emulator.read_byte(0xFF55), 0x01);
+    emulator.run_frame();
+    assert_eq!(vram(&emulator, 0x9000), expected);
+    assert_eq!(emulator.read_byte(0xFF55), 0xFF);
+}
+
+#[test]
+fn copies_to_oam_with_dma() {
+    let mut emulator = Emulator::new(spin_rom());
+    for offset in 0..0xA0 {
+        emulator.write_byte(0xC100 + offset, offset as u8 ^ 0x5A);
+    }
+    emulator.write_byte(0xFF46, 0xC1);
+    assert_eq!(emulator.read_byte(0xFF46), 0xC1);
+    assert!((0..0xA0).all(|offset| emulator.read_byte(0xFE00 + offset) == offset as u8 ^ 0x5A));
+}
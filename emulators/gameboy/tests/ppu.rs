@@ -0,0 +1,281 @@
+//! The two renderers should agree on static scenes, and only the pixel FIFO
+//! should show register writes that land mid-line. Also covers fine scroll,
+//! the window's edge cases, mode 3 timing and STAT interrupt edges.
+
+mod common;
+
+use common::RomBuilder;
+use gameboy::{Emulator, Renderer};
+
+fn with_renderer(rom: Vec<u8>, renderer: Renderer) -> Emulator {
+    let mut emulator = Emulator::new(rom);
+    emulator.set_renderer(renderer);
+    emulator
+}
+
+/// Fills VRAM and OAM with a scene using scroll, the window and sprites
+/// with every flag, all at once through the debugger.
+fn draw_scene(emulator: &mut Emulator) {
+    for offset in 0..0x1000u16 {
+        emulator.write_byte(0x8000 + offset, (offset.wrapping_mul(37) >> 3) as u8);
+    }
+    for offset in 0..0x800u16 {
+        emulator.write_byte(0x9800 + offset, (offset * 7 % 97) as u8);
+    }
+    for sprite in 0..40u16 {
+        let attributes = [0x00, 0x20, 0x40, 0x80, 0x10, 0xF0][sprite as usize % 6];
+        let oam = [(16 + sprite * 13 % 150) as u8, (sprite * 29 % 176) as u8, sprite as u8 * 3, attributes];
+        for (offset, value) in oam.into_iter().enumerate() {
+            emulator.write_byte(0xFE00 + sprite * 4 + offset as u16, value);
+        }
+    }
+    // Scroll, window position, then BGP, OBP0 and OBP1
+    let registers = [(0xFF42, 5), (0xFF43, 3), (0xFF4A, 40), (0xFF4B, 50), (0xFF47, 0xE4), (0xFF48, 0xD2), (0xFF49, 0x1B)];
+    for (register, value) in registers {
+        emulator.write_byte(register, value);
+    }
+    emulator.write_byte(0xFF40, 0xF3);
+}
+
+#[test]
+fn renderers_agree_on_static_scenes() {
+    let spin = RomBuilder::new().program(&[0x18, 0xFE]).build();
+    let frames: Vec<Vec<u8>> = [Renderer::Scanline, Renderer::PixelFifo]
+        .into_iter()
+        .map(|renderer| {
+            let mut emulator = with_renderer(spin.clone(), renderer);
+            draw_scene(&mut emulator);
+            emulator.run_frame();
+            emulator.run_frame();
+            emulator.frame_buffer()
+        })
+        .collect();
+    assert!(frames[0].iter().any(|&pixel| pixel != frames[0][0]));
+    assert!(frames[0] == frames[1], "pixel FIFO frame differs from the scanline one");
+}
+
+#[test]
+fn pixel_fifo_shows_mid_line_palette_changes() {
+    let program = RomBuilder::new()
+        .program(&[
+            0x3E, 0xFF, 0xE0, 0x47, // BGP = all black
+            0xF0, 0x41, 0xE6, 0x03, 0xFE, 0x03, 0x20, 0xF8, // wait for mode 3
+            0xAF, 0xE0, 0x47, // BGP = all white
+            0x18, 0xFE,
+        ])
+        .build();
+
+    let mut emulator = with_renderer(program.clone(), Renderer::PixelFifo);
+    emulator.run_frame();
+    let line = &emulator.frame_buffer()[..160];
+    assert_eq!((line[0], line[159]), (0x00, 0xFF));
+
+    let mut emulator = with_renderer(program, Renderer::Scanline);
+    emulator.run_frame();
+    assert!(emulator.frame_buffer()[..160].iter().all(|&pixel| pixel == 0xFF));
+}
+
+/// A ROM that runs NOPs, so every step is 4 dots.
+fn nop_rom() -> Vec<u8> {
+    RomBuilder::new().at(0x7FFD, &[0xC3, 0x00, 0x01]).build() // JP $0100
+}
+
+fn ly(emulator: &Emulator) -> u8 {
+    emulator.read_byte(0xFF44)
+}
+
+fn mode(emulator: &Emulator) -> u8 {
+    emulator.read_byte(0xFF41) & 0x03
+}
+
+fn step_until(emulator: &mut Emulator, done: impl Fn(&Emulator) -> bool) {
+    for _ in 0..100_000 {
+        if done(emulator) {
+            return;
+        }
+        emulator.step();
+    }
+    panic!("condition never held");
+}
+
+/// Tile 0 is white, tile 1 black, and tile 2 white on the left and black
+/// on the right. Both tile maps are filled with `background` and
+/// `window` tiles, apart from `first_window` in the window's top left.
+fn draw_tiles(emulator: &mut Emulator, background: u8, window: u8, first_window: u8) {
+    for offset in 0..16 {
+        emulator.write_byte(0x8000 + offset, 0x00);
+        emulator.write_byte(0x8010 + offset, 0xFF);
+        emulator.write_byte(0x8020 + offset, 0x0F);
+    }
+    for offset in 0..0x400 {
+        emulator.write_byte(0x9800 + offset, background);
+        emulator.write_byte(0x9C00 + offset, window);
+    }
+    emulator.write_byte(0x9C00, first_window);
+    emulator.write_byte(0xFF47, 0xE4);
+}
+
+/// Runs two frames and returns line `y` of the second.
+fn line(emulator: &mut Emulator, y: usize) -> Vec<u8> {
+    emulator.run_frame();
+    emulator.run_frame();
+    emulator.frame_buffer()[y * 160..(y + 1) * 160].to_vec()
+}
+
+const WHITE: u8 = 0xFF;
+const BLACK: u8 = 0x00;
+
+#[test]
+fn fine_scroll_drops_the_first_pixels() {
+    for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+        let mut emulator = with_renderer(nop_rom(), renderer);
+        // Alternating white and black tiles, scrolled 3 pixels left
+        draw_tiles(&mut emulator, 0, 0, 0);
+        for column in (1..32).step_by(2) {
+            emulator.write_byte(0x9800 + column, 1);
+        }
+        emulator.write_byte(0xFF43, 3);
+        emulator.write_byte(0xFF40, 0x91);
+
+        let line = line(&mut emulator, 0);
+        assert!(line[..5].iter().all(|&pixel| pixel == WHITE), "{:?}", renderer);
+        assert!(line[5..13].iter().all(|&pixel| pixel == BLACK), "{:?}", renderer);
+        assert!(line[13..21].iter().all(|&pixel| pixel == WHITE), "{:?}", renderer);
+    }
+}
+
+#[test]
+fn window_below_wx_7_starts_off_screen() {
+    for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+        let mut emulator = with_renderer(nop_rom(), renderer);
+        // Black background; the window's first tile is black on its right
+        // half only, and the rest of it white
+        draw_tiles(&mut emulator, 1, 0, 2);
+        emulator.write_byte(0xFF4A, 0);
+        emulator.write_byte(0xFF4B, 3);
+        emulator.write_byte(0xFF40, 0xF1);
+
+        // WX = 3 puts window column 4 at the left edge
+        let line = line(&mut emulator, 0);
+        assert!(line[..4].iter().all(|&pixel| pixel == BLACK), "{:?}", renderer);
+        assert!(line[4..160].iter().all(|&pixel| pixel == WHITE), "{:?}", renderer);
+    }
+}
+
+#[test]
+fn window_stays_on_once_wy_has_matched() {
+    for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+        let mut emulator = with_renderer(nop_rom(), renderer);
+        draw_tiles(&mut emulator, 1, 0, 0);
+        emulator.write_byte(0xFF4A, 0);
+        emulator.write_byte(0xFF4B, 7);
+        emulator.write_byte(0xFF40, 0xF1);
+        emulator.run_frame();
+
+        // Moving WY below the current line mid-frame doesn't hide it
+        step_until(&mut emulator, |emulator| ly(emulator) == 50);
+        emulator.write_byte(0xFF4A, 100);
+        emulator.run_frame();
+        let frame = emulator.frame_buffer();
+        assert!(frame[60 * 160..61 * 160].iter().all(|&pixel| pixel == WHITE), "{:?}", renderer);
+    }
+}
+
+/// Dots line 10 spends in mode 3, to within a step.
+fn mode3_dots(emulator: &mut Emulator) -> u64 {
+    step_until(emulator, |emulator| ly(emulator) == 10 && mode(emulator) == 2);
+    step_until(emulator, |emulator| mode(emulator) == 3);
+    let start = emulator.cycle_count();
+    step_until(emulator, |emulator| mode(emulator) == 0);
+    emulator.cycle_count() - start
+}
+
+#[test]
+fn mode_3_stretches_with_fine_scroll_and_sprites() {
+    let setup = |scroll_x: u8, sprites: u16| {
+        let mut emulator = with_renderer(nop_rom(), Renderer::PixelFifo);
+        emulator.write_byte(0xFF43, scroll_x);
+        // Ten sprites on lines 10-17, spread across the line
+        for sprite in 0..sprites {
+            emulator.write_byte(0xFE00 + sprite * 4, 26);
+            emulator.write_byte(0xFE01 + sprite * 4, 8 + sprite as u8 * 16);
+        }
+        emulator.write_byte(0xFF40, 0x93);
+        emulator.run_frame();
+        emulator
+    };
+
+    let plain = mode3_dots(&mut setup(0, 0));
+    assert!((172..=184).contains(&plain), "mode 3 took {} dots", plain);
+
+    let scrolled = mode3_dots(&mut setup(7, 0));
+    assert!((plain + 4..=plain + 8).contains(&scrolled), "{} dots with SCX = 7, {} without", scrolled, plain);
+
+    // Each sprite fetch stalls the output for at least 6 dots
+    let with_sprites = mode3_dots(&mut setup(0, 10));
+    assert!(with_sprites >= plain + 60, "{} dots with ten sprites, {} without", with_sprites, plain);
+    assert!(with_sprites <= 289, "{} dots with ten sprites", with_sprites);
+
+    // The scanline renderer keeps mode 3 at a fixed length
+    let mut emulator = with_renderer(nop_rom(), Renderer::Scanline);
+    emulator.run_frame();
+    let fixed = mode3_dots(&mut emulator);
+    assert!((172..=176).contains(&fixed), "mode 3 took {} dots", fixed);
+}
+
+fn stat_requested(emulator: &mut Emulator) -> bool {
+    let requested = emulator.read_byte(0xFF0F) & 0x02 != 0;
+    emulator.write_byte(0xFF0F, 0x00);
+    requested
+}
+
+#[test]
+fn stat_interrupts_fire_on_rising_edges() {
+    for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+        let mut emulator = with_renderer(nop_rom(), renderer);
+        emulator.run_frame();
+
+        // LYC: once as LY reaches it, not again while it holds
+        emulator.write_byte(0xFF45, 20);
+        emulator.write_byte(0xFF41, 0x40);
+        step_until(&mut emulator, |emulator| ly(emulator) == 19);
+        stat_requested(&mut emulator);
+        step_until(&mut emulator, |emulator| ly(emulator) == 20);
+        assert!(stat_requested(&mut emulator), "{:?}", renderer);
+        step_until(&mut emulator, |emulator| ly(emulator) == 20 && mode(emulator) == 0);
+        assert!(!stat_requested(&mut emulator), "{:?}", renderer);
+
+        // Mode 0: once per line, at the start of HBlank
+        emulator.write_byte(0xFF41, 0x08);
+        step_until(&mut emulator, |emulator| mode(emulator) == 3);
+        stat_requested(&mut emulator);
+        step_until(&mut emulator, |emulator| mode(emulator) == 0);
+        assert!(stat_requested(&mut emulator), "{:?}", renderer);
+        step_until(&mut emulator, |emulator| mode(emulator) == 2);
+        assert!(!stat_requested(&mut emulator), "{:?}", renderer);
+
+        // Mode 2: at the start of OAM scan
+        emulator.write_byte(0xFF41, 0x20);
+        step_until(&mut emulator, |emulator| mode(emulator) == 3);
+        stat_requested(&mut emulator);
+        step_until(&mut emulator, |emulator| mode(emulator) == 2);
+        assert!(stat_requested(&mut emulator), "{:?}", renderer);
+
+        // With both enabled the line stays high from HBlank into OAM scan,
+        // so there's no second edge
+        emulator.write_byte(0xFF41, 0x28);
+        step_until(&mut emulator, |emulator| mode(emulator) == 0);
+        stat_requested(&mut emulator);
+        step_until(&mut emulator, |emulator| mode(emulator) == 2);
+        assert!(!stat_requested(&mut emulator), "{:?}", renderer);
+    }
+}
+
+#[test]
+fn vblank_is_requested_with_ie_clear() {
+    let mut emulator = with_renderer(nop_rom(), Renderer::Scanline);
+    emulator.write_byte(0xFFFF, 0x00);
+    emulator.write_byte(0xFF0F, 0x00);
+    step_until(&mut emulator, |emulator| ly(emulator) == 144);
+    assert_eq!(emulator.read_byte(0xFF0F) & 0x01, 0x01);
+}
@@ -0,0 +1,58 @@
+//! Feeds Game Boy Printer packets straight into a `Printer`, as the serial
+//! port would, and checks the replies and the printed image.
+
+use gameboy::{Printer, SerialDevice};
+
+/// Sends one packet and returns the printer's replies to its last two
+/// bytes: the 0x81 alive marker and the status.
+fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+    let mut body = vec![command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+    body.extend_from_slice(data);
+    let checksum = body.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+
+    let mut packet = vec![0x88, 0x33];
+    packet.extend(body);
+    packet.extend(checksum.to_le_bytes());
+    packet.extend([0, 0]);
+    let replies: Vec<u8> = packet.into_iter().map(|byte| printer.exchange(byte)).collect();
+    (replies[replies.len() - 2], replies[replies.len() - 1])
+}
+
+#[test]
+fn prints_compressed_and_raw_image_data() {
+    let mut printer = Printer::new();
+    assert_eq!(send(&mut printer, 0x01, false, &[]), (0x81, 0x00));
+
+    // Two bands of 20 tiles: colour 1 everywhere, then colour 3 everywhere
+    let raw: Vec<u8> = [0xFF, 0x00].repeat(0x140);
+    assert_eq!(send(&mut printer, 0x04, false, &raw), (0x81, 0x08));
+    // Four runs of 129 0xFFs and one of 124, 0x280 bytes in all
+    let compressed = [[0xFF, 0xFF].repeat(4), vec![0xFA, 0xFF]].concat();
+    assert_eq!(send(&mut printer, 0x04, true, &compressed), (0x81, 0x08));
+    assert_eq!(send(&mut printer, 0x04, false, &[]), (0x81, 0x0C));
+
+    // One sheet, one unit of margin after, palette mapping 1 to light grey
+    // and 3 to black, then busy until a status inquiry finds it done
+    assert_eq!(send(&mut printer, 0x02, false, &[0x01, 0x01, 0xE4, 0x40]), (0x81, 0x02));
+    assert_eq!(send(&mut printer, 0x0F, false, &[]), (0x81, 0x02));
+    assert_eq!(send(&mut printer, 0x0F, false, &[]), (0x81, 0x00));
+
+    let prints = printer.take_prints();
+    assert_eq!(prints.len(), 1);
+    let image = &prints[0];
+    assert_eq!((image.width, image.height), (160, 40));
+    let pixel = |y: usize| &image.rgba[y * 160 * 4..y * 160 * 4 + 4];
+    assert_eq!(pixel(0), [0xC0, 0xC0, 0xC0, 0xFF]);
+    assert_eq!(pixel(16), [0x00, 0x00, 0x00, 0xFF]);
+    assert_eq!(pixel(39), [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert!(printer.take_prints().is_empty());
+}
+
+#[test]
+fn rejects_packets_with_bad_checksums() {
+    let mut printer = Printer::new();
+    let packet = [0x88, 0x33, 0x04, 0x00, 0x02, 0x00, 0x12, 0x34, 0x00, 0x00, 0x00, 0x00];
+    let replies: Vec<u8> = packet.into_iter().map(|byte| printer.exchange(byte)).collect();
+    assert_eq!(replies[10..], [0x81, 0x01]);
+    assert_eq!(send(&mut printer, 0x0F, false, &[]), (0x81, 0x00));
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::spin_rom;
+use emu_common::state::{crc32, StateError};
+use gameboy::Emulator;
+
+#[test]
+fn rejected_states_leave_the_emulator_alone() {
+    let mut emulator = Emulator::new(spin_rom());
+    emulator.step();
+    let state = emulator.save_state();
+
+    let mut corrupt = state.clone();
+    corrupt[40] ^= 0xFF;
+    assert_eq!(emulator.load_state(&corrupt), Err(StateError::ChecksumMismatch));
+
+    // A newer version, with a valid checksum so only the version is wrong
+    let mut newer = state.clone();
+    newer[4] = newer[4].wrapping_add(1);
+    let body = newer.len() - 4;
+    let checksum = crc32(&newer[..body]);
+    newer[body..].copy_from_slice(&checksum.to_le_bytes());
+    assert!(matches!(emulator.load_state(&newer), Err(StateError::UnsupportedVersion(_))));
+
+    // Another game's state passes the header checks and loads the CPU
+    // before the ROM checksum fails, so this checks the backup is restored
+    let mut other_rom = spin_rom();
+    other_rom[0x101] = 0x99;
+    let mut other = Emulator::new(other_rom);
+    for _ in 0..2 {
+        other.step();
+    }
+    assert_eq!(emulator.load_state(&other.save_state()), Err(StateError::InvalidField("ROM checksum")));
+    assert_eq!(emulator.save_state(), state);
+}
+
+#[test]
+fn runs_roms_smaller_than_32_kib() {
+    let mut rom = spin_rom();
+    rom.truncate(0x200);
+    let mut emulator = Emulator::new(rom);
+    for _ in 0..100 {
+        emulator.step();
+    }
+    assert_eq!(emulator.read_byte(0x4000), 0xFF);
+    let state = emulator.save_state();
+    emulator.load_state(&state).unwrap();
+}
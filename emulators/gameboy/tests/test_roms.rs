@@ -0,0 +1,132 @@
+//! Runs Blargg's and Mooneye's test ROMs and checks the results they report.
+//!
+//! Only the ROMs this core has the hardware for are run: Blargg's CPU
+//! instruction and timing tests, which need the timer and MBC1, and
+//! Mooneye's MBC1 and DIV tests. The rest of both suites exercise the APU
+//! or cycle-exact memory timing, which aren't emulated.
+//!
+//! The ROMs aren't redistributable, so these tests are ignored by default
+//! and read the ROMs from `$GB_TEST_ROMS` if set, otherwise `tests/roms`:
+//!
+//! - `blargg/`: the contents of https://github.com/retrio/gb-test-roms
+//! - `mooneye/`: the contents of a mooneye-test-suite release archive,
+//!   from https://gekkio.fi/files/mooneye-test-suite/
+//!
+//! Then run `cargo test --release --test test_roms -- --ignored --nocapture`
+//! to see per-ROM results. A missing ROM fails the test.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use gameboy::Emulator;
+
+/// Blargg's slowest ROMs need about a minute of emulated time.
+const BLARGG_FRAMES: u32 = 60 * 60;
+const MOONEYE_FRAMES: u32 = 60 * 20;
+
+const BLARGG_ROMS: &[&str] = &[
+    "cpu_instrs/individual/01-special.gb",
+    "cpu_instrs/individual/02-interrupts.gb",
+    "cpu_instrs/individual/03-op sp,hl.gb",
+    "cpu_instrs/individual/04-op r,imm.gb",
+    "cpu_instrs/individual/05-op rp.gb",
+    "cpu_instrs/individual/06-ld r,r.gb",
+    "cpu_instrs/individual/07-jr,jp,call,ret,rst.gb",
+    "cpu_instrs/individual/08-misc instrs.gb",
+    "cpu_instrs/individual/09-op r,r.gb",
+    "cpu_instrs/individual/10-bit ops.gb",
+    "cpu_instrs/individual/11-op a,(hl).gb",
+    "cpu_instrs/cpu_instrs.gb",
+    "instr_timing/instr_timing.gb",
+];
+
+const MOONEYE_ROMS: &[&str] = &[
+    "acceptance/timer/div_write.gb",
+    "emulator-only/mbc1/bits_bank1.gb",
+    "emulator-only/mbc1/bits_bank2.gb",
+    "emulator-only/mbc1/bits_mode.gb",
+    "emulator-only/mbc1/bits_ramg.gb",
+    "emulator-only/mbc1/ram_64kb.gb",
+    "emulator-only/mbc1/ram_256kb.gb",
+    "emulator-only/mbc1/rom_512kb.gb",
+    "emulator-only/mbc1/rom_1Mb.gb",
+    "emulator-only/mbc1/rom_2Mb.gb",
+    "emulator-only/mbc1/rom_4Mb.gb",
+    "emulator-only/mbc1/rom_8Mb.gb",
+    "emulator-only/mbc1/rom_16Mb.gb",
+];
+
+/// Registers B, C, D, E, H and L when a Mooneye test passes.
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+#[test]
+#[ignore = "needs Blargg's test ROMs in $GB_TEST_ROMS/blargg; see the module docs"]
+fn blargg() {
+    run_suite("blargg", BLARGG_ROMS, |emulator| {
+        let mut output = String::new();
+        for _ in 0..BLARGG_FRAMES {
+            emulator.debug_run_to_frame();
+            output.push_str(&String::from_utf8_lossy(&emulator.take_serial_output()));
+            if output.contains("Passed") {
+                return Ok(());
+            }
+            if output.contains("Failed") {
+                return Err(output.trim().to_string());
+            }
+        }
+        Err(format!("timed out, serial output: {:?}", output))
+    });
+}
+
+#[test]
+#[ignore = "needs Mooneye's test ROMs in $GB_TEST_ROMS/mooneye; see the module docs"]
+fn mooneye() {
+    run_suite("mooneye", MOONEYE_ROMS, |emulator| {
+        for _ in 0..MOONEYE_FRAMES {
+            emulator.debug_run_to_frame();
+            let registers = emulator.registers();
+            let signature = [registers.bc, registers.de, registers.hl].map(u16::to_be_bytes).concat();
+            if signature == MOONEYE_PASS {
+                return Ok(());
+            }
+            if signature == MOONEYE_FAIL {
+                return Err("failed".to_string());
+            }
+            if registers.locked {
+                return Err(format!("locked up at {:04X}", registers.pc));
+            }
+        }
+        Err("timed out".to_string())
+    });
+}
+
+/// Runs `check` on each of `roms` in the suite's directory and fails
+/// listing the ROMs that are missing or didn't pass.
+fn run_suite(suite: &str, roms: &[&str], check: fn(&mut Emulator) -> Result<(), String>) {
+    let root = std::env::var_os("GB_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms"));
+    let directory = root.join(suite);
+
+    let mut failures = Vec::new();
+    for name in roms {
+        let path = directory.join(name);
+        let result = match std::fs::read(&path) {
+            // A panic in one ROM shouldn't hide the other ROMs' results
+            Ok(rom) => panic::catch_unwind(AssertUnwindSafe(|| check(&mut Emulator::new(rom))))
+                .unwrap_or_else(|_| Err("panicked".to_string())),
+            Err(error) => Err(format!("{}: {}", path.display(), error)),
+        };
+        match result {
+            Ok(()) => eprintln!("pass  {}", name),
+            Err(reason) => {
+                eprintln!("FAIL  {}: {}", name, reason);
+                failures.push(*name);
+            }
+        }
+    }
+
+    eprintln!("{}: {}/{} passed", suite, roms.len() - failures.len(), roms.len());
+    assert!(failures.is_empty(), "{} {} ROMs failed: {}", failures.len(), suite, failures.join(", "));
+}
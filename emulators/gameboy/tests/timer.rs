@@ -0,0 +1,94 @@
+//! DIV, TIMA, TMA and TAC, timed by running NOPs (4 clocks each).
+
+mod common;
+
+use common::RomBuilder;
+use gameboy::Emulator;
+
+/// NOPs, and at the timer vector LD A,$99 and a spin.
+fn timer_rom() -> RomBuilder {
+    RomBuilder::new().at(0x50, &[0x3E, 0x99, 0x18, 0xFE])
+}
+
+fn nops(emulator: &mut Emulator, count: u32) {
+    for _ in 0..count {
+        emulator.step();
+    }
+}
+
+#[test]
+fn div_counts_every_256_clocks() {
+    let mut emulator = timer_rom().boot();
+    emulator.write_byte(0xFF04, 0x55);
+    assert_eq!(emulator.read_byte(0xFF04), 0);
+    nops(&mut emulator, 63);
+    assert_eq!(emulator.read_byte(0xFF04), 0);
+    nops(&mut emulator, 1);
+    assert_eq!(emulator.read_byte(0xFF04), 1);
+}
+
+#[test]
+fn tima_counts_at_the_selected_rate() {
+    // Clocks per TIMA tick for each TAC rate
+    for (tac, period) in [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+        let mut emulator = timer_rom().boot();
+        emulator.write_byte(0xFF07, tac);
+        emulator.write_byte(0xFF04, 0);
+        emulator.write_byte(0xFF05, 0);
+        nops(&mut emulator, period * 3 / 4);
+        assert_eq!(emulator.read_byte(0xFF05), 3, "TAC={:02X}", tac);
+        assert_eq!(emulator.read_byte(0xFF07), 0xF8 | tac);
+    }
+
+    // Disabled, it doesn't count
+    let mut emulator = timer_rom().boot();
+    emulator.write_byte(0xFF07, 0x01);
+    emulator.write_byte(0xFF05, 0);
+    nops(&mut emulator, 100);
+    assert_eq!(emulator.read_byte(0xFF05), 0);
+}
+
+#[test]
+fn overflow_reloads_tma_and_requests_the_interrupt() {
+    let mut emulator = timer_rom().boot();
+    emulator.write_byte(0xFF07, 0x05);
+    emulator.write_byte(0xFF04, 0);
+    emulator.write_byte(0xFF06, 0xF0);
+    emulator.write_byte(0xFF05, 0xFF);
+    emulator.write_byte(0xFF0F, 0x00);
+    nops(&mut emulator, 4);
+    assert_eq!(emulator.read_byte(0xFF05), 0xF0);
+    assert_eq!(emulator.read_byte(0xFF0F) & 0x04, 0x04);
+}
+
+#[test]
+fn resetting_div_on_a_high_edge_ticks_tima() {
+    let mut emulator = timer_rom().boot();
+    emulator.write_byte(0xFF07, 0x05);
+    emulator.write_byte(0xFF04, 0);
+    emulator.write_byte(0xFF05, 0);
+    // Bit 3 of the counter is set after 8 clocks
+    nops(&mut emulator, 2);
+    assert_eq!(emulator.read_byte(0xFF05), 0);
+    emulator.write_byte(0xFF04, 0);
+    assert_eq!(emulator.read_byte(0xFF05), 1);
+}
+
+#[test]
+fn timer_interrupt_wakes_halt() {
+    let mut emulator = timer_rom().program(&[0x76, 0x18, 0xFE]).boot(); // HALT; spin
+    emulator.write_byte(0xFFFF, 0x04);
+    emulator.write_byte(0xFF07, 0x05);
+    emulator.write_byte(0xFF05, 0xFE);
+
+    nops(&mut emulator, 20);
+    assert_eq!(emulator.registers().af >> 8, 0x99);
+
+    // The timer survives a save state
+    emulator.write_byte(0xFF06, 0x12);
+    let state = emulator.save_state();
+    emulator.write_byte(0xFF06, 0x00);
+    emulator.load_state(&state).unwrap();
+    assert_eq!(emulator.read_byte(0xFF06), 0x12);
+    assert_eq!(emulator.read_byte(0xFF07), 0xFD);
+}
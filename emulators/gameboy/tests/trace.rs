@@ -0,0 +1,69 @@
+//! Instruction traces in Game Boy Doctor format.
+
+mod common;
+
+use common::boot;
+use gameboy::Emulator;
+
+fn halting_program() -> Emulator {
+    boot(&[
+        0x3E, 0x42, // LD A,$42
+        0x76, // HALT
+        0x18, 0xFE, // JR $0104
+        0x00,
+    ])
+}
+
+/// The PC field of a trace line.
+fn pc(line: &str) -> &str {
+    line.split(' ').find(|field| field.starts_with("PC:")).unwrap()
+}
+
+#[test]
+fn logs_state_before_each_instruction() {
+    let mut emulator = halting_program();
+    emulator.start_trace(16);
+    emulator.step();
+    emulator.step();
+
+    assert_eq!(
+        emulator.take_trace(),
+        [
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,42,76,18",
+            "A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:76,18,FE,00",
+        ]
+    );
+    assert!(emulator.take_trace().is_empty());
+
+    // Nothing is logged while the CPU sits in HALT
+    emulator.step();
+    assert!(emulator.take_trace().is_empty());
+}
+
+#[test]
+fn ring_keeps_the_latest_lines() {
+    let mut emulator = boot(&[0x3C, 0x3C, 0x3C, 0x3C]); // INC A
+    emulator.start_trace(2);
+    for _ in 0..4 {
+        emulator.step();
+    }
+
+    let trace = emulator.take_trace();
+    let pcs: Vec<&str> = trace.iter().map(|line| pc(line)).collect();
+    assert_eq!(pcs, ["PC:0102", "PC:0103"]);
+}
+
+#[test]
+fn streams_to_a_file() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("gameboy-trace.log");
+    let mut emulator = halting_program();
+    emulator.trace_to_file(&path).unwrap();
+    emulator.step();
+    emulator.step();
+    emulator.finish_trace().unwrap();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let pcs: Vec<&str> = log.lines().map(pc).collect();
+    assert_eq!(pcs, ["PC:0100", "PC:0102"]);
+    assert!(emulator.take_trace().is_empty());
+}
@@ -0,0 +1,151 @@
+use emu_common::palette::Palette;
+use emu_common::{EmulatorCore, Input};
+use gameboy::debugger::StopReason;
+
+/// Why a frame ended early.
+pub enum Stop {
+    /// PC reached a breakpoint; the instruction there has not run yet.
+    Breakpoint(u16),
+}
+
+/// A core plus the debugging hooks the runner needs, which differ per
+/// system.
+pub trait Machine {
+    fn core(&self) -> &dyn EmulatorCore;
+
+    fn core_mut(&mut self) -> &mut dyn EmulatorCore;
+
+    /// Runs one frame, or less if a breakpoint intervenes.
+    fn run_frame(&mut self) -> Result<Option<Stop>, String>;
+
+    /// Maps a key name from an input script to an input.
+    fn input(&self, name: &str) -> Option<Input>;
+
+    fn add_breakpoint(&mut self, address: u16);
+
+    /// Switches the frame buffer to RGBA in `palette`.
+    fn set_palette(&mut self, palette: Palette);
+
+    /// Bytes the program sent out over the serial port so far.
+    fn serial(&self) -> &[u8] {
+        &[]
+    }
+}
+
+pub struct Chip8 {
+    machine: chip8::Chip8,
+}
+
+impl Chip8 {
+    /// `cycles_per_frame` overrides the core's instructions per frame.
+    pub fn new(rom: &[u8], seed: u64, quirks: chip8::Quirks, cycles_per_frame: Option<u32>) -> Result<Self, String> {
+        let mut machine = chip8::Chip8::new();
+        machine.set_seed(seed);
+        machine.quirks = quirks;
+        if let Some(cycles) = cycles_per_frame {
+            machine.instructions_per_frame = cycles;
+        }
+        machine.load_rom(rom).map_err(|error| error.to_string())?;
+        Ok(Chip8 { machine })
+    }
+}
+
+impl Machine for Chip8 {
+    fn core(&self) -> &dyn EmulatorCore {
+        &self.machine
+    }
+
+    fn core_mut(&mut self) -> &mut dyn EmulatorCore {
+        &mut self.machine
+    }
+
+    fn run_frame(&mut self) -> Result<Option<Stop>, String> {
+        match self.machine.debug_run_to_frame() {
+            Ok(true) => Ok(Some(Stop::Breakpoint(self.machine.pc()))),
+            Ok(false) => Ok(None),
+            Err(error) => {
+                let fault = self.machine.fault().copied();
+                Err(match fault {
+                    Some(fault) => format!("{} (opcode {:04X} at {:#05X})", error, fault.opcode, fault.pc),
+                    None => error.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Keys are the hex digits 0-F of the CHIP-8 keypad.
+    fn input(&self, name: &str) -> Option<Input> {
+        match u8::from_str_radix(name, 16) {
+            Ok(key) if name.len() == 1 => Some(Input::Key(key)),
+            _ => None,
+        }
+    }
+
+    fn add_breakpoint(&mut self, address: u16) {
+        self.machine.breakpoints.insert(address);
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.machine.display.set_palette(Some(palette));
+    }
+}
+
+pub struct GameBoy {
+    emulator: gameboy::Emulator,
+    serial: Vec<u8>,
+}
+
+impl GameBoy {
+    pub fn new(rom: &[u8]) -> Self {
+        GameBoy { emulator: gameboy::Emulator::new(rom.to_vec()), serial: Vec::new() }
+    }
+}
+
+impl Machine for GameBoy {
+    fn core(&self) -> &dyn EmulatorCore {
+        &self.emulator
+    }
+
+    fn core_mut(&mut self) -> &mut dyn EmulatorCore {
+        &mut self.emulator
+    }
+
+    fn run_frame(&mut self) -> Result<Option<Stop>, String> {
+        let stop = match self.emulator.debug_run_to_frame() {
+            StopReason::Breakpoint(pc) => Some(Stop::Breakpoint(pc)),
+            // A frame that never reaches VBlank, e.g. with the LCD off,
+            // still counts towards the frame limit
+            _ => None,
+        };
+        self.serial.extend(self.emulator.take_serial_output());
+        Ok(stop)
+    }
+
+    /// Keys are the button names: right, left, up, down, a, b, select, start.
+    fn input(&self, name: &str) -> Option<Input> {
+        let input = match name.to_ascii_lowercase().as_str() {
+            "right" => Input::Right,
+            "left" => Input::Left,
+            "up" => Input::Up,
+            "down" => Input::Down,
+            "a" => Input::A,
+            "b" => Input::B,
+            "select" => Input::Select,
+            "start" => Input::Start,
+            _ => return None,
+        };
+        Some(input)
+    }
+
+    fn add_breakpoint(&mut self, address: u16) {
+        self.emulator.add_breakpoint(address);
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.emulator.set_palette(Some(palette));
+    }
+
+    fn serial(&self) -> &[u8] {
+        &self.serial
+    }
+}
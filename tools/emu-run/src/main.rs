@@ -0,0 +1,244 @@
+//! Headless ROM runner for regression testing.
+//!
+//! Runs a CHIP-8 or Game Boy ROM for a number of frames, or until a
+//! breakpoint or expected serial output, then prints a hash of the final
+//! frame and optionally writes it out as a PNG.
+
+mod machine;
+mod script;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use emu_common::palette::Palette;
+use emu_common::{FrameBufferInfo, PixelFormat};
+use machine::{Machine, Stop};
+
+const USAGE: &str = "\
+usage: emu-run [options] <rom>
+
+options:
+  --system <chip8|gameboy>  emulator to use; guessed from the ROM extension
+  --frames <n>              frames to run (default 600)
+  --break <addr>            stop at a hex address; may be repeated
+  --serial-until <text>     stop once the serial output contains <text>
+  --input <file>            scripted input, `<frame> press|release <key>` per line
+  --png <file>              write the final frame as a PNG
+  --palette <name>          render in colour: dmg, pocket, grey or chip8
+  --expect-hash <hash>      exit with status 1 unless the frame hash matches
+  --seed <n>                CHIP-8 random seed (default 0)
+  --quirks <profile>        CHIP-8 platform: legacy (default), chip8, schip or xochip
+  --cycles-per-frame <n>    CHIP-8 instructions per frame (default 10)
+  -h, --help                print this help";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum System {
+    Chip8,
+    GameBoy,
+}
+
+struct Options {
+    rom: PathBuf,
+    system: Option<System>,
+    frames: u64,
+    breakpoints: Vec<u16>,
+    serial_until: Option<String>,
+    input: Option<PathBuf>,
+    png: Option<PathBuf>,
+    palette: Option<Palette>,
+    expect_hash: Option<String>,
+    seed: u64,
+    quirks: chip8::Quirks,
+    cycles_per_frame: Option<u32>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprintln!("emu-run: {}\n\n{}", error, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(&options) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("emu-run: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Returns `None` when help was requested.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: PathBuf::new(),
+        system: None,
+        frames: 600,
+        breakpoints: Vec::new(),
+        serial_until: None,
+        input: None,
+        png: None,
+        palette: None,
+        expect_hash: None,
+        seed: 0,
+        quirks: chip8::Quirks::default(),
+        cycles_per_frame: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--system" => {
+                options.system = Some(match value()?.as_str() {
+                    "chip8" => System::Chip8,
+                    "gameboy" | "gb" => System::GameBoy,
+                    other => return Err(format!("unknown system `{}`", other)),
+                })
+            }
+            "--frames" => options.frames = parse_number(&value()?)?,
+            "--break" => {
+                let address = value()?;
+                let digits = address.trim_start_matches("0x").trim_start_matches('$');
+                let address = u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address `{}`", address))?;
+                options.breakpoints.push(address);
+            }
+            "--serial-until" => options.serial_until = Some(value()?),
+            "--input" => options.input = Some(value()?.into()),
+            "--png" => options.png = Some(value()?.into()),
+            "--palette" => {
+                let name = value()?;
+                options.palette = Some(Palette::named(&name).ok_or(format!("unknown palette `{}`", name))?);
+            }
+            "--expect-hash" => options.expect_hash = Some(value()?.to_ascii_lowercase()),
+            "--seed" => options.seed = parse_number(&value()?)?,
+            "--quirks" => {
+                let profile = value()?;
+                options.quirks = chip8::Quirks::profile(&profile).ok_or(format!("unknown quirk profile `{}`", profile))?;
+            }
+            "--cycles-per-frame" => options.cycles_per_frame = Some(parse_number(&value()?)?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument `{}`", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or("no ROM given")?;
+    Ok(Some(options))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid number `{}`", value))
+}
+
+/// Guesses the system from the ROM's file extension.
+fn detect_system(rom: &Path) -> Option<System> {
+    let extension = rom.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "gb" | "gbc" => Some(System::GameBoy),
+        "ch8" | "c8" | "chip8" => Some(System::Chip8),
+        _ => None,
+    }
+}
+
+/// Runs the ROM and reports the result. Returns false if the frame hash
+/// didn't match `--expect-hash`.
+fn run(options: &Options) -> Result<bool, String> {
+    let rom = std::fs::read(&options.rom).map_err(|error| format!("{}: {}", options.rom.display(), error))?;
+    let system = options
+        .system
+        .or_else(|| detect_system(&options.rom))
+        .ok_or("can't tell the system from the ROM extension, pass --system")?;
+
+    let mut machine: Box<dyn Machine> = match system {
+        System::Chip8 => Box::new(machine::Chip8::new(&rom, options.seed, options.quirks, options.cycles_per_frame)?),
+        System::GameBoy => Box::new(machine::GameBoy::new(&rom)),
+    };
+    for &address in &options.breakpoints {
+        machine.add_breakpoint(address);
+    }
+    if let Some(palette) = options.palette {
+        machine.set_palette(palette);
+    }
+
+    let events = match &options.input {
+        Some(path) => {
+            let source =
+                std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+            script::parse(&source, machine.as_ref()).map_err(|error| format!("{}: {}", path.display(), error))?
+        }
+        None => Vec::new(),
+    };
+    let mut events = events.iter().peekable();
+
+    let mut frame = 0;
+    let mut stop = format!("frame limit after {} frames", options.frames);
+    while frame < options.frames {
+        while let Some(event) = events.next_if(|event| event.frame <= frame) {
+            machine.core_mut().set_input(event.input, event.pressed);
+        }
+
+        let result = machine.run_frame().map_err(|error| format!("frame {}: {}", frame, error))?;
+        if let Some(Stop::Breakpoint(address)) = result {
+            stop = format!("breakpoint at ${:04X} in frame {}", address, frame);
+            break;
+        }
+        frame += 1;
+
+        if let Some(text) = &options.serial_until {
+            if String::from_utf8_lossy(machine.serial()).contains(text.as_str()) {
+                stop = format!("serial output matched after {} frames", frame);
+                break;
+            }
+        }
+    }
+
+    let info = machine.core().frame_buffer_info();
+    let pixels = machine.core().frame_buffer();
+    let hash = format!("{:016x}", fnv1a(&pixels));
+    println!("stopped: {}", stop);
+    println!("hash: {}", hash);
+    if !machine.serial().is_empty() {
+        println!("serial: {}", String::from_utf8_lossy(machine.serial()).escape_debug());
+    }
+
+    if let Some(path) = &options.png {
+        write_png(path, info, &pixels).map_err(|error| format!("{}: {}", path.display(), error))?;
+    }
+
+    match &options.expect_hash {
+        Some(expected) if *expected != hash => {
+            eprintln!("emu-run: frame hash {} does not match expected {}", hash, expected);
+            Ok(false)
+        }
+        _ => Ok(true),
+    }
+}
+
+/// 64-bit FNV-1a, stable across platforms and releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+fn write_png(path: &Path, info: FrameBufferInfo, pixels: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), info.width, info.height);
+    encoder.set_color(match info.format {
+        PixelFormat::Gray8 => png::ColorType::Grayscale,
+        PixelFormat::Rgba8888 => png::ColorType::Rgba,
+    });
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels)?;
+    Ok(())
+}
@@ -0,0 +1,40 @@
+//! Scripted input: one event per line, `<frame> press|release <key>`, with
+//! `#` starting a comment. Events apply before their frame runs.
+
+use emu_common::Input;
+
+use crate::machine::Machine;
+
+pub struct Event {
+    pub frame: u64,
+    pub input: Input,
+    pub pressed: bool,
+}
+
+/// Parses a script, resolving key names against `machine`. Events come back
+/// sorted by frame, keeping file order within a frame.
+pub fn parse(source: &str, machine: &dyn Machine) -> Result<Vec<Event>, String> {
+    let mut events = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+
+        let [frame, action, key] = fields[..] else {
+            return Err(error("expected `<frame> press|release <key>`"));
+        };
+        let frame = frame.parse().map_err(|_| error("invalid frame number"))?;
+        let pressed = match action {
+            "press" => true,
+            "release" => false,
+            _ => return Err(error("action must be `press` or `release`")),
+        };
+        let input = machine.input(key).ok_or_else(|| error(&format!("unknown key `{}`", key)))?;
+        events.push(Event { frame, input, pressed });
+    }
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
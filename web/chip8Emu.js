@@ -1,13 +1,7 @@
-import init, {
-  load_rom,
-  cycle,
-  get_display_buffer,
-  key_down,
-  key_up,
-} from "./chip8/chip8.js";
+import init, { Chip8 } from "./chip8/chip8.js";
 
+let chip8;
 let wasmMemory;
-let displayBuffer;
 let animationFrame;
 const scale = 10;
 const width = 64;
@@ -15,58 +9,88 @@ const height = 32;
 
 const canvas = document.getElementById("screen");
 const ctx = canvas.getContext("2d");
+ctx.imageSmoothingEnabled = false;
+
+// The core renders at native resolution; this canvas holds one frame so it
+// can be scaled up in a single drawImage
+const frameCanvas = new OffscreenCanvas(width, height);
+const frameCtx = frameCanvas.getContext("2d");
 
 export async function initChip8() {
   const wasm = await init();
   wasmMemory = wasm.memory;
+  chip8 = new Chip8();
+  chip8.set_palette("chip8");
+}
 
-  displayBuffer = new Uint8Array(
-    wasmMemory.buffer,
-    get_display_buffer(),
-    width * height
-  );
+/**
+ * Sets the colours as 0xRRGGBB numbers and redraws.
+ */
+export function setColors(background, foreground) {
+  chip8.set_colors(background, foreground);
+  renderDisplay();
 }
 
 export function loadROM(rom) {
-  load_rom(rom);
+  try {
+    chip8.load_rom(rom);
+  } catch (error) {
+    console.error("Failed to load ROM:", error);
+  }
 }
 
 export function startChip8() {
   function emulate() {
-    cycle();
-    renderDisplay();
+    try {
+      chip8.cycle();
+    } catch (fault) {
+      reportFault(fault);
+      return;
+    }
+    if (chip8.frame_changed()) {
+      renderDisplay();
+    }
     animationFrame = requestAnimationFrame(emulate);
   }
   emulate();
 }
 
+export function rewindStep() {
+  const rewound = chip8.rewind_step();
+  if (rewound) {
+    renderDisplay();
+  }
+  return rewound;
+}
+
+export function getFault() {
+  return chip8.get_fault();
+}
+
+function reportFault(fault) {
+  const hex = (value, width) => value.toString(16).toUpperCase().padStart(width, "0");
+  console.error(
+    `CHIP-8 halted: ${fault.reason} (opcode ${hex(fault.opcode, 4)} at PC ${hex(fault.pc, 4)})`
+  );
+}
+
 export function keyDown(key) {
   console.log("Key down:", key);
-  key_down(key);
+  chip8.key_down(key);
 }
 
 export function keyUp(key) {
   console.log("Key up:", key);
-  key_up(key);
+  chip8.key_up(key);
 }
 
 function renderDisplay() {
-  // Refresh the display buffer in case the memory was resized
-  displayBuffer = new Uint8Array(
+  // Take a fresh view every frame, as growing the wasm memory detaches old ones
+  const pixels = new Uint8ClampedArray(
     wasmMemory.buffer,
-    get_display_buffer(),
-    width * height
+    chip8.get_rgba_buffer(),
+    width * height * 4
   );
-
-  ctx.clearRect(0, 0, canvas.width, canvas.height);
-
-  for (let y = 0; y < height; y++) {
-    for (let x = 0; x < width; x++) {
-      const index = y * width + x;
-      const pixel = displayBuffer[index];
-
-      ctx.fillStyle = pixel ? "white" : "black";
-      ctx.fillRect(x * scale, y * scale, scale, scale);
-    }
-  }
+  frameCtx.putImageData(new ImageData(pixels, width, height), 0, 0);
+  ctx.drawImage(frameCanvas, 0, 0, width * scale, height * scale);
 }
@@ -1,11 +1,13 @@
 import init, { Emulator } from "./gameboy/gameboy.js";
 
 let emulator;
+let linkedEmulator; // A second Game Boy on the other end of the link cable
 let wasm; // Declare wasm globally
 const canvas = document.getElementById("canvas");
 const ctx = canvas.getContext("2d");
-const imageData = ctx.createImageData(160, 144);
+const linkCtx = document.getElementById("linkCanvas").getContext("2d");
 let isRunning = false;
+let isRewinding = false;
 
 /**
  * Initialize the WebAssembly module
@@ -27,10 +29,11 @@ async function loadRomFile(file) {
     emulator.load_rom(romData);
   } else {
     emulator = new Emulator(romData);
+    emulator.set_palette(paletteSelect.value);
   }
 
   console.log("ROM loaded successfully");
-  drawFrame(); // Draw the first frame after loading the ROM
+  drawFrames(); // Draw the first frame after loading the ROM
 
   // Start the game loop after loading the ROM
   if (!isRunning) {
@@ -40,32 +43,70 @@ async function loadRomFile(file) {
 }
 
 /**
- * Draws the current frame from the emulator onto the canvas
+ * Starts a second Game Boy next to the first and plugs a link cable
+ * between them, for trading and battles
+ * @param {File} file - The ROM file for the second Game Boy
  */
-function drawFrame() {
-  if (!emulator || !wasm) {
-    console.warn("WASM or emulator is not initialized.");
+async function loadLinkedRomFile(file) {
+  if (!emulator) {
+    console.warn("Load a ROM for the first Game Boy before linking.");
     return;
   }
+  const romData = new Uint8Array(await file.arrayBuffer());
+
+  if (linkedEmulator) {
+    linkedEmulator.load_rom(romData);
+  } else {
+    linkedEmulator = new Emulator(romData);
+    linkedEmulator.set_palette(paletteSelect.value);
+    emulator.link_with(linkedEmulator);
+  }
+
+  console.log("Linked ROM loaded successfully");
+  drawFrames();
+}
 
-  const wasmMemory = new Uint8Array(wasm.memory.buffer);
-  const frameBufferPtr = emulator.get_frame_buffer();
-  const length = emulator.get_frame_buffer_length();
+/**
+ * Draws the current frame from an emulator onto a canvas
+ */
+function drawFrame(source, context) {
+  if (!source || !wasm) {
+    console.warn("WASM or emulator is not initialized.");
+    return;
+  }
 
-  const frameBuffer = wasmMemory.subarray(
-    frameBufferPtr,
-    frameBufferPtr + length
+  // The core renders RGBA in the selected palette, so the frame can go
+  // straight to the canvas without copying
+  const pixels = new Uint8ClampedArray(
+    wasm.memory.buffer,
+    source.get_rgba_buffer(),
+    source.get_rgba_buffer_length()
   );
+  context.putImageData(new ImageData(pixels, 160, 144), 0, 0);
+}
 
-  for (let i = 0; i < frameBuffer.length; i++) {
-    const color = frameBuffer[i];
-    imageData.data[i * 4] = color;
-    imageData.data[i * 4 + 1] = color;
-    imageData.data[i * 4 + 2] = color;
-    imageData.data[i * 4 + 3] = 255;
+/**
+ * Adds anything the Game Boy Printer finished to the page, newest last
+ */
+function showPrints() {
+  for (const rgba of emulator.take_prints()) {
+    const print = document.createElement("canvas");
+    print.width = 160;
+    print.height = rgba.length / 640;
+    const pixels = new Uint8ClampedArray(rgba.buffer);
+    print.getContext("2d").putImageData(new ImageData(pixels, 160, print.height), 0, 0);
+    document.getElementById("prints").appendChild(print);
   }
+}
 
-  ctx.putImageData(imageData, 0, 0);
+/**
+ * Draws both Game Boys, the second only once one is linked
+ */
+function drawFrames() {
+  drawFrame(emulator, ctx);
+  if (linkedEmulator) {
+    drawFrame(linkedEmulator, linkCtx);
+  }
 }
 
 /**
@@ -74,8 +115,15 @@ function drawFrame() {
 function gameLoop() {
   if (emulator) {
     try {
-      emulator.step();
-      drawFrame();
+      if (isRewinding) {
+        emulator.rewind_step();
+      } else {
+        // Linked Game Boys advance together so transfers line up
+        emulator.step();
+        linkedEmulator?.step();
+      }
+      drawFrames();
+      showPrints();
     } catch (e) {
       console.error("Emulator step failed:", e);
       isRunning = false; // Stop the game loop on error
@@ -99,5 +147,47 @@ document
     }
   });
 
+/**
+ * Event listener for loading the linked Game Boy's ROM
+ */
+document
+  .getElementById("linkRomInput")
+  .addEventListener("change", async (event) => {
+    const file = event.target.files[0];
+    if (file) {
+      await loadLinkedRomFile(file);
+    }
+  });
+
+/**
+ * Switching palettes redraws straight away, even while paused
+ */
+const paletteSelect = document.getElementById("paletteSelect");
+paletteSelect.addEventListener("change", () => {
+  if (emulator) {
+    emulator.set_palette(paletteSelect.value);
+    linkedEmulator?.set_palette(paletteSelect.value);
+    drawFrames();
+  }
+});
+
+/**
+ * The printer takes the first Game Boy's link port, so it can't be used
+ * alongside a linked second Game Boy
+ */
+document.getElementById("printerButton").addEventListener("click", () => {
+  if (emulator && !linkedEmulator) {
+    emulator.connect_printer();
+  }
+});
+
+/**
+ * Holding the rewind button steps back one snapshot per animation frame
+ */
+const rewindButton = document.getElementById("rewindButton");
+rewindButton.addEventListener("pointerdown", () => (isRewinding = true));
+rewindButton.addEventListener("pointerup", () => (isRewinding = false));
+rewindButton.addEventListener("pointerleave", () => (isRewinding = false));
+
 // Initialize WebAssembly on page load
 initializeWasm();